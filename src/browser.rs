@@ -0,0 +1,1478 @@
+//TODO: RUN CLIPPY LINT
+
+use std::vec::Vec;
+use std::vec;
+use std::fmt;
+use std::collections::{ HashMap, HashSet };
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError };
+use std::time::{ Duration, Instant };
+use std::thread;
+use std::path::Path;
+
+//use ming_wm_lib::logging::log;
+use ming_wm_lib::window_manager_types::{ DrawInstructions, WindowLike, WindowLikeType };
+use ming_wm_lib::messages::{ WindowMessage, WindowMessageResponse };
+use ming_wm_lib::utils::{ get_rest_of_split, Substring };
+use ming_wm_lib::framebuffer_types::{ Dimensions, RGBColor };
+use ming_wm_lib::themes::ThemeInfo;
+use ming_wm_lib::fonts::{ CachedFontCharGetter, measure_text, measure_text_with_cache };
+use ming_wm_lib::ipc::listen;
+use ming_wm_lib::dirs::exe_dir;
+
+use koxinga::http::{ BodyStream, HttpClient, HttpError, Response };
+use koxinga::download::{ format_size, DownloadState, DownloadStatus, Downloads };
+use koxinga::config::Config;
+use koxinga::save::{ self, file_name };
+use koxinga::dump;
+use koxinga::gemini::{ GeminiClient, GeminiError, GeminiResponse };
+use koxinga::gopher::{ self, GopherError };
+use koxinga::file;
+use koxinga::document::{ is_text, Document };
+use koxinga::encoding::decode;
+use koxinga::xml::{ remove_quotes, handle_escaped, escape_html, Form, FormSubmitMethod, OutputType, Style, URL_REPLACE };
+use koxinga::url::{ percent_decode, percent_encode, Url };
+use koxinga::layout::{ layout, line_at_y, Subtype, LINE_HEIGHT };
+
+const BAND_HEIGHT: usize = 19;
+const MONO_WIDTH: u8 = 11; //of each char of monospace text, so columns line up
+const COLUMN_WIDTH: usize = MONO_WIDTH as usize + 1; //a monospace char and the space after it, also how far sideways scrolling goes a column at a time
+const LOAD_WAIT: Duration = Duration::from_millis(500); //how long after a load starts it is waited on before answering the window manager
+const MAX_CACHED_PAGES: usize = 10; //history entries further than this from the current page get their nodes dropped
+
+#[derive(Default, PartialEq)]
+enum State {
+  #[default]
+  None,
+  Maybeg,
+  Maybez, //z starts sideways scrolling and wrapping keys, like in vim
+}
+
+#[derive(Default, PartialEq, Clone, Copy)]
+enum Mode {
+  #[default]
+  Normal,
+  Url,
+  Link,
+  Search,
+  FormSubmit,
+  FormInput, //(input elements)
+  Prompt, //a page asking for input, eg: gemini 1x
+  Save, //choosing what to save the page as
+}
+
+impl fmt::Display for Mode {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+    fmt.write_str(match self {
+      Mode::Normal => "NORMAL",
+      Mode::Url => "URL",
+      Mode::Link => "LINK",
+      Mode::Search => "SEARCH",
+      Mode::FormSubmit => "FORM SUBMIT",
+      Mode::FormInput => "FORM INPUT",
+      Mode::Prompt => "INPUT",
+      Mode::Save => "SAVE",
+    })?;
+    Ok(())
+  }
+}
+
+//a visited page, and what it looked like when we left it
+struct HistoryEntry {
+  url: Url,
+  title: Option<String>,
+  top_line_no: usize,
+  wrap: bool,
+  form_inputs: HashMap<(usize, String), String>,
+  mode: Mode,
+  document: Option<Document>, //None if no longer cached in memory, will need to be refetched
+}
+
+//what to do with a page once it is loaded
+enum LoadKind {
+  Navigate(bool), //whether to open in a new buffer
+  History(usize), //index of history entry to restore
+}
+
+//what the worker thread sends back
+enum Loaded {
+  Page {
+    document: Document,
+    url: Url, //final url, may differ from requested url because of redirects
+  },
+  //not something to show, so it should be downloaded instead
+  Download {
+    url: Url,
+    stream: BodyStream, //the body, still to be read, so it isn't fetched again
+  },
+  //the page wants some input first, to be sent as the query of url
+  Prompt {
+    prompt: String,
+    sensitive: bool,
+    url: Url,
+  },
+}
+
+//why a page couldn't be loaded, whatever the protocol
+struct LoadError {
+  title: String,
+  description: Option<String>,
+}
+
+impl From<HttpError> for LoadError {
+  fn from(e: HttpError) -> Self {
+    if let Some(status) = e.status() {
+      LoadError { title: format!("{} {}", status, e.reason().unwrap_or("Unknown Status")), description: None }
+    } else {
+      LoadError { title: "Could not load page".to_string(), description: Some(e.to_string()) }
+    }
+  }
+}
+
+impl From<GeminiError> for LoadError {
+  fn from(e: GeminiError) -> Self {
+    if let GeminiError::Status(status, message) = &e {
+      LoadError { title: format!("{} {}", status, e.reason().unwrap_or_default()), description: Some(message.clone()).filter(|m| !m.is_empty()) }
+    } else {
+      LoadError { title: "Could not load page".to_string(), description: Some(e.to_string()) }
+    }
+  }
+}
+
+impl From<GopherError> for LoadError {
+  fn from(e: GopherError) -> Self {
+    LoadError { title: "Could not load page".to_string(), description: Some(e.to_string()) }
+  }
+}
+
+//a page being fetched in the background
+struct Loading {
+  url: Url,
+  kind: LoadKind,
+  receiver: Receiver<Result<Loaded, LoadError>>,
+  started: Instant,
+}
+
+//a page asking for input
+struct Prompt {
+  prompt: String,
+  sensitive: bool,
+  url: Url,
+}
+
+//shown in place of a page that could not be loaded, so retrying works like reloading
+fn error_page(url: &Url, error: &LoadError) -> String {
+  let description = error.description.as_ref().map_or(String::new(), |d| format!("<p>{}</p>", escape_html(d)));
+  format!("<html><head><title>{}</title></head><body><h1>{}</h1>{}<p>{}</p><p>Press r to retry.</p></body></html>", escape_html(&error.title), escape_html(&error.title), description, escape_html(&url.to_string()))
+}
+
+fn gemini_job(client: GeminiClient, url: Url) -> Result<Loaded, LoadError> {
+  match client.get(&url)? {
+    GeminiResponse::Success { mime, body, url } => {
+      if is_text(&mime) {
+        Ok(Loaded::Page { document: Document::new(&mime, &decode(&body, Some(&mime))), url })
+      } else {
+        Err(LoadError { title: "Can't display this page".to_string(), description: Some(format!("it is {}", mime)) })
+      }
+    },
+    GeminiResponse::Input { prompt, sensitive, url } => Ok(Loaded::Prompt { prompt, sensitive, url }),
+  }
+}
+
+fn file_job(url: Url) -> Result<Loaded, LoadError> {
+  let (mime, bytes) = file::get(&url).map_err(|e| LoadError { title: "Could not open file".to_string(), description: Some(e.to_string()) })?;
+  if is_text(mime) {
+    Ok(Loaded::Page { document: Document::new(mime, &decode(&bytes, None)), url })
+  } else {
+    Err(LoadError { title: "Can't display this page".to_string(), description: Some(format!("it is {}", mime)) })
+  }
+}
+
+fn gopher_job(url: Url) -> Result<Loaded, LoadError> {
+  let (item_type, body) = gopher::get(&url)?;
+  if let Some(mime) = gopher::mime(item_type) {
+    let text = decode(&body, None);
+    let text = if item_type == '0' { gopher::text_file(&text) } else { text };
+    Ok(Loaded::Page { document: Document::new(mime, &text), url })
+  } else {
+    Err(LoadError { title: "Can't display this page".to_string(), description: Some(format!("it is gopher item type {}", item_type)) })
+  }
+}
+
+//pages without a content type are hopefully html
+fn http_document(resp: &Response) -> Document {
+  Document::new(resp.content_type.as_deref().unwrap_or("text/html"), &resp.body)
+}
+
+//a page to show, unless it is a file (or attachment) to download, from the response already there
+fn http_loaded(resp: Response) -> Loaded {
+  match resp.stream {
+    Some(stream) => Loaded::Download { url: resp.url, stream },
+    None => Loaded::Page { document: http_document(&resp), url: resp.url },
+  }
+}
+
+//fonts for text in a style, most specific first, eg: nimbus-roman-h1, nimbus-roman-bold, nimbus-roman, ...
+//only the ones in `installed` are used, since chars of a missing font are looked for on disk every time
+fn style_fonts(fonts: &[String], installed: &HashSet<String>, style: Style) -> Vec<String> {
+  let base = if style.monospace { "nimbus-romono" } else { &fonts[0] };
+  let mut style_fonts = Vec::new();
+  if (1..=3).contains(&style.heading) {
+    style_fonts.push(format!("{}-h{}", base, style.heading));
+  }
+  match (style.bold || style.heading > 0, style.italic) {
+    (true, true) => style_fonts.push(base.to_string() + "-bold-italic"),
+    (true, false) => style_fonts.push(base.to_string() + "-bold"),
+    (false, true) => style_fonts.push(base.to_string() + "-italic"),
+    _ => {},
+  }
+  if style.monospace {
+    style_fonts.push(base.to_string());
+  }
+  style_fonts.retain(|f| installed.contains(f));
+  style_fonts.extend(fonts.iter().cloned());
+  style_fonts
+}
+
+//the style fonts there are, checked once rather than on every char
+fn installed_fonts(fonts: &[String]) -> HashSet<String> {
+  let mut installed = HashSet::new();
+  for base in [fonts[0].as_str(), "nimbus-romono"] {
+    for suffix in ["", "-h1", "-h2", "-h3", "-bold", "-italic", "-bold-italic"] {
+      let font = base.to_string() + suffix;
+      if exe_dir(Some(&("ming_bmps/".to_string() + &font))).is_dir() {
+        installed.insert(font);
+      }
+    }
+  }
+  installed
+}
+
+//bold and italic that the fonts used don't already show, so have to be drawn some other way
+fn faked_styles(fonts: &[String], style: Style) -> (bool, bool) {
+  let first = &fonts[0];
+  let bold_font = ["-bold", "-bold-italic", "-h1", "-h2", "-h3"].iter().any(|suffix| first.ends_with(suffix));
+  ((style.bold || style.heading > 0) && !bold_font, style.italic && !first.ends_with("-italic"))
+}
+
+//each style has its own cache, since the cache is only keyed by char
+fn char_width(caches: &mut HashMap<Style, CachedFontCharGetter>, fonts: &[String], installed: &HashSet<String>, c: char, style: Style) -> usize {
+  if style.monospace {
+    return COLUMN_WIDTH;
+  }
+  let fc_getter = caches.entry(style).or_insert_with(|| CachedFontCharGetter::new(81)); //all eng alpha + numbers + 19
+  measure_text_with_cache(fc_getter, &style_fonts(fonts, installed, style), &c.to_string(), None).width + 1 //+1 for horiz spacing
+}
+
+//tall enough for the tallest chars (of latin and cjk, anyways), but never less than normal
+fn style_line_height(fonts: &[String], installed: &HashSet<String>, style: Style) -> usize {
+  (measure_text(&style_fonts(fonts, installed, style), "Hgj鄭", None).height + 2).max(LINE_HEIGHT)
+}
+
+//scrolled sideways, a run at x starting off the left loses the chars before left_x. returns where the rest starts, and the rest
+fn clip_run<'a>(text: &'a str, x: usize, left_x: usize, fonts: &[String], monospace: bool) -> (usize, &'a str) {
+  if x >= left_x {
+    return (x, text);
+  }
+  let starts: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+  //fewest chars to drop for the rest to start at left_x or after (or all of them)
+  let dropped = if monospace {
+    (left_x - x).div_ceil(COLUMN_WIDTH).min(starts.len() - 1)
+  } else {
+    //widths of prefixes only go up, so binary search rather than measuring char by char
+    let (mut low, mut high) = (1, starts.len() - 1);
+    while low < high {
+      let mid = (low + high) / 2;
+      if x + measure_text(fonts, &text[..starts[mid]], Some(1)).width + 1 >= left_x {
+        high = mid;
+      } else {
+        low = mid + 1;
+      }
+    }
+    high
+  };
+  let width = match dropped {
+    0 => 0,
+    _ if monospace => dropped * COLUMN_WIDTH,
+    _ => measure_text(fonts, &text[..starts[dropped]], Some(1)).width + 1,
+  };
+  (x + width, &text[starts[dropped]..])
+}
+
+fn subtype_rgb(subtype: Subtype, style: Style, theme_info: &ThemeInfo) -> RGBColor {
+  match subtype {
+    Subtype::Text if style.heading > 0 => theme_info.top,
+    Subtype::Text => theme_info.text,
+    Subtype::Link => theme_info.alt_text,
+    Subtype::TextInput => theme_info.alt_secondary,
+    Subtype::Button => theme_info.alt_secondary,
+  }
+}
+
+//everything about a single open page (and its history)
+#[derive(Default)]
+struct Buffer {
+  top_line_no: usize,
+  left_x: usize, //how far the page is scrolled sideways
+  wrap: bool, //whether preformatted text and wide tables are wrapped to the window, rather than scrolled sideways to
+  url: Option<Url>,
+  links: Vec<String>,
+  forms: Vec<Form>,
+  form_inputs: HashMap<(usize, String), String>, //form #+input name, input value
+  title: Option<String>,
+  document: Document,
+  page: Vec<(usize, usize, String, Subtype, Style)>, //x, y, text, subtype, style
+  line_ys: Vec<usize>, //top of each line, then the bottom of the last
+  line_heights: HashMap<Style, usize>, //of each style on the page, to line text up along the bottom of its line
+  borders: Vec<(usize, usize, usize, usize)>, //x, y, width, height of table borders
+  width: usize, //furthest right anything on the page goes
+  anchors: HashMap<String, usize>, //element id, line number
+  line_offsets: Vec<usize>, //number of chars before the start of each line, to keep the same text at the top when rewrapping
+  history: Vec<HistoryEntry>,
+  history_index: usize, //index of current page in history
+}
+
+impl Buffer {
+  //save current page state into its history entry, so it can be restored later
+  fn save_history_entry(&mut self, mode: Mode) {
+    if let Some(entry) = self.history.get_mut(self.history_index) {
+      entry.title = self.title.clone();
+      entry.top_line_no = self.top_line_no;
+      entry.wrap = self.wrap;
+      entry.form_inputs = self.form_inputs.clone();
+      entry.mode = mode;
+      entry.document = Some(std::mem::take(&mut self.document));
+    }
+  }
+
+  fn push_history_entry(&mut self, url: Url, mode: Mode) {
+    //new page, so any forward history is gone
+    if self.url.is_some() {
+      self.save_history_entry(mode);
+      self.history.truncate(self.history_index + 1);
+    }
+    self.history.push(HistoryEntry {
+      url,
+      title: None,
+      top_line_no: 0,
+      wrap: false,
+      form_inputs: HashMap::new(),
+      mode: Mode::Normal,
+      document: None,
+    });
+    self.history_index = self.history.len() - 1;
+    self.uncache_far_history();
+  }
+
+  fn uncache_far_history(&mut self) {
+    for (i, entry) in self.history.iter_mut().enumerate() {
+      if i.abs_diff(self.history_index) > MAX_CACHED_PAGES {
+        entry.document = None;
+      }
+    }
+  }
+
+  //the last line that can be at the top, where the rest of the page fits below it
+  fn max_top_line_no(&self, page_height: usize) -> usize {
+    let end = self.line_ys.last().copied().unwrap_or(0);
+    self.line_ys.partition_point(|y| end - y > page_height).min(self.line_ys.len().saturating_sub(2))
+  }
+
+  //the furthest right the page can be scrolled, so the right edge of the widest line is at the right of the window
+  fn max_left_x(&self, window_width: usize) -> usize {
+    (self.width + 2).saturating_sub(window_width)
+  }
+
+  //line containing the char at offset (see line_offsets)
+  fn line_at_offset(&self, offset: usize) -> usize {
+    let line = self.line_offsets.partition_point(|o| *o < offset);
+    if line > 0 && self.line_offsets.get(line) != Some(&offset) {
+      line - 1
+    } else {
+      line
+    }
+  }
+}
+
+#[derive(Default)]
+struct KoxingaBrowser {
+  client: HttpClient,
+  gemini_client: GeminiClient,
+  dimensions: Dimensions,
+  fonts: Vec<String>,
+  installed_fonts: HashSet<String>, //of the bold, italic, heading and monospace fonts
+  mode: Mode,
+  state: State,
+  input: String,
+  maybe_num: Option<usize>,
+  link_new_buffer: bool, //whether the link chosen in link mode opens in a new buffer
+  buffers: Vec<Buffer>,
+  buffer_index: usize,
+  loading: Option<Loading>,
+  prompt: Option<Prompt>,
+  config: Config,
+  downloads: Downloads,
+  downloads_shown: Vec<DownloadStatus>, //as of when the downloads page was last made, to know when it needs remaking
+  status: Option<String>, //shown in the bottom band until the next key press, eg: where a page was saved
+}
+
+impl WindowLike for KoxingaBrowser {
+  fn handle_message(&mut self, message: WindowMessage) -> WindowMessageResponse {
+    //the worker thread has no way to wake us up, so check on every message
+    let loaded = self.poll_loading(Duration::ZERO);
+    let downloads_changed = self.refresh_downloads();
+    //any key is handled on the new page, which needs showing even if the key does nothing
+    let response = match message {
+      WindowMessage::Init(dimensions) => {
+        self.dimensions = dimensions;
+        WindowMessageResponse::JustRedraw
+      },
+      WindowMessage::ChangeDimensions(dimensions) => {
+        self.dimensions = dimensions;
+        self.rewrap();
+        WindowMessageResponse::JustRedraw
+      },
+      WindowMessage::KeyPress(key_press) => {
+        self.status = None;
+        match self.mode {
+          Mode::Normal => {
+            let page_height = self.page_height();
+            if self.state == State::Maybeg && key_press.key != 'g' {
+              self.state = State::None;
+            }
+            let has_url = self.buffer().url.is_some();
+            if self.state == State::Maybez {
+              self.state = State::None;
+              let num = self.maybe_num.take().unwrap_or(1);
+              let half_screen = self.dimensions[0] / 2;
+              let changed = match key_press.key {
+                'h' | 'l' => self.scroll_sideways(key_press.key == 'l', num * COLUMN_WIDTH),
+                'H' | 'L' => self.scroll_sideways(key_press.key == 'L', half_screen),
+                'w' => {
+                  let buffer = self.buffer_mut();
+                  buffer.wrap = !buffer.wrap;
+                  self.rewrap();
+                  self.status = Some(if self.buffer().wrap { "wrapping" } else { "not wrapping" }.to_string());
+                  true
+                },
+                _ => false,
+              };
+              if changed {
+                WindowMessageResponse::JustRedraw
+              } else {
+                WindowMessageResponse::DoNothing
+              }
+            } else if key_press.is_escape() && self.loading.is_some() {
+              //abort. the worker will finish on its own, but the result is thrown away
+              self.loading = None;
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'u' {
+              self.mode = Mode::Url;
+              self.input = self.buffer().url.clone().unwrap_or(Url::new(String::new())).to_string();
+              WindowMessageResponse::JustRedraw
+            } else if (key_press.key == 'l' || key_press.key == 'F') && has_url {
+              self.mode = Mode::Link;
+              self.link_new_buffer = key_press.key == 'F';
+              self.calc_page(false);
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'f' {
+              self.mode = Mode::FormSubmit;
+              self.calc_page(false);
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 's' {
+              self.mode = Mode::Search;
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'f' && has_url {
+              self.mode = Mode::FormSubmit;
+              self.calc_page(false);
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'i' && has_url {
+              self.mode = Mode::FormInput;
+              self.calc_page(false);
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'j' || key_press.key == 'k' {
+              let num = self.maybe_num.unwrap_or(1);
+              self.maybe_num = None;
+              let buffer = self.buffer_mut();
+              if key_press.key == 'j' {
+                let max_top = buffer.max_top_line_no(page_height);
+                if buffer.top_line_no + num < max_top {
+                  buffer.top_line_no += num;
+                  WindowMessageResponse::JustRedraw
+                } else if buffer.top_line_no != max_top {
+                  buffer.top_line_no = max_top;
+                  WindowMessageResponse::JustRedraw
+                } else {
+                  WindowMessageResponse::DoNothing
+                }
+              } else {
+                if buffer.top_line_no > num {
+                  buffer.top_line_no -= num;
+                  WindowMessageResponse::JustRedraw
+                } else if buffer.top_line_no > 0 {
+                  buffer.top_line_no = 0;
+                  WindowMessageResponse::JustRedraw
+                } else {
+                  WindowMessageResponse::DoNothing
+                }
+              }
+            } else if key_press.key == 'g' {
+              if self.state == State::Maybeg {
+                self.buffer_mut().top_line_no = 0;
+                WindowMessageResponse::JustRedraw
+              } else {
+                self.state = State::Maybeg;
+                WindowMessageResponse::DoNothing
+              }
+            } else if key_press.key == 'z' {
+              self.state = State::Maybez;
+              WindowMessageResponse::DoNothing
+            } else if key_press.is_left_arrow() || key_press.is_right_arrow() {
+              let num = self.maybe_num.take().unwrap_or(1);
+              if self.scroll_sideways(key_press.is_right_arrow(), num * COLUMN_WIDTH) {
+                WindowMessageResponse::JustRedraw
+              } else {
+                WindowMessageResponse::DoNothing
+              }
+            } else if key_press.key == 'H' || key_press.key == 'L' {
+              if self.go_history(key_press.key == 'H') {
+                WindowMessageResponse::JustRedraw
+              } else {
+                WindowMessageResponse::DoNothing
+              }
+            } else if key_press.key == 'r' || key_press.key == 'R' {
+              if self.reload(key_press.key == 'R') {
+                WindowMessageResponse::JustRedraw
+              } else {
+                WindowMessageResponse::DoNothing
+              }
+            } else if key_press.key == 'J' || key_press.key == 'K' {
+              //next/previous buffer, wrapping around
+              if self.buffers.len() > 1 {
+                let new_index = if key_press.key == 'J' {
+                  (self.buffer_index + 1) % self.buffers.len()
+                } else {
+                  (self.buffer_index + self.buffers.len() - 1) % self.buffers.len()
+                };
+                self.switch_buffer(new_index);
+                WindowMessageResponse::JustRedraw
+              } else {
+                WindowMessageResponse::DoNothing
+              }
+            } else if key_press.key == 'b' {
+              //<num>b goes to that buffer, b on its own lists them
+              if let Some(num) = self.maybe_num.take() {
+                if num < self.buffers.len() {
+                  self.switch_buffer(num);
+                  WindowMessageResponse::JustRedraw
+                } else {
+                  WindowMessageResponse::DoNothing
+                }
+              } else {
+                self.go_to(Url::new("about:buffers".to_string()), false)
+              }
+            } else if key_press.key == 't' {
+              self.buffers.push(Default::default());
+              self.switch_buffer(self.buffers.len() - 1);
+              self.mode = Mode::Url;
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'w' && has_url {
+              self.mode = Mode::Save;
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'x' {
+              self.close_buffer();
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key == 'G' {
+              let buffer = self.buffer_mut();
+              buffer.top_line_no = buffer.max_top_line_no(page_height);
+              WindowMessageResponse::JustRedraw
+            } else if key_press.key.is_ascii_digit() {
+              self.maybe_num = Some(self.maybe_num.unwrap_or(0) * 10 + key_press.key.to_digit(10).unwrap() as usize);
+              WindowMessageResponse::DoNothing
+            } else if self.maybe_num.is_some() {
+              self.maybe_num = None;
+              WindowMessageResponse::DoNothing
+            } else {
+              WindowMessageResponse::DoNothing
+            }
+          },
+          Mode::Save => {
+            self.mode = Mode::Normal;
+            if ['s', 't', 'm'].contains(&key_press.key) {
+              self.save_page(key_press.key);
+            }
+            WindowMessageResponse::JustRedraw
+          },
+          //all modes besides normal, which use the bottom input
+          _ => {
+            if key_press.is_enter() && self.mode == Mode::Prompt {
+              //the answer goes in the query (an empty one is still an answer)
+              self.mode = Mode::Normal;
+              if let Some(prompt) = self.prompt.take() {
+                let mut url = prompt.url;
+                url.query = Some(percent_encode(&self.input));
+                url.fragment = None;
+                self.go_to(url, false)
+              } else {
+                WindowMessageResponse::JustRedraw
+              }
+            } else if key_press.is_enter() && self.input.len() > 0 {
+              if self.mode == Mode::Url || self.mode == Mode::Link {
+                let mut new_buffer = false;
+                let new_url = if self.mode == Mode::Link {
+                  self.mode = Mode::Normal;
+                  new_buffer = self.link_new_buffer;
+                  let buffer = self.buffer();
+                  let link_index = self.input.parse::<usize>().unwrap();
+                  if link_index < buffer.links.len() {
+                    let mut link = buffer.links[link_index].clone();
+                    if link.chars().count() >= 2 {
+                      link = remove_quotes(link);
+                    }
+                    buffer.url.as_ref().unwrap().resolve(&link)
+                  } else {
+                    return WindowMessageResponse::DoNothing
+                  }
+                } else {
+                  //if Mode::Url
+                  //check if starts with http:// or https://
+                  let url = if let Some(page) = self.input.strip_prefix(":") {
+                    //shorthand for internal pages, eg :history
+                    Url::new(format!("about:{}", page))
+                  } else if self.input.starts_with('/') {
+                    //a local path
+                    file::path_to_url(Path::new(&self.input))
+                  } else {
+                    Url::new(self.input.clone())
+                  };
+                  if !url.valid_scheme() && !url.is_internal() {
+                    Url::new(format!("https://lite.duckduckgo.com/lite?q={}", self.input))
+                  } else {
+                    url
+                  }
+                };
+                self.go_to(new_url, new_buffer)
+              } else if self.mode == Mode::FormSubmit || self.mode == Mode::FormInput {
+                if self.mode == Mode::FormInput {
+                  //this shouldn't be able to panic I hope
+                  //get_rest_of_split may return an empty string, but it won't panic
+                  let mut splitted = self.input.split("=");
+                  let first = splitted.next().unwrap();
+                  let input_value = get_rest_of_split(&mut splitted, Some("="));
+                  let mut first_splitted = first.split(",");
+                  let form_count = first_splitted.next().unwrap().parse::<usize>();
+                  //form count is not a number
+                  if form_count.is_err() {
+                    self.input = String::new();
+                    return WindowMessageResponse::JustRedraw;
+                  }
+                  let form_count = form_count.unwrap();
+                  let input_name = get_rest_of_split(&mut first_splitted, None); //I mean, there shouldn't be a comma in the input name, right?
+                  //insert overwrites
+                  //todo: check if exists first
+                  self.buffer_mut().form_inputs.insert((form_count, input_name), input_value);
+                  self.input = String::new();
+                  self.calc_page(false);
+                  WindowMessageResponse::JustRedraw
+                } else {
+                  //form submit
+                  let form_index = self.input.parse::<usize>().unwrap();
+                  let buffer = self.buffer();
+                  if form_index < buffer.forms.len() {
+                    let form_info = &buffer.forms[form_index];
+                    let current_url = buffer.url.clone().unwrap();
+                    let form_url = if let Some(action) = &form_info.action {
+                      current_url.resolve(action)
+                    } else {
+                      current_url.clone()
+                    };
+                    match form_info.method {
+                      FormSubmitMethod::Get => {
+                        //construct url to redirect to
+                        let mut form_url = form_url;
+                        //key aka name attr
+                        #[allow(clippy::needless_borrow)]
+                        for key in &form_info.input_names {
+                          if let Some(value) = buffer.form_inputs.get(&(form_index, key.clone())) {
+                            form_url.append_query(&key, value);
+                          }
+                        }
+                        self.go_to(form_url, false)
+                      },
+                      FormSubmitMethod::Post => {
+                        //todo. maybe later
+                        let mut body = String::new();
+                        for key in &form_info.input_names {
+                          if let Some(value) = buffer.form_inputs.get(&(form_index, key.clone())) {
+                            body += &format!("{}{}={}", if body.len() > 0 { "&" } else { "" }, key, handle_escaped(value, URL_REPLACE.to_vec(), true).replace(" ", "+"));
+                          }
+                        }
+                        let client = self.client.clone();
+                        self.start_load(form_url.clone(), LoadKind::Navigate(false), move || {
+                          Ok(http_loaded(client.post(form_url, body, &current_url)?))
+                        });
+                        WindowMessageResponse::JustRedraw
+                      },
+                    }
+                  } else {
+                    WindowMessageResponse::DoNothing
+                  }
+                }
+              } else {
+                //Mode::Search
+                let buffer = &mut self.buffers[self.buffer_index];
+                for p in &buffer.page {
+                  let line_no = line_at_y(&buffer.line_ys, p.1);
+                  if line_no > buffer.top_line_no {
+                    //p.2 is the text
+                    if p.2.contains(&self.input) {
+                      buffer.top_line_no = line_no;
+                      //and sideways, if the text is off the side
+                      if p.0 < buffer.left_x || p.0 >= buffer.left_x + self.dimensions[0] {
+                        buffer.left_x = (p.0 - 2).min(buffer.max_left_x(self.dimensions[0]));
+                      }
+                      return WindowMessageResponse::JustRedraw;
+                    }
+                  }
+                }
+                WindowMessageResponse::DoNothing
+              }
+            } else if key_press.is_escape() {
+              self.input = String::new();
+              let old_mode = self.mode;
+              self.mode = Mode::Normal;
+              if old_mode == Mode::Link || old_mode == Mode::FormSubmit || old_mode == Mode::FormInput {
+                self.calc_page(false);
+              }
+              WindowMessageResponse::JustRedraw
+            } else if key_press.is_backspace() && self.input.len() > 0 {
+              self.input = self.input.remove_last();
+              WindowMessageResponse::JustRedraw
+            } else if ((self.mode == Mode::Link || self.mode == Mode::FormSubmit) && key_press.key.is_ascii_digit() && self.input.len() < 10) || ((self.mode != Mode::Link && self.mode != Mode::FormSubmit) && key_press.is_regular()) {
+              self.input += &key_press.key.to_string();
+              WindowMessageResponse::JustRedraw
+            } else {
+              WindowMessageResponse::DoNothing
+            }
+          },
+        }
+      },
+      WindowMessage::CtrlKeyPress(key_press) => {
+        if key_press.key == 'a' {
+          self.input = String::new();
+          WindowMessageResponse::JustRedraw
+        } else {
+          WindowMessageResponse::DoNothing
+        }
+      },
+      _ => WindowMessageResponse::DoNothing,
+    };
+    //and the window is only redrawn in answer to a message, so a load that was just started is waited on for a bit,
+    //letting quick pages show now rather than on the next key press
+    let wait = self.loading.as_ref().map(|loading| LOAD_WAIT.saturating_sub(loading.started.elapsed()));
+    let loaded = loaded || wait.is_some_and(|wait| !wait.is_zero() && self.poll_loading(wait));
+    if (loaded || downloads_changed) && matches!(response, WindowMessageResponse::DoNothing) {
+      WindowMessageResponse::JustRedraw
+    } else {
+      response
+    }
+  }
+
+  fn draw(&self, theme_info: &ThemeInfo) -> Vec<DrawInstructions> {
+    let mut instructions = Vec::new();
+    let page_height = self.page_height();
+    let buffer = self.buffer();
+    let top_y = buffer.line_ys.get(buffer.top_line_no).copied().unwrap_or(0); //drawn 2 down, like the first line of the page is
+    for p in &buffer.page {
+      let line_no = line_at_y(&buffer.line_ys, p.1);
+      let line_height = buffer.line_ys[line_no + 1] - buffer.line_ys[line_no];
+      if line_no < buffer.top_line_no {
+        continue;
+      } else if p.1 - top_y + line_height > page_height {
+        break;
+      }
+      let (subtype, style) = (p.3, p.4);
+      let fonts = style_fonts(&self.fonts, &self.installed_fonts, style);
+      let (x, text) = clip_run(&p.2, p.0, buffer.left_x, &fonts, style.monospace);
+      if text.is_empty() || x - buffer.left_x >= self.dimensions[0] {
+        continue;
+      }
+      let style_height = buffer.line_heights.get(&style).copied().unwrap_or(LINE_HEIGHT);
+      //smaller text sits at the bottom of the line
+      let top_left = [x - buffer.left_x, p.1 - top_y + 2 + line_height - style_height];
+      let bg_colour = if subtype == Subtype::TextInput || subtype == Subtype::Button {
+        Some(theme_info.alt_background)
+      } else {
+        None
+      };
+      let mono_width = if style.monospace { Some(MONO_WIDTH) } else { None };
+      let width = if style.monospace {
+        (text.chars().count() * COLUMN_WIDTH).saturating_sub(1)
+      } else {
+        measure_text(&fonts, text, Some(1)).width
+      };
+      if let Some(bg_colour) = bg_colour {
+        instructions.push(DrawInstructions::Rect([top_left[0].saturating_sub(2), top_left[1] - 2], [width, style_height], bg_colour));
+      }
+      let colour = subtype_rgb(subtype, style, theme_info);
+      let (fake_bold, fake_italic) = faked_styles(&fonts, style);
+      let bg_colour = bg_colour.unwrap_or(theme_info.background);
+      if fake_bold {
+        //drawn again a pixel to the right, so the strokes are thicker
+        instructions.push(DrawInstructions::Text([top_left[0] + 1, top_left[1]], fonts.clone(), text.to_string(), colour, bg_colour, Some(1), mono_width));
+      }
+      instructions.push(DrawInstructions::Text(top_left, fonts, text.to_string(), colour, bg_colour, Some(1), mono_width));
+      if fake_italic {
+        //dotted, so it isn't mistaken for an underline
+        for dot_x in (top_left[0]..top_left[0] + width).step_by(3) {
+          instructions.push(DrawInstructions::Rect([dot_x, top_left[1] + style_height - 3], [1, 1], colour));
+        }
+      }
+      if style.underline {
+        instructions.push(DrawInstructions::Rect([top_left[0], top_left[1] + style_height - 3], [width, 1], colour));
+      }
+      if style.strikethrough {
+        instructions.push(DrawInstructions::Rect([top_left[0], top_left[1] + style_height / 2], [width, 1], colour));
+      }
+    }
+    //only the part on screen
+    for (x, y, width, height) in &buffer.borders {
+      let (top, bottom) = ((*y).max(top_y), (y + height).min(top_y + page_height));
+      let (left, right) = ((*x).max(buffer.left_x), (x + width).min(buffer.left_x + self.dimensions[0]));
+      if bottom > top && right > left {
+        instructions.push(DrawInstructions::Rect([left - buffer.left_x, top - top_y + 2], [right - left, bottom - top], theme_info.text));
+      }
+    }
+    //mode, in a blue band
+    instructions.push(DrawInstructions::Rect([0, self.dimensions[1] - BAND_HEIGHT * 2], [self.dimensions[0], BAND_HEIGHT], theme_info.top));
+    let bottom_text = if let Some(loading) = &self.loading {
+      format!("LOADING {}", loading.url)
+    } else {
+      let mut bottom_text = self.mode.to_string() + ": ";
+      if let (Mode::Normal, Some(status)) = (self.mode, &self.status) {
+        bottom_text += status;
+      } else if self.mode == Mode::Save {
+        bottom_text += "s(ource), t(ext), m(arkdown)";
+      } else if self.mode == Mode::Normal && self.dimensions[0] >= 300 {
+        bottom_text += "u(rl)";
+        if buffer.url.is_some() && self.dimensions[0] >= 640 {
+          bottom_text += ", s(earch), l(ink), i(nput), f(orm), j, k, H, L";
+        }
+      } else if self.mode == Mode::FormInput && self.dimensions[0] > 500 {
+        bottom_text += "syntax is eg \"0,inputname=input value\"";
+      } else if let (Mode::Prompt, Some(prompt)) = (self.mode, &self.prompt) {
+        bottom_text += &prompt.prompt;
+      }
+      bottom_text
+    };
+    instructions.push(DrawInstructions::Text([0, self.dimensions[1] - LINE_HEIGHT * 2], vec!["nimbus-romono".to_string()], bottom_text, theme_info.top_text, theme_info.top, Some(1), Some(MONO_WIDTH)));
+    let input = if self.mode == Mode::Prompt && self.prompt.as_ref().is_some_and(|p| p.sensitive) {
+      "*".repeat(self.input.chars().count())
+    } else {
+      self.input.clone()
+    };
+    instructions.push(DrawInstructions::Text([0, self.dimensions[1] - LINE_HEIGHT], vec!["nimbus-romono".to_string()], input, theme_info.text, theme_info.background, Some(1), Some(MONO_WIDTH)));
+    instructions
+  }
+
+  fn title(&self) -> String {
+    let t = if let Some(title) = &self.buffer().title {
+      format!(": {}", title)
+    } else {
+      " Browser".to_string()
+    };
+    //only bother showing the buffer number if there is more than one
+    let b = if self.buffers.len() > 1 {
+      format!(" [{}]", self.buffer_index)
+    } else {
+      String::new()
+    };
+    "Koxinga".to_string() + &b + &t
+  }
+
+  fn subtype(&self) -> WindowLikeType {
+    WindowLikeType::Window
+  }
+
+  fn ideal_dimensions(&self, _dimensions: Dimensions) -> Dimensions {
+    [650, 410]
+  }
+
+  fn resizable(&self) -> bool {
+    true
+  }
+}
+
+impl KoxingaBrowser {
+  pub fn new(fonts: Vec<String>) -> Self {
+    Self {
+      installed_fonts: installed_fonts(&fonts),
+      fonts,
+      buffers: vec![Default::default()],
+      config: Config::load(),
+      ..Default::default()
+    }
+  }
+
+  fn buffer(&self) -> &Buffer {
+    &self.buffers[self.buffer_index]
+  }
+
+  fn buffer_mut(&mut self) -> &mut Buffer {
+    &mut self.buffers[self.buffer_index]
+  }
+
+  //height of the page part of the window, above the band and input
+  fn page_height(&self) -> usize {
+    self.dimensions[1].saturating_sub(2 + LINE_HEIGHT * 2)
+  }
+
+  fn rewrap(&mut self) {
+    //rewrapping changes line numbers, so keep the same text at the top
+    let top_offset = self.buffer().line_offsets.get(self.buffer().top_line_no).copied();
+    self.calc_page(false);
+    if let Some(top_offset) = top_offset {
+      let max_top_line_no = self.buffer().max_top_line_no(self.page_height());
+      let buffer = self.buffer_mut();
+      buffer.top_line_no = buffer.line_at_offset(top_offset).min(max_top_line_no);
+    }
+    let max_left_x = self.buffer().max_left_x(self.dimensions[0]);
+    let buffer = self.buffer_mut();
+    buffer.left_x = buffer.left_x.min(max_left_x);
+  }
+
+  //scroll sideways by amount, returns false if it was already as far as it goes
+  fn scroll_sideways(&mut self, right: bool, amount: usize) -> bool {
+    let max_left_x = self.buffer().max_left_x(self.dimensions[0]);
+    let buffer = self.buffer_mut();
+    let left_x = if right {
+      (buffer.left_x + amount).min(max_left_x.max(buffer.left_x))
+    } else {
+      buffer.left_x.saturating_sub(amount)
+    };
+    let changed = left_x != buffer.left_x;
+    buffer.left_x = left_x;
+    changed
+  }
+
+  fn switch_buffer(&mut self, index: usize) {
+    //the page was meant for the buffer we are leaving
+    self.loading = None;
+    self.buffer_index = index;
+    self.mode = Mode::Normal;
+    self.input = String::new();
+    //dimensions may have changed since it was last laid out
+    self.calc_page(false);
+  }
+
+  fn close_buffer(&mut self) {
+    self.buffers.remove(self.buffer_index);
+    if self.buffers.is_empty() {
+      self.buffers.push(Default::default());
+    }
+    self.switch_buffer(self.buffer_index.min(self.buffers.len() - 1));
+  }
+
+  //load url and show it, either in the current buffer or a new one
+  fn go_to(&mut self, url: Url, new_buffer: bool) -> WindowMessageResponse {
+    if let (Some(fragment), Some(current_url)) = (&url.fragment, &self.buffer().url) {
+      if !new_buffer && url.same_document(current_url) {
+        //same page, so just scroll, unless it's eg: a json fold toggle
+        let fragment = fragment.clone();
+        if self.buffer_mut().document.toggle(&fragment) {
+          self.calc_page(false);
+          let max_top_line_no = self.buffer().max_top_line_no(self.page_height());
+          let buffer = self.buffer_mut();
+          buffer.top_line_no = buffer.top_line_no.min(max_top_line_no);
+        } else {
+          self.buffer_mut().url = Some(url);
+          self.scroll_to_fragment(&fragment);
+        }
+        return WindowMessageResponse::JustRedraw;
+      }
+    }
+    if url.is_internal() {
+      let mut url = url;
+      if url.path == "downloads" {
+        //cancel links are about:downloads?cancel=<num>
+        if let Some(index) = url.query.take().and_then(|q| q.strip_prefix("cancel=").and_then(|i| i.parse().ok())) {
+          self.downloads.cancel(index);
+          if self.buffer().url.as_ref() == Some(&url) {
+            self.reload(false);
+            return WindowMessageResponse::JustRedraw;
+          }
+        }
+      }
+      //generated on the spot, no need for the worker
+      if let Some(text) = self.internal_page(&url) {
+        self.show_page(url, Document::new("text/html", &text), new_buffer);
+        WindowMessageResponse::JustRedraw
+      } else {
+        WindowMessageResponse::DoNothing
+      }
+    } else if url.scheme.as_deref() == Some("gopher") && matches!(gopher::split_url(&url), ('7', _, None)) {
+      //gopher searches need something to search for before there is anything to fetch
+      self.prompt = Some(Prompt { prompt: "Search for".to_string(), sensitive: false, url });
+      self.mode = Mode::Prompt;
+      self.input = String::new();
+      self.calc_page(false);
+      WindowMessageResponse::JustRedraw
+    } else {
+      self.start_get(url, LoadKind::Navigate(new_buffer));
+      WindowMessageResponse::JustRedraw
+    }
+  }
+
+  fn show_page(&mut self, url: Url, document: Document, new_buffer: bool) {
+    if new_buffer {
+      self.buffers.push(Default::default());
+      self.buffer_index = self.buffers.len() - 1;
+    }
+    self.change_url(url, document);
+  }
+
+  //any load already in progress is abandoned
+  fn start_load(&mut self, url: Url, kind: LoadKind, job: impl FnOnce() -> Result<Loaded, LoadError> + Send + 'static) {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+      //if the load was aborted, the receiver is gone, and that's fine
+      let _ = sender.send(job());
+    });
+    self.loading = Some(Loading { url, kind, receiver, started: Instant::now() });
+    let old_mode = self.mode;
+    self.mode = Mode::Normal;
+    self.input = String::new();
+    if old_mode == Mode::Link || old_mode == Mode::FormSubmit || old_mode == Mode::FormInput {
+      self.calc_page(false);
+    }
+  }
+
+  //fetch url with whichever client its scheme needs. only http has a cache to skip
+  fn get_job(&self, url: Url, use_cache: bool) -> impl FnOnce() -> Result<Loaded, LoadError> + Send + 'static {
+    let client = self.client.clone();
+    let gemini_client = self.gemini_client.clone();
+    move || {
+      if url.scheme.as_deref() == Some("gemini") {
+        gemini_job(gemini_client, url)
+      } else if url.scheme.as_deref() == Some("gopher") {
+        gopher_job(url)
+      } else if url.scheme.as_deref() == Some("file") {
+        file_job(url)
+      } else {
+        let resp = if use_cache { client.get(&url) } else { client.get_uncached(&url) }?;
+        Ok(http_loaded(resp))
+      }
+    }
+  }
+
+  fn start_get(&mut self, url: Url, kind: LoadKind) {
+    let job = self.get_job(url.clone(), true);
+    self.start_load(url, kind, job);
+  }
+
+  //returns true if the load finished (successfully or not), waiting up to `wait` for it
+  fn poll_loading(&mut self, wait: Duration) -> bool {
+    if let Some(loading) = &self.loading {
+      let loaded = match loading.receiver.recv_timeout(wait) {
+        Ok(loaded) => loaded,
+        Err(RecvTimeoutError::Timeout) => return false,
+        //worker died
+        Err(RecvTimeoutError::Disconnected) => Err(LoadError { title: "Could not load page".to_string(), description: Some("the page stopped loading".to_string()) }),
+      };
+      let loading = self.loading.take().unwrap();
+      //errors get a page of their own, under the url that was asked for
+      let loaded = loaded.unwrap_or_else(|e| Loaded::Page {
+        document: Document::new("text/html", &error_page(&loading.url, &e)),
+        url: loading.url.clone(),
+      });
+      match loaded {
+        Loaded::Page { document, mut url } => {
+          //fragments aren't sent to the server, so the final url won't have it, unless a redirect added one
+          if url.fragment.is_none() {
+            url.fragment = loading.url.fragment;
+          }
+          match loading.kind {
+            LoadKind::Navigate(new_buffer) => self.show_page(url, document, new_buffer),
+            LoadKind::History(index) => {
+              if index < self.buffer().history.len() {
+                self.restore_history(index, Some(document));
+              }
+            },
+          }
+        },
+        Loaded::Download { url, stream } => {
+          self.downloads.start(url, stream, self.config.download_dir.clone());
+          self.show_downloads();
+        },
+        Loaded::Prompt { prompt, sensitive, url } => {
+          //the current page stays, with the question on the bottom
+          self.prompt = Some(Prompt { prompt, sensitive, url });
+          self.mode = Mode::Prompt;
+          self.input = String::new();
+        },
+      }
+      true
+    } else {
+      false
+    }
+  }
+
+  //save the page to the save dir: its s(ource), laid out t(ext) with link footnotes, or m(arkdown)
+  fn save_page(&mut self, format: char) {
+    let buffer = self.buffer();
+    let Some(url) = &buffer.url else {
+      return;
+    };
+    let (extension, contents) = match format {
+      's' => (buffer.document.extension(), buffer.document.source().to_string()),
+      't' => {
+        //the same layout as calc_page does
+        let mut caches = HashMap::new();
+        let measure = |c: char, style: Style| char_width(&mut caches, &self.fonts, &self.installed_fonts, c, style);
+        ("txt", dump::render(&buffer.document, url, self.dimensions[0], buffer.wrap, measure, true))
+      },
+      _ => ("md", save::markdown(&buffer.document, url)),
+    };
+    let name = file_name(buffer.title.as_deref(), url, extension);
+    self.status = Some(match save::save(&self.config.save_dir, &name, &contents) {
+      Ok(path) => format!("saved to {}", path.to_string_lossy()),
+      Err(e) => format!("could not save: {}", e),
+    });
+  }
+
+  fn show_downloads(&mut self) {
+    let url = Url::new("about:downloads".to_string());
+    if self.buffer().url.as_ref() == Some(&url) {
+      self.reload(false);
+    } else {
+      self.go_to(url, false);
+    }
+  }
+
+  //remake the downloads page if it is showing and any download has moved on since. returns true if it was remade
+  fn refresh_downloads(&mut self) -> bool {
+    let showing = self.buffer().url.as_ref().is_some_and(|u| u.is_internal() && u.path == "downloads");
+    if !showing || self.mode != Mode::Normal || self.loading.is_some() {
+      return false;
+    }
+    let statuses = self.downloads.statuses();
+    if statuses == self.downloads_shown {
+      return false;
+    }
+    self.reload(false);
+    true
+  }
+
+  pub fn change_url(&mut self, new_url: Url, document: Document) {
+    let mode = self.mode;
+    let fragment = new_url.fragment.clone();
+    let buffer = self.buffer_mut();
+    buffer.push_history_entry(new_url.clone(), mode);
+    buffer.url = Some(new_url);
+    buffer.top_line_no = 0;
+    buffer.left_x = 0;
+    buffer.wrap = document.wraps();
+    buffer.document = document;
+    self.input = String::new();
+    self.mode = Mode::Normal;
+    self.calc_page(true);
+    if let Some(fragment) = fragment {
+      self.scroll_to_fragment(&fragment);
+    }
+  }
+
+  //returns false if the page has no such element
+  fn scroll_to_fragment(&mut self, fragment: &str) -> bool {
+    let page_height = self.page_height();
+    let buffer = self.buffer_mut();
+    let line_no = buffer.anchors.get(fragment).or(buffer.anchors.get(&percent_decode(fragment))).copied();
+    if let Some(line_no) = line_no {
+      buffer.top_line_no = line_no.min(buffer.max_top_line_no(page_height));
+      true
+    } else if fragment.is_empty() || fragment.eq_ignore_ascii_case("top") {
+      //html spec says these mean the top of the page, if there isn't an element with that id
+      buffer.top_line_no = 0;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn internal_page(&mut self, url: &Url) -> Option<String> {
+    match url.path.as_str() {
+      "history" => {
+        let buffer = self.buffer();
+        let mut body = String::new();
+        //newest first
+        for (i, entry) in buffer.history.iter().enumerate().rev() {
+          let title = if i == buffer.history_index {
+            buffer.title.as_ref()
+          } else {
+            entry.title.as_ref()
+          }.map_or(entry.url.to_string(), |t| t.to_string());
+          body += &format!("<p>{}<a href=\"{}\">{}</a> {}</p>", if i == buffer.history_index { "&gt; " } else { "" }, entry.url.to_string().replace("\"", "%22"), escape_html(&title), escape_html(&entry.url.to_string()));
+        }
+        Some(format!("<html><head><title>History</title></head><body><h1>History</h1>{}</body></html>", body))
+      },
+      "buffers" => {
+        let mut body = String::new();
+        for (i, buffer) in self.buffers.iter().enumerate() {
+          let marker = if i == self.buffer_index { "&gt; " } else { "" };
+          if let Some(url) = &buffer.url {
+            let title = buffer.title.clone().unwrap_or(url.to_string());
+            body += &format!("<p>{}{}: <a href=\"{}\">{}</a> {}</p>", marker, i, url.to_string().replace("\"", "%22"), escape_html(&title), escape_html(&url.to_string()));
+          } else {
+            body += &format!("<p>{}{}: (empty)</p>", marker, i);
+          }
+        }
+        Some(format!("<html><head><title>Buffers</title></head><body><h1>Buffers</h1>{}</body></html>", body))
+      },
+      "downloads" => {
+        let statuses = self.downloads.statuses();
+        let mut body = format!("<p>Saving to {}</p>", escape_html(&self.config.download_dir.to_string_lossy()));
+        //newest first
+        for (i, status) in statuses.iter().enumerate().rev() {
+          let name = status.path.as_ref().and_then(|p| p.file_name()).map_or(status.url.to_string(), |n| n.to_string_lossy().to_string());
+          let progress = match status.size {
+            Some(size) if size > 0 => format!("{} of {} ({}%)", format_size(status.received), format_size(size), status.received * 100 / size),
+            _ => format_size(status.received),
+          };
+          let state = match &status.state {
+            DownloadState::Downloading => format!("downloading. <a href=\"about:downloads?cancel={}\">Cancel</a>", i),
+            DownloadState::Finished => "done".to_string(),
+            DownloadState::Failed(e) => format!("failed: {}", escape_html(e)),
+            DownloadState::Cancelled => "cancelled".to_string(),
+          };
+          body += &format!("<p>{}: <a href=\"{}\">{}</a></p><div indent=\"1\">{}, {}</div>", i, status.url.to_string().replace("\"", "%22"), escape_html(&name), progress, state);
+          if let (Some(path), DownloadState::Finished) = (&status.path, &status.state) {
+            body += &format!("<div indent=\"1\">Saved to {}</div>", escape_html(&path.to_string_lossy()));
+          }
+        }
+        self.downloads_shown = statuses;
+        Some(format!("<html><head><title>Downloads</title></head><body><h1>Downloads</h1>{}</body></html>", body))
+      },
+      _ => None,
+    }
+  }
+
+  //returns false if there is nowhere to go
+  pub fn go_history(&mut self, back: bool) -> bool {
+    let buffer = self.buffer();
+    if buffer.url.is_none() || (back && buffer.history_index == 0) || (!back && buffer.history_index + 1 >= buffer.history.len()) {
+      return false;
+    }
+    let index = if back {
+      buffer.history_index - 1
+    } else {
+      buffer.history_index + 1
+    };
+    let entry = &buffer.history[index];
+    if entry.document.is_some() {
+      self.restore_history(index, None);
+    } else if entry.url.is_internal() {
+      let url = entry.url.clone();
+      let document = Document::new("text/html", &self.internal_page(&url).unwrap_or_default());
+      self.restore_history(index, Some(document));
+    } else {
+      //not in memory anymore, refetch
+      self.start_get(entry.url.clone(), LoadKind::History(index));
+    }
+    true
+  }
+
+  //fetch the current page again, keeping the scroll position. returns false if there is no page
+  //forced reloads skip the cache
+  fn reload(&mut self, force: bool) -> bool {
+    let buffer = self.buffer();
+    let Some(url) = buffer.url.clone() else {
+      return false;
+    };
+    let index = buffer.history_index;
+    if url.is_internal() {
+      let document = Document::new("text/html", &self.internal_page(&url).unwrap_or_default());
+      self.restore_history(index, Some(document));
+    } else {
+      let job = self.get_job(url.clone(), !force);
+      self.start_load(url, LoadKind::History(index), job);
+    }
+    true
+  }
+
+  //fresh_document is Some if the page was not cached and had to be refetched
+  fn restore_history(&mut self, index: usize, fresh_document: Option<Document>) {
+    let mode = self.mode;
+    let buffer = self.buffer_mut();
+    buffer.save_history_entry(mode);
+    buffer.history_index = index;
+    let entry = &mut buffer.history[index];
+    let cached_document = entry.document.take();
+    let form_inputs = entry.form_inputs.clone();
+    let top_line_no = entry.top_line_no;
+    let entry_mode = entry.mode;
+    buffer.url = Some(entry.url.clone());
+    buffer.left_x = 0;
+    buffer.wrap = entry.wrap;
+    self.mode = entry_mode;
+    self.input = String::new();
+    if let Some(document) = fresh_document {
+      self.buffer_mut().document = document;
+      self.calc_page(true);
+      //keep anything typed in that the page still has
+      self.buffer_mut().form_inputs.extend(form_inputs);
+      self.calc_page(false);
+    } else {
+      let buffer = self.buffer_mut();
+      buffer.document = cached_document.unwrap_or_default();
+      buffer.form_inputs = form_inputs;
+      self.calc_page(false);
+    }
+    let page_height = self.page_height();
+    let buffer = self.buffer_mut();
+    buffer.top_line_no = top_line_no.min(buffer.max_top_line_no(page_height));
+    buffer.uncache_far_history();
+  }
+
+  pub fn calc_page(&mut self, new_page: bool) {
+    let buffer = &mut self.buffers[self.buffer_index];
+    buffer.title = None;
+    buffer.page = Vec::new();
+    buffer.links = Vec::new();
+    buffer.forms = Vec::new();
+    buffer.anchors = HashMap::new();
+    if new_page {
+      buffer.form_inputs = HashMap::new();
+    }
+    let (title, outputs) = buffer.document.outputs();
+    buffer.title = title;
+    let mut link_counter = 0;
+    let mut form_counter = 0;
+    let mode = self.mode;
+    let mut caches = HashMap::new();
+    let measure = |c: char, style: Style| char_width(&mut caches, &self.fonts, &self.installed_fonts, c, style);
+    let mut line_heights = HashMap::new();
+    let line_height = |style: Style| *line_heights.entry(style).or_insert_with(|| style_line_height(&self.fonts, &self.installed_fonts, style));
+    let layout = layout(outputs, self.dimensions[0], buffer.wrap, measure, line_height, |o| match o {
+      OutputType::StartLink(link) => {
+        if mode == Mode::Link {
+          buffer.links.push(link.to_string());
+          let s = link_counter.to_string() + ":";
+          link_counter += 1;
+          Some(s)
+        } else {
+          None
+        }
+      },
+      OutputType::Form(form) => {
+        buffer.forms.push(form.clone());
+        let t = if mode == Mode::FormSubmit {
+          form_counter.to_string() + ":"
+        } else {
+          String::new()
+        } + "Submit Form";
+        form_counter += 1;
+        Some(t)
+      },
+      OutputType::TextInput(name, default_value) => {
+        if new_page {
+          buffer.form_inputs.insert((form_counter, name.to_string()), default_value.to_string());
+        }
+        let t = if mode == Mode::FormInput || mode == Mode::FormSubmit {
+          format!("{},{}={}\n", form_counter, name, buffer.form_inputs.get(&(form_counter, name.to_owned())).unwrap())
+        } else {
+          name.to_owned() + "\n"
+        };
+        Some(t)
+      },
+      _ => None,
+    });
+    buffer.page = layout.page;
+    buffer.line_offsets = layout.line_offsets;
+    buffer.anchors = layout.anchors;
+    buffer.line_ys = layout.line_ys;
+    buffer.line_heights = line_heights;
+    buffer.borders = layout.borders;
+    buffer.width = layout.width;
+  }
+}
+
+pub fn run() {
+  listen(KoxingaBrowser::new(vec!["nimbus-roman".to_string(), "shippori-mincho".to_string()]));
+}
+
+#[test]
+fn test_draw_first_line_input() {
+  let mut browser = KoxingaBrowser { dimensions: [650, 410], fonts: vec!["nimbus-roman".to_string()], buffers: vec![Default::default()], ..Default::default() };
+  browser.buffer_mut().document = Document::new("text/html", "<input name=q><p>below</p>");
+  browser.calc_page(true);
+  let instructions = browser.draw(&ThemeInfo::default());
+  //the input's background is just above and left of its text, which is 2 from the top like any first line
+  let DrawInstructions::Text(top_left, _, text, ..) = &instructions[1] else { panic!("input text not drawn second") };
+  assert_eq!((top_left[1], text.as_str()), (2, "q"));
+  assert!(matches!(instructions[0], DrawInstructions::Rect([0, 0], _, _)));
+}
+
+#[test]
+fn test_key_after_load() {
+  let mut browser = KoxingaBrowser { dimensions: [650, 410], fonts: vec!["nimbus-roman".to_string()], buffers: vec![Default::default()], ..Default::default() };
+  let (sender, receiver) = channel();
+  let url = Url::new("https://example.org/".to_string());
+  sender.send(Ok(Loaded::Page { document: Document::new("text/html", &"<p>line</p>".repeat(100)), url: url.clone() })).unwrap();
+  browser.loading = Some(Loading { url, kind: LoadKind::Navigate(false), receiver, started: Instant::now() });
+  //the page shows, and the key goes to it rather than being lost
+  browser.handle_message(WindowMessage::KeyPress(ming_wm_lib::messages::KeyPress { key: 'G' }));
+  assert!(browser.loading.is_none());
+  assert!(browser.buffer().top_line_no > 0);
+}
+
+#[test]
+fn test_style_fonts() {
+  let fonts = vec!["nimbus-roman".to_string()];
+  let bold_italic = Style { bold: true, italic: true, ..Style::default() };
+  //fonts that aren't there are left out, and what they would have shown is drawn another way
+  let none = HashSet::new();
+  assert_eq!(style_fonts(&fonts, &none, bold_italic), fonts);
+  assert_eq!(faked_styles(&fonts, bold_italic), (true, true));
+  let installed = HashSet::from(["nimbus-roman-bold-italic".to_string(), "nimbus-roman-h1".to_string()]);
+  assert_eq!(style_fonts(&fonts, &installed, bold_italic), vec!["nimbus-roman-bold-italic", "nimbus-roman"]);
+  assert_eq!(faked_styles(&style_fonts(&fonts, &installed, bold_italic), bold_italic), (false, false));
+  let h2 = Style { heading: 2, ..Style::default() };
+  assert_eq!(faked_styles(&style_fonts(&fonts, &installed, h2), h2), (true, false));
+  let h1 = Style { heading: 1, ..Style::default() };
+  assert_eq!(faked_styles(&style_fonts(&fonts, &installed, h1), h1), (false, false));
+  //so bold is drawn twice, a pixel apart, and italic has dots under it
+  let mut browser = KoxingaBrowser { dimensions: [650, 410], fonts, buffers: vec![Default::default()], ..Default::default() };
+  browser.buffer_mut().document = Document::new("text/html", "<b>bold</b> <i>italic</i>");
+  browser.calc_page(true);
+  let instructions = browser.draw(&ThemeInfo::default());
+  let texts: Vec<(usize, &str)> = instructions.iter().filter_map(|i| if let DrawInstructions::Text(top_left, _, text, ..) = i { Some((top_left[0], text.as_str())) } else { None }).collect();
+  assert_eq!(texts[..2], [(3, "bold"), (2, "bold")]);
+  assert!(texts.iter().any(|(_, text)| *text == "italic"));
+  assert!(instructions.iter().filter(|i| matches!(i, DrawInstructions::Rect(_, [1, 1], _))).count() > 1);
+}
+
+#[test]
+fn test_quick_load() {
+  let mut browser = KoxingaBrowser { dimensions: [650, 410], fonts: vec!["nimbus-roman".to_string()], buffers: vec![Default::default()], ..Default::default() };
+  let url = Url::new("https://example.org/".to_string());
+  let job_url = url.clone();
+  browser.start_load(url.clone(), LoadKind::Navigate(false), move || {
+    thread::sleep(Duration::from_millis(50));
+    Ok(Loaded::Page { document: Document::new("text/html", "<p>quick</p>"), url: job_url })
+  });
+  //shown in answer to the same message, without waiting for another
+  assert_eq!(browser.handle_message(WindowMessage::Focus), WindowMessageResponse::JustRedraw);
+  assert!(browser.loading.is_none());
+  //but a load that has been going a while isn't waited on again
+  let (_sender, receiver) = channel();
+  browser.loading = Some(Loading { url, kind: LoadKind::Navigate(false), receiver, started: Instant::now() - LOAD_WAIT });
+  let before = Instant::now();
+  assert_eq!(browser.handle_message(WindowMessage::Focus), WindowMessageResponse::DoNothing);
+  assert!(browser.loading.is_some() && before.elapsed() < LOAD_WAIT);
+}
+
+#[test]
+fn test_clip_run() {
+  //monospace chars are 12 wide, so 28 off the left takes 3 of them
+  assert_eq!(clip_run("abcdef", 2, 30, &[], true), (38, "def"));
+  assert_eq!(clip_run("abcdef", 2, 2, &[], true), (2, "abcdef"));
+  assert_eq!(clip_run("鄭成功", 2, 100, &[], true), (38, ""));
+}
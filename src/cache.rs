@@ -0,0 +1,327 @@
+use std::vec::Vec;
+use std::collections::HashMap;
+use std::fs::{ create_dir_all, read_to_string, remove_file, write };
+use std::path::PathBuf;
+
+use reqwest::header::HeaderMap;
+
+use crate::cookies::parse_cookie_date;
+use crate::dirs::cache_dir;
+
+//a private (one user) cache of GET responses on disk, mostly following rfc 9111
+//bodies are in their own files, named by a hash of the url, and everything else is in an index file
+
+const MAX_SIZE: u64 = 50 * 1024 * 1024; //bytes of bodies
+const MAX_HEURISTIC_LIFETIME: u64 = 24 * 60 * 60; //see rfc 9111 4.2.2
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheEntry {
+  pub final_url: String, //after redirects
+  pub fresh_until: u64, //unix seconds. 0 means it always has to be revalidated
+  pub etag: Option<String>,
+  pub last_modified: Option<String>,
+  pub content_type: Option<String>,
+  last_used: u64, //not a time, just goes up every use, for lru eviction
+  size: u64,
+}
+
+impl CacheEntry {
+  pub fn is_fresh(&self, now: u64) -> bool {
+    now < self.fresh_until
+  }
+
+  //one line, tab separated, for the index file. url last since it's the only thing that might have a tab
+  fn serialise(&self, url: &str) -> String {
+    format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", self.last_used, self.size, self.fresh_until, self.etag.as_deref().unwrap_or(""), self.last_modified.as_deref().unwrap_or(""), self.content_type.as_deref().unwrap_or(""), self.final_url, url)
+  }
+
+  //lines from before content types were kept are a field short, so they don't parse and just get dropped
+  fn deserialise(line: &str) -> Option<(String, Self)> {
+    let mut parts = line.splitn(8, '\t');
+    let last_used = parts.next()?.parse().ok()?;
+    let size = parts.next()?.parse().ok()?;
+    let fresh_until = parts.next()?.parse().ok()?;
+    let etag = Some(parts.next()?.to_string()).filter(|e| !e.is_empty());
+    let last_modified = Some(parts.next()?.to_string()).filter(|l| !l.is_empty());
+    let content_type = Some(parts.next()?.to_string()).filter(|c| !c.is_empty());
+    let final_url = parts.next()?.to_string();
+    let url = parts.next()?.to_string();
+    Some((url, Self { final_url, fresh_until, etag, last_modified, content_type, last_used, size }))
+  }
+}
+
+//fnv-1a, which (unlike the std hasher) is guaranteed to stay the same between runs
+fn file_name(url: &str) -> String {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for b in url.bytes() {
+    hash ^= b as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  format!("{:016x}", hash)
+}
+
+//fragments are never sent to the server, so page#a and page#b are the same response
+fn key(url: &str) -> &str {
+  url.split_once('#').map_or(url, |(url, _)| url)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+  headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+//lowercase directive names and their values (without quotes), from all Cache-Control headers
+fn cache_control(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+  headers.get_all("Cache-Control").iter().filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(',')).filter_map(|directive| {
+    let (name, value) = if let Some((name, value)) = directive.split_once('=') {
+      (name, Some(value.trim().trim_matches('"').to_string()))
+    } else {
+      (directive, None)
+    };
+    let name = name.trim().to_lowercase();
+    if name.is_empty() {
+      None
+    } else {
+      Some((name, value))
+    }
+  }).collect()
+}
+
+//when a response stops being fresh, or None if it shouldn't be stored at all
+pub fn fresh_until(headers: &HeaderMap, now: u64) -> Option<u64> {
+  let directives = cache_control(headers);
+  let directive = |name: &str| directives.iter().find(|(n, _)| n == name);
+  if directive("no-store").is_some() || header(headers, "Vary").is_some_and(|v| v.trim() == "*") {
+    return None;
+  }
+  let has_validator = headers.contains_key("ETag") || headers.contains_key("Last-Modified");
+  if directive("no-cache").is_some() {
+    return if has_validator { Some(0) } else { None };
+  }
+  let date = header(headers, "Date").and_then(parse_cookie_date).unwrap_or(now);
+  let age = header(headers, "Age").and_then(|a| a.trim().parse::<u64>().ok()).unwrap_or(0);
+  //max-age wins over expires, and with neither, guess from how long ago it was last changed
+  let lifetime = if let Some(max_age) = directive("max-age").and_then(|(_, v)| v.as_ref()?.parse::<u64>().ok()) {
+    max_age
+  } else if let Some(expires) = header(headers, "Expires") {
+    //invalid dates (like "0") mean already expired
+    parse_cookie_date(expires).map_or(0, |e| e.saturating_sub(date))
+  } else if let Some(last_modified) = header(headers, "Last-Modified").and_then(parse_cookie_date) {
+    (date.saturating_sub(last_modified) / 10).min(MAX_HEURISTIC_LIFETIME)
+  } else {
+    0
+  };
+  if lifetime <= age {
+    //stale already, only worth keeping if it can be revalidated
+    if has_validator { Some(0) } else { None }
+  } else {
+    Some(now + lifetime - age)
+  }
+}
+
+#[derive(Default)]
+pub struct HttpCache {
+  entries: HashMap<String, CacheEntry>, //keyed by the requested url, without its fragment
+  dir: Option<PathBuf>, //None to not cache anything
+  max_size: u64,
+  counter: u64,
+  dirty: bool, //whether entries have changed (only last_used, otherwise it's saved straight away) since the index was saved
+}
+
+impl HttpCache {
+  //read the index from the cache dir
+  pub fn load() -> Self {
+    Self::in_dir(cache_dir().map(|mut c| {
+      c.push("koxinga");
+      c.push("http");
+      c
+    }), MAX_SIZE)
+  }
+
+  fn in_dir(dir: Option<PathBuf>, max_size: u64) -> Self {
+    let entries: HashMap<String, CacheEntry> = dir.as_ref().and_then(|d| read_to_string(d.join("index")).ok()).map_or(HashMap::new(), |contents| {
+      contents.lines().filter_map(CacheEntry::deserialise).collect()
+    });
+    let counter = entries.values().map(|e| e.last_used).max().unwrap_or(0);
+    Self { entries, dir, max_size, counter, dirty: false }
+  }
+
+  fn save(&mut self) {
+    self.dirty = false;
+    if let Some(dir) = &self.dir {
+      let contents: Vec<String> = self.entries.iter().map(|(url, entry)| entry.serialise(url)).collect();
+      let _ = create_dir_all(dir);
+      let _ = write(dir.join("index"), contents.join("\n"));
+    }
+  }
+
+  //not saved until the next change that is, or the cache is dropped, since every lookup does this
+  fn touch(&mut self, url: &str) {
+    self.counter += 1;
+    if let Some(entry) = self.entries.get_mut(url) {
+      entry.last_used = self.counter;
+      self.dirty = true;
+    }
+  }
+
+  //the entry and body for url, if there is one. it may be stale
+  pub fn lookup(&mut self, url: &str) -> Option<(CacheEntry, String)> {
+    let url = key(url);
+    let dir = self.dir.as_ref()?;
+    self.entries.get(url)?;
+    if let Ok(body) = read_to_string(dir.join(file_name(url))) {
+      self.touch(url);
+      Some((self.entries[url].clone(), body))
+    } else {
+      //body file went missing
+      self.remove(url);
+      None
+    }
+  }
+
+  //store a 200 response to a GET of url, if its headers allow it
+  pub fn store(&mut self, url: &str, final_url: &str, headers: &HeaderMap, body: &str, now: u64) {
+    let url = key(url);
+    let Some(dir) = self.dir.clone() else {
+      return;
+    };
+    let size = body.len() as u64;
+    let Some(fresh_until) = fresh_until(headers, now).filter(|_| size <= self.max_size) else {
+      self.remove(url);
+      return;
+    };
+    if create_dir_all(&dir).is_err() || write(dir.join(file_name(url)), body).is_err() {
+      return;
+    }
+    self.entries.insert(url.to_string(), CacheEntry {
+      final_url: final_url.to_string(),
+      fresh_until,
+      etag: header(headers, "ETag").map(|e| e.to_string()),
+      last_modified: header(headers, "Last-Modified").map(|l| l.to_string()),
+      content_type: header(headers, "Content-Type").map(|c| c.to_string()),
+      last_used: 0,
+      size,
+    });
+    self.touch(url);
+    self.evict();
+    self.save();
+  }
+
+  //the server said (304) the stored response is still good. returns it, with freshness updated from the new headers
+  pub fn revalidated(&mut self, url: &str, headers: &HeaderMap, now: u64) -> Option<(CacheEntry, String)> {
+    let url = key(url);
+    let (mut entry, body) = self.lookup(url)?;
+    if let Some(fresh_until) = fresh_until(headers, now) {
+      entry.fresh_until = fresh_until;
+      if let Some(etag) = header(headers, "ETag") {
+        entry.etag = Some(etag.to_string());
+      }
+      if let Some(last_modified) = header(headers, "Last-Modified") {
+        entry.last_modified = Some(last_modified.to_string());
+      }
+      self.entries.insert(url.to_string(), entry.clone());
+      self.save();
+    } else {
+      self.remove(url);
+    }
+    Some((entry, body))
+  }
+
+  pub fn remove(&mut self, url: &str) {
+    let url = key(url);
+    if self.entries.remove(url).is_some() {
+      if let Some(dir) = &self.dir {
+        let _ = remove_file(dir.join(file_name(url)));
+      }
+      self.save();
+    }
+  }
+
+  //least recently used go first, until under the size cap
+  fn evict(&mut self) {
+    let mut total: u64 = self.entries.values().map(|e| e.size).sum();
+    while total > self.max_size {
+      let Some(url) = self.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(url, _)| url.clone()) else {
+        break;
+      };
+      total -= self.entries[&url].size;
+      self.entries.remove(&url);
+      if let Some(dir) = &self.dir {
+        let _ = remove_file(dir.join(file_name(&url)));
+      }
+    }
+  }
+}
+
+impl Drop for HttpCache {
+  fn drop(&mut self) {
+    if self.dirty {
+      self.save();
+    }
+  }
+}
+
+#[cfg(test)]
+fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+  let mut headers = HeaderMap::new();
+  for (name, value) in pairs {
+    headers.append(*name, value.parse().unwrap());
+  }
+  headers
+}
+
+#[test]
+fn test_fresh_until() {
+  let now = 1445412480; //Wed, 21 Oct 2015 07:28:00 GMT
+  assert_eq!(fresh_until(&headers(&[("Cache-Control", "public, max-age=60")]), now), Some(now + 60));
+  assert_eq!(fresh_until(&headers(&[("Cache-Control", "max-age=\"60\""), ("Age", "20")]), now), Some(now + 40));
+  //max-age wins over expires
+  assert_eq!(fresh_until(&headers(&[("Cache-Control", "max-age=60"), ("Expires", "Wed, 21 Oct 2015 08:28:00 GMT")]), now), Some(now + 60));
+  assert_eq!(fresh_until(&headers(&[("Date", "Wed, 21 Oct 2015 07:28:00 GMT"), ("Expires", "Wed, 21 Oct 2015 08:28:00 GMT")]), now), Some(now + 3600));
+  //heuristic, a tenth of the time since it was modified
+  assert_eq!(fresh_until(&headers(&[("Last-Modified", "Wed, 21 Oct 2015 06:28:00 GMT")]), now), Some(now + 360));
+  //stale, or no-cache, is only kept if it can be revalidated
+  assert_eq!(fresh_until(&headers(&[("Expires", "0")]), now), None);
+  assert_eq!(fresh_until(&headers(&[("Expires", "0"), ("ETag", "\"abc\"")]), now), Some(0));
+  assert_eq!(fresh_until(&headers(&[("Cache-Control", "private, max-age=0, must-revalidate"), ("Last-Modified", "Wed, 21 Oct 2015 06:28:00 GMT")]), now), Some(0));
+  assert_eq!(fresh_until(&headers(&[("Cache-Control", "no-cache"), ("Cache-Control", "max-age=60")]), now), None);
+  assert_eq!(fresh_until(&headers(&[("Cache-Control", "no-store, max-age=60")]), now), None);
+  assert_eq!(fresh_until(&headers(&[]), now), None);
+}
+
+#[test]
+fn test_http_cache() {
+  let dir = std::env::temp_dir().join(format!("koxinga-cache-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  let mut cache = HttpCache::in_dir(Some(dir.clone()), 10);
+  let fresh = headers(&[("Cache-Control", "max-age=60")]);
+  cache.store("https://a.com/", "https://a.com/", &fresh, "aaaa", 1000);
+  cache.store("https://b.com/", "https://b.com/b", &headers(&[("ETag", "\"b\""), ("Content-Type", "text/plain")]), "bbbb", 1000);
+  //the fragment doesn't matter
+  let (entry, body) = cache.lookup("https://a.com/#top").unwrap();
+  assert_eq!(body, "aaaa");
+  assert!(entry.is_fresh(1059) && !entry.is_fresh(1060));
+  //persisted
+  let mut cache = HttpCache::in_dir(Some(dir.clone()), 10);
+  let (entry, body) = cache.lookup("https://b.com/").unwrap();
+  assert_eq!((body.as_str(), entry.final_url.as_str(), entry.etag.as_deref(), entry.content_type.as_deref()), ("bbbb", "https://b.com/b", Some("\"b\""), Some("text/plain")));
+  assert!(!entry.is_fresh(1000));
+  //304 with new freshness
+  let (entry, body) = cache.revalidated("https://b.com/", &fresh, 2000).unwrap();
+  assert_eq!((body.as_str(), entry.fresh_until, entry.etag.as_deref()), ("bbbb", 2060, Some("\"b\"")));
+  //a.com was used least recently, so it goes to make room
+  cache.store("https://c.com/", "https://c.com/", &fresh, "cccc", 1000);
+  assert!(cache.lookup("https://a.com/").is_none());
+  assert!(cache.lookup("https://b.com/").is_some());
+  assert!(cache.lookup("https://c.com/").is_some());
+  //uncacheable responses replace what was there
+  cache.store("https://c.com/", "https://c.com/", &headers(&[("Cache-Control", "no-store")]), "new", 1000);
+  assert!(cache.lookup("https://c.com/").is_none());
+  //uses are saved when it goes, so b.com is still more recently used than c.com
+  cache.store("https://d.com/", "https://d.com/", &fresh, "dddd", 1000);
+  cache.lookup("https://b.com/");
+  drop(cache);
+  let mut cache = HttpCache::in_dir(Some(dir.clone()), 10);
+  cache.store("https://e.com/", "https://e.com/", &fresh, "eeee", 1000);
+  assert!(cache.lookup("https://b.com/").is_some() && cache.lookup("https://d.com/").is_none());
+  let _ = std::fs::remove_dir_all(&dir);
+}
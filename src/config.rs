@@ -0,0 +1,90 @@
+//! Settings, read from `koxinga/config` in the config directory (`$XDG_CONFIG_HOME`, or `~/.config`).
+//!
+//! One `name = value` per line, and lines starting with `#` are comments. Anything not set keeps its default. Paths can start with `~/`.
+//!
+//! ```text
+//! # where downloads are saved
+//! download_dir = ~/Downloads
+//! # where pages are saved
+//! save_dir = ~/Documents/pages
+//! ```
+
+use std::env;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use crate::dirs::{ config_dir, download_dir };
+
+/// The settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+  /// Where downloads are saved (`download_dir`). Defaults to `$XDG_DOWNLOAD_DIR`, or else `~/Downloads`.
+  pub download_dir: PathBuf,
+  /// Where saved pages go (`save_dir`). Defaults to the same as `download_dir`.
+  pub save_dir: PathBuf,
+}
+
+impl std::default::Default for Config {
+  fn default() -> Self {
+    let download_dir = download_dir().unwrap_or(PathBuf::from("."));
+    Self {
+      save_dir: download_dir.clone(),
+      download_dir,
+    }
+  }
+}
+
+//~/ is the home dir
+fn expand_home(path: &str) -> PathBuf {
+  match (path.strip_prefix("~/"), env::var("HOME")) {
+    (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+    _ => PathBuf::from(path),
+  }
+}
+
+impl Config {
+  /// Read the config file. If there isn't one, everything is the default.
+  pub fn load() -> Self {
+    let file = config_dir().map(|mut c| {
+      c.push("koxinga");
+      c.push("config");
+      c
+    });
+    file.and_then(|f| read_to_string(f).ok()).map_or(Config::default(), |contents| Config::parse(&contents))
+  }
+
+  /// Settings from the contents of a config file. Unknown names are ignored.
+  pub fn parse(contents: &str) -> Self {
+    let mut config = Config::default();
+    let mut save_dir = None;
+    for line in contents.lines() {
+      let line = line.trim();
+      if line.starts_with('#') {
+        continue;
+      }
+      let Some((name, value)) = line.split_once('=') else {
+        continue;
+      };
+      let value = value.trim();
+      match name.trim() {
+        "download_dir" if !value.is_empty() => config.download_dir = expand_home(value),
+        "save_dir" if !value.is_empty() => save_dir = Some(expand_home(value)),
+        _ => {},
+      }
+    }
+    config.save_dir = save_dir.unwrap_or(config.download_dir.clone());
+    config
+  }
+}
+
+#[test]
+fn test_config() {
+  let config = Config::parse("# comment\ndownload_dir = /tmp/koxinga downloads\nunknown = 1\nnonsense\n");
+  assert_eq!(config.download_dir, PathBuf::from("/tmp/koxinga downloads"));
+  assert_eq!(config.save_dir, config.download_dir);
+  assert_eq!(Config::parse("save_dir = /tmp/pages").save_dir, PathBuf::from("/tmp/pages"));
+  assert_eq!(Config::parse("download_dir =\n"), Config::default());
+  if let Ok(home) = env::var("HOME") {
+    assert_eq!(Config::parse("download_dir=~/dl").download_dir, PathBuf::from(home).join("dl"));
+  }
+}
@@ -0,0 +1,341 @@
+use std::vec::Vec;
+use std::fs::{ create_dir_all, read_to_string, write };
+use std::path::PathBuf;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::dirs::config_dir;
+
+use crate::url::Url;
+
+//mostly following rfc 6265 section 5 (the user agent side)
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+  pub name: String,
+  pub value: String,
+  pub domain: String, //lowercase, no leading dot
+  pub host_only: bool, //no Domain attribute, so only sent to the exact host
+  pub path: String,
+  pub expires: Option<u64>, //unix seconds. None means a session cookie, gone when the browser closes
+  pub secure: bool,
+  pub http_only: bool, //we don't run scripts, so this doesn't change anything, but keep it anyways
+  pub creation: u64,
+}
+
+impl Cookie {
+  fn is_expired(&self, now: u64) -> bool {
+    self.expires.is_some_and(|e| e <= now)
+  }
+
+  //one line, tab separated, for the cookies file
+  fn serialise(&self) -> String {
+    format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", self.domain, self.host_only, self.path, self.secure, self.http_only, self.expires.unwrap_or(0), self.creation, self.name, self.value)
+  }
+
+  fn deserialise(line: &str) -> Option<Self> {
+    let mut parts = line.splitn(9, '\t');
+    Some(Self {
+      domain: parts.next()?.to_string(),
+      host_only: parts.next()? == "true",
+      path: parts.next()?.to_string(),
+      secure: parts.next()? == "true",
+      http_only: parts.next()? == "true",
+      expires: Some(parts.next()?.parse().ok()?),
+      creation: parts.next()?.parse().ok()?,
+      name: parts.next()?.to_string(),
+      value: parts.next()?.to_string(),
+    })
+  }
+}
+
+pub fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+//days since 1970-01-01 (howard hinnant's days_from_civil)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = if year >= 0 { year } else { year - 399 } / 400;
+  let yoe = year - era * 400;
+  let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+//leading digits of a token, if there are between min and max of them (and the token does not continue with more digits)
+fn leading_digits(token: &str, min: usize, max: usize) -> Option<u64> {
+  let digits: String = token.chars().take_while(|c| c.is_ascii_digit()).collect();
+  if digits.len() >= min && digits.len() <= max {
+    digits.parse().ok()
+  } else {
+    None
+  }
+}
+
+//rfc 6265 5.1.1, which is very forgiving. also used for http dates (Expires, Date, etc)
+pub fn parse_cookie_date(s: &str) -> Option<u64> {
+  let is_delimiter = |c: char| c == '\x09' || (' '..='/').contains(&c) || (';'..='@').contains(&c) || ('['..='`').contains(&c) || ('{'..='~').contains(&c);
+  let mut time = None;
+  let mut day = None;
+  let mut month = None;
+  let mut year = None;
+  for token in s.split(is_delimiter).filter(|t| !t.is_empty()) {
+    if time.is_none() {
+      let mut parts = token.splitn(3, ':');
+      if let (Some(h), Some(m), Some(sec)) = (parts.next(), parts.next(), parts.next()) {
+        //hours and minutes must be only digits, seconds may be followed by junk
+        let only_digits = h.chars().chain(m.chars()).all(|c| c.is_ascii_digit());
+        if let (true, Some(h), Some(m), Some(sec)) = (only_digits, leading_digits(h, 1, 2), leading_digits(m, 1, 2), leading_digits(sec, 1, 2)) {
+          time = Some((h, m, sec));
+          continue;
+        }
+      }
+    }
+    if day.is_none() {
+      if let Some(d) = leading_digits(token, 1, 2) {
+        day = Some(d);
+        continue;
+      }
+    }
+    if month.is_none() {
+      let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+      if let Some(m) = months.iter().position(|m| token.get(..3).is_some_and(|t| t.eq_ignore_ascii_case(m))) {
+        month = Some(m as u64 + 1);
+        continue;
+      }
+    }
+    if year.is_none() {
+      if let Some(y) = leading_digits(token, 2, 4) {
+        year = Some(y);
+        continue;
+      }
+    }
+  }
+  let (hour, minute, second) = time?;
+  let day = day?;
+  let month = month?;
+  let mut year = year?;
+  if (70..=99).contains(&year) {
+    year += 1900;
+  } else if year <= 69 {
+    year += 2000;
+  }
+  if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+    return None;
+  }
+  let days = days_from_civil(year as i64, month as i64, day as i64);
+  let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+  //before 1970 is just as expired as 1970
+  Some(secs.max(0) as u64)
+}
+
+//rfc 6265 5.1.3
+fn domain_match(host: &str, domain: &str) -> bool {
+  host == domain || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.') && host.parse::<std::net::IpAddr>().is_err())
+}
+
+//rfc 6265 5.1.4
+fn default_path(url: &Url) -> String {
+  if !url.path.starts_with('/') {
+    return "/".to_string();
+  }
+  match url.path.rfind('/') {
+    Some(0) | None => "/".to_string(),
+    Some(i) => url.path[..i].to_string(),
+  }
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+  let request_path = if request_path.is_empty() { "/" } else { request_path };
+  request_path == cookie_path || (request_path.starts_with(cookie_path) && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+//rfc 6265 5.2 and 5.3. None if the cookie should be ignored
+pub fn parse_set_cookie(header: &str, url: &Url, now: u64) -> Option<Cookie> {
+  let mut parts = header.split(';');
+  let (name, value) = parts.next()?.split_once('=')?;
+  let name = name.trim();
+  if name.is_empty() {
+    return None;
+  }
+  let host = url.host_str().to_lowercase();
+  let mut cookie = Cookie {
+    name: name.to_string(),
+    value: value.trim().to_string(),
+    domain: host.clone(),
+    host_only: true,
+    path: default_path(url),
+    expires: None,
+    secure: false,
+    http_only: false,
+    creation: now,
+  };
+  let mut max_age = None;
+  let mut expires = None;
+  for attribute in parts {
+    let (attr_name, attr_value) = attribute.split_once('=').unwrap_or((attribute, ""));
+    let attr_value = attr_value.trim();
+    match attr_name.trim().to_lowercase().as_str() {
+      "expires" => {
+        if let Some(e) = parse_cookie_date(attr_value) {
+          expires = Some(e);
+        }
+      },
+      "max-age" => {
+        //must be an optional - followed by digits, otherwise ignored
+        let digits = attr_value.strip_prefix('-').unwrap_or(attr_value);
+        if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+          if let Ok(secs) = attr_value.parse::<i64>() {
+            //zero or negative means expire right away
+            max_age = Some(if secs <= 0 { 0 } else { now.saturating_add(secs as u64) });
+          }
+        }
+      },
+      "domain" => {
+        let domain = attr_value.strip_prefix('.').unwrap_or(attr_value).to_lowercase();
+        if !domain.is_empty() {
+          //no public suffix list, but at least don't let sites set cookies for a whole tld
+          if !domain_match(&host, &domain) || (!domain.contains('.') && domain != host) {
+            return None;
+          }
+          cookie.domain = domain;
+          cookie.host_only = false;
+        }
+      },
+      "path" if attr_value.starts_with('/') => cookie.path = attr_value.to_string(),
+      "secure" => cookie.secure = true,
+      "httponly" => cookie.http_only = true,
+      _ => {},
+    }
+  }
+  //max-age wins over expires
+  cookie.expires = max_age.or(expires);
+  Some(cookie)
+}
+
+#[derive(Default)]
+pub struct CookieJar {
+  cookies: Vec<Cookie>,
+  file: Option<PathBuf>, //where persistent cookies are saved, None to only keep them in memory
+}
+
+impl CookieJar {
+  //read saved cookies from the config dir
+  pub fn load() -> Self {
+    let file = config_dir().map(|mut c| {
+      c.push("koxinga");
+      c.push("cookies");
+      c
+    });
+    let now = now();
+    let cookies = file.as_ref().and_then(|f| read_to_string(f).ok()).map_or(Vec::new(), |contents| {
+      contents.lines().filter_map(Cookie::deserialise).filter(|c| !c.is_expired(now)).collect()
+    });
+    Self { cookies, file }
+  }
+
+  //handle a Set-Cookie header from a response to url
+  pub fn set_cookie(&mut self, header: &str, url: &Url) {
+    let now = now();
+    if let Some(mut cookie) = parse_set_cookie(header, url, now) {
+      if let Some(index) = self.cookies.iter().position(|c| c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path) {
+        if cookie.is_expired(now) {
+          self.cookies.remove(index);
+        } else {
+          //replaced cookies keep their original creation time (and place)
+          cookie.creation = self.cookies[index].creation;
+          self.cookies[index] = cookie;
+        }
+      } else if !cookie.is_expired(now) {
+        self.cookies.push(cookie);
+      }
+    }
+  }
+
+  //value for the Cookie request header, if any cookies should be sent to url
+  pub fn cookie_header(&self, url: &Url) -> Option<String> {
+    let now = now();
+    let host = url.host_str().to_lowercase();
+    let https = url.scheme.as_deref() == Some("https");
+    let mut matching: Vec<&Cookie> = self.cookies.iter().filter(|c| {
+      !c.is_expired(now) && (!c.secure || https) && path_match(&url.path, &c.path) && if c.host_only {
+        host == c.domain
+      } else {
+        domain_match(&host, &c.domain)
+      }
+    }).collect();
+    //longer paths first, then older first. sort is stable, so insertion order breaks ties
+    matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.creation.cmp(&b.creation)));
+    if matching.is_empty() {
+      None
+    } else {
+      Some(matching.iter().map(|c| format!("{}={}", c.name, c.value)).collect::<Vec<String>>().join("; "))
+    }
+  }
+
+  //write persistent cookies to disk. session cookies are not saved, as per the rfc
+  pub fn save(&self) {
+    if let Some(file) = &self.file {
+      let now = now();
+      let contents: Vec<String> = self.cookies.iter().filter(|c| c.expires.is_some() && !c.is_expired(now)).map(|c| c.serialise()).collect();
+      if let Some(parent) = file.parent() {
+        let _ = create_dir_all(parent);
+      }
+      let _ = write(file, contents.join("\n"));
+    }
+  }
+}
+
+#[test]
+fn test_cookie_date() {
+  assert_eq!(parse_cookie_date("Wed, 21 Oct 2015 07:28:00 GMT"), Some(1445412480));
+  assert_eq!(parse_cookie_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+  assert_eq!(parse_cookie_date("Thu Jan  1 00:00:01 1970"), Some(1));
+  assert_eq!(parse_cookie_date("Fri, 31 Dec 9999 23:59:59 GMT"), Some(253402300799));
+  assert_eq!(parse_cookie_date("not a date"), None);
+  assert_eq!(parse_cookie_date("Wed, 32 Oct 2015 07:28:00 GMT"), None);
+}
+
+#[test]
+fn test_set_cookie_attributes() {
+  let url = Url::new("https://news.ycombinator.com/login?goto=news".to_string());
+  let cookie = parse_set_cookie("user=stjet&abc; Domain=.YCombinator.com; Path=/; Max-Age=100; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Secure; HttpOnly", &url, 1000).unwrap();
+  assert_eq!(cookie.name, "user");
+  assert_eq!(cookie.value, "stjet&abc");
+  assert_eq!(cookie.domain, "ycombinator.com");
+  assert!(!cookie.host_only);
+  assert_eq!(cookie.path, "/");
+  assert_eq!(cookie.expires, Some(1100)); //max-age wins
+  assert!(cookie.secure && cookie.http_only);
+  //default path, host only
+  let cookie = parse_set_cookie("a=b", &url, 1000).unwrap();
+  assert!(cookie.host_only);
+  assert_eq!(cookie.domain, "news.ycombinator.com");
+  assert_eq!(cookie.path, "/");
+  let cookie = parse_set_cookie("a=b", &Url::new("https://x.com/a/b/c".to_string()), 1000).unwrap();
+  assert_eq!(cookie.path, "/a/b");
+  //other site, or a whole tld
+  assert!(parse_set_cookie("a=b; Domain=example.com", &url, 1000).is_none());
+  assert!(parse_set_cookie("a=b; Domain=com", &url, 1000).is_none());
+  assert!(parse_set_cookie("=b", &url, 1000).is_none());
+}
+
+#[test]
+fn test_cookie_jar_matching() {
+  let mut jar: CookieJar = Default::default();
+  let url = Url::new("https://en.wikipedia.org/wiki/Koxinga".to_string());
+  jar.set_cookie("all=1; Domain=wikipedia.org; Path=/", &url);
+  jar.set_cookie("wiki=2; Path=/wiki", &url);
+  jar.set_cookie("secret=3; Secure", &url);
+  jar.set_cookie("gone=4; Max-Age=0", &url);
+  //secret gets the default path, /wiki
+  assert_eq!(jar.cookie_header(&url).unwrap(), "wiki=2; secret=3; all=1");
+  assert_eq!(jar.cookie_header(&Url::new("http://de.wikipedia.org/w/index.php".to_string())).unwrap(), "all=1");
+  assert!(jar.cookie_header(&Url::new("https://en.wikipedia.org.evil.com/wiki".to_string())).is_none());
+  //wiki path shouldn't match wikis
+  assert_eq!(jar.cookie_header(&Url::new("https://en.wikipedia.org/wikis".to_string())).unwrap(), "all=1");
+  //replace, and delete
+  jar.set_cookie("wiki=5; Path=/wiki", &url);
+  jar.set_cookie("all=1; Domain=wikipedia.org; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT", &url);
+  assert_eq!(jar.cookie_header(&url).unwrap(), "wiki=5; secret=3");
+}
@@ -0,0 +1,58 @@
+use std::env;
+use std::path::{ Path, PathBuf };
+
+//same as ming-wm's, so the library doesn't need it
+
+pub fn config_dir() -> Option<PathBuf> {
+  //$XDG_CONFIG_HOME or $HOME/.config
+  if let Ok(config_home) = env::var("XDG_CONFIG_HOME") {
+    Some(PathBuf::from(config_home))
+  } else {
+    env::var("HOME").ok().map(|home| {
+      let mut config_home = PathBuf::from(home);
+      config_home.push(".config");
+      config_home
+    })
+  }
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+  //$XDG_CACHE_HOME or $HOME/.cache
+  if let Ok(cache_home) = env::var("XDG_CACHE_HOME") {
+    Some(PathBuf::from(cache_home))
+  } else {
+    env::var("HOME").ok().map(|home| {
+      let mut cache_home = PathBuf::from(home);
+      cache_home.push(".cache");
+      cache_home
+    })
+  }
+}
+
+pub fn download_dir() -> Option<PathBuf> {
+  //$XDG_DOWNLOAD_DIR or $HOME/Downloads
+  if let Ok(download_dir) = env::var("XDG_DOWNLOAD_DIR") {
+    Some(PathBuf::from(download_dir))
+  } else {
+    env::var("HOME").ok().map(|home| {
+      let mut download_dir = PathBuf::from(home);
+      download_dir.push("Downloads");
+      download_dir
+    })
+  }
+}
+
+//dir/name, or if that exists, dir/name (1), dir/name (2), etc (before the extension)
+pub fn unused_path(dir: &Path, name: &str) -> PathBuf {
+  let (stem, extension) = match name.rsplit_once('.') {
+    Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+    _ => (name, String::new()),
+  };
+  let mut path = dir.join(name);
+  let mut n = 1;
+  while path.exists() {
+    path = dir.join(format!("{} ({}){}", stem, n, extension));
+    n += 1;
+  }
+  path
+}
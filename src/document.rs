@@ -0,0 +1,152 @@
+//! A loaded page, in whatever format it came in.
+
+use std::collections::HashSet;
+
+use crate::gemini::gemtext_outputs;
+use crate::gopher::menu_outputs;
+use crate::json::{ json_outputs, parse_json, Json, FOLD_PREFIX };
+use crate::layout::page_outputs;
+use crate::markdown::markdown_outputs;
+use crate::xml::{ parse, Node, OutputType };
+
+/// A loaded page, ready to be turned into [`OutputType`]s for [`crate::layout::layout`].
+#[derive(Debug)]
+pub enum Document {
+  /// Html.
+  Html {
+    /// The parsed page.
+    nodes: Vec<Box<Node>>,
+    /// The html it was parsed from.
+    source: String,
+  },
+  /// Gemtext (text/gemini).
+  Gemtext(String),
+  /// Plain text, shown preformatted (but wrapped by default, see [`Document::wraps`]).
+  Plain(String),
+  /// A gopher menu (application/gopher-menu).
+  GopherMenu(String),
+  /// Json, pretty printed. Objects and arrays whose number is in `collapsed` are folded (see [`crate::json`]).
+  Json {
+    /// The parsed json.
+    value: Json,
+    /// Numbers of the folded objects and arrays.
+    collapsed: HashSet<usize>,
+    /// The json it was parsed from.
+    source: String,
+  },
+  /// Markdown (text/markdown).
+  Markdown(String),
+  /// Something that isn't text, so can only be downloaded, with its mime type.
+  Binary(String),
+}
+
+/// Whether a mime type (as in a Content-Type header) is something that can be shown as text, rather than downloaded.
+pub fn is_text(mime: &str) -> bool {
+  let essence = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+  essence.starts_with("text/") || essence.ends_with("+json") || essence.ends_with("+xml") || ["application/json", "application/xml", "application/javascript", "application/gopher-menu"].contains(&essence.as_str())
+}
+
+impl std::default::Default for Document {
+  fn default() -> Self {
+    Document::Html { nodes: Vec::new(), source: String::new() }
+  }
+}
+
+impl Document {
+  /// Parse `text` according to its mime type (as in a Content-Type header, parameters and all). Json that doesn't parse is shown as plain text. Anything that isn't text (see [`is_text`]) is [`Document::Binary`], and anything unknown is treated as html.
+  pub fn new(mime: &str, text: &str) -> Self {
+    let essence = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    match essence.as_str() {
+      "text/gemini" => Document::Gemtext(text.to_string()),
+      "text/plain" => Document::Plain(text.to_string()),
+      "text/markdown" | "text/x-markdown" => Document::Markdown(text.to_string()),
+      "application/gopher-menu" => Document::GopherMenu(text.to_string()),
+      "application/json" => Document::json(text),
+      _ if essence.ends_with("+json") => Document::json(text),
+      _ if !essence.is_empty() && !is_text(&essence) => Document::Binary(essence),
+      _ => Document::Html { nodes: parse(text), source: text.to_string() },
+    }
+  }
+
+  fn json(text: &str) -> Self {
+    match parse_json(text) {
+      Ok(value) => Document::Json { value, collapsed: HashSet::new(), source: text.to_string() },
+      Err(_) => Document::Plain(text.to_string()),
+    }
+  }
+
+  /// Follow a same page link with this fragment, if it is one the document handles itself (folding and unfolding json). Returns false if it isn't, so it's just an anchor to scroll to.
+  pub fn toggle(&mut self, fragment: &str) -> bool {
+    if let Document::Json { collapsed, .. } = self {
+      if let Some(id) = fragment.strip_prefix(FOLD_PREFIX).and_then(|id| id.parse().ok()) {
+        if !collapsed.remove(&id) {
+          collapsed.insert(id);
+        }
+        return true;
+      }
+    }
+    false
+  }
+
+  /// The text the page was made from, as it came. Nothing for binary files.
+  pub fn source(&self) -> &str {
+    match self {
+      Document::Html { source, .. } | Document::Json { source, .. } => source,
+      Document::Gemtext(text) | Document::Plain(text) | Document::GopherMenu(text) | Document::Markdown(text) => text,
+      Document::Binary(_) => "",
+    }
+  }
+
+  /// Whether the page should be laid out wrapping preformatted text to the width too, unless asked otherwise (see [`crate::layout::layout`]). Only plain text is, since it is mostly prose, where other pages keep preformatted text for code and the like.
+  pub fn wraps(&self) -> bool {
+    matches!(self, Document::Plain(_))
+  }
+
+  /// The usual file extension of the source, eg: "html".
+  pub fn extension(&self) -> &'static str {
+    match self {
+      Document::Html { .. } => "html",
+      Document::Gemtext(_) => "gmi",
+      Document::Plain(_) | Document::GopherMenu(_) | Document::Binary(_) => "txt",
+      Document::Json { .. } => "json",
+      Document::Markdown(_) => "md",
+    }
+  }
+
+  /// The title (if there is one) and outputs of the page.
+  pub fn outputs(&self) -> (Option<String>, Vec<OutputType>) {
+    match self {
+      Document::Html { nodes, .. } => page_outputs(nodes),
+      Document::Gemtext(text) => gemtext_outputs(text),
+      Document::Plain(text) => {
+        let mut outputs = vec![OutputType::Preformatted(true)];
+        for line in text.lines() {
+          if !line.is_empty() {
+            outputs.push(OutputType::Text(line.to_string()));
+          }
+          outputs.push(OutputType::Newline);
+        }
+        outputs.push(OutputType::Preformatted(false));
+        (None, outputs)
+      },
+      Document::GopherMenu(text) => (None, menu_outputs(text)),
+      Document::Json { value, collapsed, .. } => (None, json_outputs(value, collapsed)),
+      Document::Markdown(text) => markdown_outputs(text),
+      Document::Binary(mime) => (Some("Can't show this page".to_string()), vec![OutputType::Text(format!("This is {}, which can't be shown.", mime)), OutputType::Newline]),
+    }
+  }
+}
+
+#[test]
+fn test_document_new() {
+  assert!(matches!(Document::new("text/markdown; charset=utf-8", "# Hi"), Document::Markdown(_)));
+  assert!(matches!(Document::new("application/ld+json", "{}"), Document::Json { .. }));
+  assert!(matches!(Document::new("application/json", "{oops"), Document::Plain(_)));
+  assert!(matches!(Document::new("image/png", ""), Document::Binary(m) if m == "image/png"));
+  assert!(matches!(Document::new("application/xhtml+xml", "<p>hi</p>"), Document::Html { .. }));
+  assert!(matches!(Document::new("", "<p>hi</p>"), Document::Html { .. }));
+  assert_eq!(Document::new("text/html", "<p>hi</p>").source(), "<p>hi</p>");
+  let mut document = Document::new("application/json", "[[1]]");
+  assert!(document.toggle("fold-1") && !document.toggle("top"));
+  assert!(document.outputs().1.contains(&OutputType::Text("[+]".to_string())));
+}
@@ -0,0 +1,131 @@
+//! Downloading files in the background, with their progress available to show (and cancel) while they go.
+
+use std::path::PathBuf;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+
+use crate::http::{ BodyStream, HttpError };
+use crate::url::Url;
+
+/// Where a download is at.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DownloadState {
+  /// Still going.
+  Downloading,
+  /// Saved in full.
+  Finished,
+  /// Something went wrong, and what was saved so far was deleted.
+  Failed(String),
+  /// Cancelled, and what was saved so far was deleted.
+  Cancelled,
+}
+
+/// A snapshot of a download.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadStatus {
+  /// What is being downloaded.
+  pub url: Url,
+  /// Where it is being saved, once known.
+  pub path: Option<PathBuf>,
+  /// Bytes received so far.
+  pub received: u64,
+  /// Total size, if the server said.
+  pub size: Option<u64>,
+  /// Where it is at.
+  pub state: DownloadState,
+}
+
+//the status is updated by the download's thread
+struct Download {
+  status: Arc<Mutex<DownloadStatus>>,
+  cancel: Arc<AtomicBool>,
+}
+
+/// Downloads, each on a thread of its own. Numbered in the order they were started.
+#[derive(Default)]
+pub struct Downloads {
+  downloads: Vec<Download>,
+}
+
+impl Downloads {
+  /// Start saving the body of the response from url into dir, in the background (see [`BodyStream::save`]). Returns its number.
+  pub fn start(&mut self, url: Url, stream: BodyStream, dir: PathBuf) -> usize {
+    let status = Arc::new(Mutex::new(DownloadStatus { url, path: None, received: 0, size: stream.size, state: DownloadState::Downloading }));
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (thread_status, thread_cancel) = (status.clone(), cancel.clone());
+    thread::spawn(move || {
+      let result = stream.save(&dir, |path, received, size| {
+        let mut status = thread_status.lock().unwrap();
+        if status.path.is_none() {
+          status.path = Some(path.to_path_buf());
+        }
+        status.received = received;
+        status.size = size;
+        !thread_cancel.load(Ordering::Relaxed)
+      });
+      thread_status.lock().unwrap().state = match result {
+        Ok(_) => DownloadState::Finished,
+        Err(HttpError::Cancelled) => DownloadState::Cancelled,
+        Err(e) => DownloadState::Failed(e.to_string()),
+      };
+    });
+    self.downloads.push(Download { status, cancel });
+    self.downloads.len() - 1
+  }
+
+  /// Cancel download number `index`. Returns false if there is no such download, or it isn't going anymore.
+  pub fn cancel(&self, index: usize) -> bool {
+    if let Some(download) = self.downloads.get(index) {
+      if download.status.lock().unwrap().state == DownloadState::Downloading {
+        download.cancel.store(true, Ordering::Relaxed);
+        return true;
+      }
+    }
+    false
+  }
+
+  /// How each download is going, in order.
+  pub fn statuses(&self) -> Vec<DownloadStatus> {
+    self.downloads.iter().map(|d| d.status.lock().unwrap().clone()).collect()
+  }
+}
+
+/// A number of bytes, in whichever of B, KB, MB or GB is most readable.
+pub fn format_size(bytes: u64) -> String {
+  const UNITS: [&'static str; 3] = ["KB", "MB", "GB"];
+  if bytes < 1024 {
+    return format!("{} B", bytes);
+  }
+  let mut size = bytes as f64 / 1024.0;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[test]
+fn test_downloads() {
+  assert_eq!(format_size(1000), "1000 B");
+  assert_eq!(format_size(1536), "1.5 KB");
+  assert_eq!(format_size(3 * 1024 * 1024 * 1024 * 1024), "3072.0 GB");
+  //the connection closes before all of it came, so it fails
+  let client = crate::http::HttpClient::new(Default::default(), Default::default());
+  let url = crate::http::serve_once("HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\nContent-Length: 10\r\nConnection: close\r\n\r\nPK");
+  let resp = client.get(&url).unwrap();
+  let dir = std::env::temp_dir().join(format!("koxinga-downloads-test-{}", std::process::id()));
+  let mut downloads = Downloads::default();
+  let index = downloads.start(resp.url, resp.stream.unwrap(), dir.clone());
+  for _ in 0..100 {
+    if downloads.statuses()[index].state != DownloadState::Downloading {
+      break;
+    }
+    thread::sleep(std::time::Duration::from_millis(20));
+  }
+  assert!(matches!(downloads.statuses()[index].state, DownloadState::Failed(_)));
+  assert_eq!(downloads.statuses()[index].size, Some(10));
+  assert!(!downloads.cancel(index) && !downloads.cancel(index + 1));
+  let _ = std::fs::remove_dir_all(&dir);
+}
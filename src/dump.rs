@@ -0,0 +1,140 @@
+//! Headless rendering of pages as plain text, like `lynx -dump`.
+//!
+//! Uses the same [`crate::layout`] as the window, just in columns instead of pixels.
+
+use std::fs::canonicalize;
+use std::io::{ stdin, Read };
+use std::path::Path;
+
+use crate::document::{ is_text, Document };
+use crate::layout::{ layout, line_at_y, LINE_HEIGHT };
+use crate::encoding::decode;
+use crate::file::{ self, path_to_url };
+use crate::gemini::{ GeminiClient, GeminiResponse };
+use crate::gopher;
+use crate::http::HttpClient;
+use crate::url::Url;
+use crate::xml::{ remove_quotes, OutputType, Style };
+
+const COLUMN_WIDTH: usize = 13; //about the width of a char in the window, so Indent lines up the same
+
+/// Width used when none is given.
+pub const DEFAULT_COLUMNS: usize = 80;
+
+//east asian wide characters take two columns
+fn columns(c: char) -> usize {
+  match c as u32 {
+    0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x20000..=0x3FFFD => 2,
+    _ => 1,
+  }
+}
+
+/// Get the page to dump, and the url it came from (for resolving links). `source` is a url, a file path, or `-` for stdin.
+///
+/// Urls without a scheme are assumed to be https. Stdin is assumed to be html.
+pub fn load(source: &str) -> Result<(Document, Url), String> {
+  if source == "-" {
+    let mut bytes = Vec::new();
+    stdin().read_to_end(&mut bytes).map_err(|e| format!("could not read stdin: {}", e))?;
+    return Ok((Document::new("text/html", &decode(&bytes, None)), Url::new("about:stdin".to_string())));
+  }
+  let url = if Path::new(source).exists() {
+    path_to_url(&canonicalize(source).map_err(|e| format!("could not read {}: {}", source, e))?)
+  } else {
+    let url = Url::new(source.to_string());
+    if url.valid_scheme() {
+      url
+    } else {
+      Url::new(format!("https://{}", source))
+    }
+  };
+  if url.scheme.as_deref() == Some("file") {
+    let (mime, bytes) = file::get(&url).map_err(|e| format!("could not read {}: {}", url, e))?;
+    if !is_text(mime) {
+      return Err(format!("{} is not text ({})", url, mime));
+    }
+    Ok((Document::new(mime, &decode(&bytes, None)), url))
+  } else if url.scheme.as_deref() == Some("gemini") {
+    match GeminiClient::default().get(&url) {
+      Ok(GeminiResponse::Success { mime, body, url }) => Ok((Document::new(&mime, &decode(&body, Some(&mime))), url)),
+      Ok(GeminiResponse::Input { prompt, .. }) => Err(format!("{} asks for input ({}), add it as the query", url, prompt)),
+      Err(e) => Err(format!("could not fetch {}: {}", url, e)),
+    }
+  } else if url.scheme.as_deref() == Some("gopher") {
+    let (item_type, body) = gopher::get(&url).map_err(|e| format!("could not fetch {}: {}", url, e))?;
+    let mime = gopher::mime(item_type).ok_or(format!("{} is not text (item type {})", url, item_type))?;
+    let text = decode(&body, None);
+    let text = if item_type == '0' { gopher::text_file(&text) } else { text };
+    Ok((Document::new(mime, &text), url))
+  } else {
+    let resp = HttpClient::default().get(&url).map_err(|e| format!("could not fetch {}: {}", url, e))?;
+    match resp.content_type.as_deref() {
+      _ if resp.attachment => Err(format!("{} is an attachment, to be downloaded rather than shown", url)),
+      Some(mime) if !is_text(mime) => Err(format!("{} is not text ({})", url, mime)),
+      //no content type, so hopefully html
+      mime => Ok((Document::new(mime.unwrap_or("text/html"), &resp.body), resp.url)),
+    }
+  }
+}
+
+/// Render `document` as text, wrapped to `columns_max` columns (except for preformatted text and tables too wide to fit, unless the document [`Document::wraps`], like in the window by default). If `links`, links are numbered, and their urls (resolved against `url`) listed at the end.
+pub fn dump(document: &Document, url: &Url, columns_max: usize, links: bool) -> String {
+  render(document, url, 2 + columns_max * COLUMN_WIDTH, document.wraps(), |c, _| columns(c) * COLUMN_WIDTH, links)
+}
+
+/// Like [`dump`], but laid out in whatever units `measure` gives, to `width` of them, and wrapping preformatted text and wide tables or not (see [`layout`]), like the window does. Gaps (eg indents) become however many spaces fit in them. Styles are lost, text is text.
+pub fn render(document: &Document, url: &Url, width: usize, wrap: bool, mut measure: impl FnMut(char, Style) -> usize, links: bool) -> String {
+  let (_, outputs) = document.outputs();
+  let mut references = Vec::new();
+  let layout = layout(outputs, width, wrap, &mut measure, |_| LINE_HEIGHT, |o| match o {
+    OutputType::StartLink(href) if links => {
+      references.push(url.resolve(&remove_quotes(href.to_string())));
+      Some(format!("[{}]", references.len()))
+    },
+    OutputType::TextInput(name, value) => Some(format!("{}={}\n", name, value)),
+    OutputType::Form(_) => Some("[Submit Form]".to_string()),
+    _ => None,
+  });
+  //back from positions to lines of text
+  let space_width = measure(' ', Style::default()).max(1);
+  let mut lines = vec![(String::new(), 2); layout.line_count + 1]; //text, where it ends
+  for (x, y, text, _, style) in layout.page {
+    let (line, end) = &mut lines[line_at_y(&layout.line_ys, y)];
+    if x > *end {
+      *line += &" ".repeat((x - *end) / space_width);
+    }
+    *line += &text;
+    *end = x + text.chars().map(|c| measure(c, style)).sum::<usize>();
+  }
+  let mut lines: Vec<String> = lines.into_iter().map(|(line, _)| line).collect();
+  //blank lines at the end are just from the last block ending
+  while lines.len() > 1 && lines.last().is_some_and(|l| l.trim().is_empty()) {
+    lines.pop();
+  }
+  let mut rendered: String = lines.iter().map(|l| l.trim_end().to_string() + "\n").collect();
+  if links && !references.is_empty() {
+    rendered += "\nReferences\n\n";
+    for (i, reference) in references.iter().enumerate() {
+      rendered += &format!("{:>4}. {}\n", i + 1, reference);
+    }
+  }
+  rendered
+}
+
+#[test]
+fn test_dump() {
+  let url = Url::new("https://news.ycombinator.com/item?id=1".to_string());
+  let html = "<html><head><title>Hi</title></head><body><p>Some text, and <a href=\"user?id=koxinga\">a link</a> that wraps.</p><div indent=\"1\">鄭成功</div></body></html>";
+  let document = Document::new("text/html", html);
+  let dumped = dump(&document, &url, 20, true);
+  assert_eq!(dumped, "Some text, and [1]a\nlink that wraps.\n  鄭成功\n\nReferences\n\n   1. https://news.ycombinator.com/user?id=koxinga\n");
+  let dumped = dump(&document, &url, 20, false);
+  assert!(dumped.starts_with("Some text, and a\nlink that wraps.\n"));
+  assert!(!dumped.contains("References"));
+  let document = Document::new("text/gemini", "# Koxinga\n=> gemini://example.org/ Example\n");
+  assert_eq!(dump(&document, &url, 20, true), "Koxinga\n[1]Example\n\nReferences\n\n   1. gemini://example.org/\n");
+  //plain text keeps its spaces, but still wraps, unlike a pre
+  let text = "a  long  line  of  plain  text";
+  assert_eq!(dump(&Document::new("text/plain", text), &url, 20, false), "a  long  line  of\nplain  text\n");
+  assert_eq!(dump(&Document::new("text/html", &format!("<pre>{}</pre>", text)), &url, 20, false), format!("{}\n", text));
+}
@@ -0,0 +1,236 @@
+//! Working out what character encoding a page is in, and decoding it.
+//!
+//! Follows the html spec's encoding sniffing algorithm: byte order mark, then the `Content-Type` header, then a prescan for `<meta charset>`, and if all of those come up empty, a guess based on the bytes.
+
+use encoding_rs::{ Encoding, BIG5, EUC_JP, EUC_KR, GBK, SHIFT_JIS, UTF_8, WINDOWS_1252, X_USER_DEFINED };
+
+const PRESCAN_LENGTH: usize = 1024;
+
+//the guess only picks from these, in order of preference when tied
+const GUESSES: [&'static Encoding; 5] = [SHIFT_JIS, EUC_JP, EUC_KR, GBK, BIG5];
+
+//very frequent characters, simplified and traditional. wrong guesses tend to come out as rarer ones
+const COMMON_HANZI: &'static str = "的一是不了在人有我他这個个们們中来來上大为為和国國地到以说說时時要就出会會可也你对對生能而子那得于於着著下自之年过過发發后後作里裡用道行所然家种種事成方多经經么去法学學如都同现現当當没沒动動面起看定天分还還进進好小部其些主样樣理心她本前开開但因只从從想实實";
+
+fn is_whitespace(b: u8) -> bool {
+  b == b'\t' || b == b'\n' || b == b'\x0C' || b == b'\r' || b == b' '
+}
+
+//the charset parameter of a Content-Type header, or a content attribute with a Content-Type in it
+fn charset_param(value: &str) -> Option<&str> {
+  let lower = value.to_ascii_lowercase();
+  let mut search_from = 0;
+  while let Some(found) = lower[search_from..].find("charset") {
+    let mut rest = value[search_from + found + 7..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+    search_from += found + 7;
+    if let Some(after_equals) = rest.strip_prefix('=') {
+      rest = after_equals.trim_start_matches(|c: char| c.is_ascii_whitespace());
+      return if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        //unmatched quotes mean no charset
+        rest[1..].find(quote).map(|end| &rest[1..end + 1])
+      } else {
+        Some(rest.split(|c: char| c.is_ascii_whitespace() || c == ';').next().unwrap_or(""))
+      }.filter(|charset| !charset.is_empty());
+    }
+  }
+  None
+}
+
+//the html spec's "get an attribute", for the prescan. name and value are lowercased
+fn get_attribute(bytes: &[u8], pos: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+  while *pos < bytes.len() && (is_whitespace(bytes[*pos]) || bytes[*pos] == b'/') {
+    *pos += 1;
+  }
+  if *pos >= bytes.len() || bytes[*pos] == b'>' {
+    return None;
+  }
+  let mut name = Vec::new();
+  let mut value = Vec::new();
+  loop {
+    let b = *bytes.get(*pos)?;
+    if b == b'=' && !name.is_empty() {
+      *pos += 1;
+      break;
+    } else if is_whitespace(b) {
+      while *pos < bytes.len() && is_whitespace(bytes[*pos]) {
+        *pos += 1;
+      }
+      if bytes.get(*pos) != Some(&b'=') {
+        return Some((name, value));
+      }
+      *pos += 1;
+      break;
+    } else if b == b'/' || b == b'>' {
+      return Some((name, value));
+    }
+    name.push(b.to_ascii_lowercase());
+    *pos += 1;
+  }
+  while *pos < bytes.len() && is_whitespace(bytes[*pos]) {
+    *pos += 1;
+  }
+  let b = *bytes.get(*pos)?;
+  if b == b'"' || b == b'\'' {
+    *pos += 1;
+    while bytes.get(*pos) != Some(&b) {
+      value.push(bytes.get(*pos)?.to_ascii_lowercase());
+      *pos += 1;
+    }
+    *pos += 1;
+  } else if b != b'>' {
+    while *pos < bytes.len() && !is_whitespace(bytes[*pos]) && bytes[*pos] != b'>' {
+      value.push(bytes[*pos].to_ascii_lowercase());
+      *pos += 1;
+    }
+  }
+  Some((name, value))
+}
+
+//look for a <meta charset> or <meta http-equiv="content-type"> near the start
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+  let bytes = &bytes[..bytes.len().min(PRESCAN_LENGTH)];
+  let starts_with_ci = |pos: usize, s: &[u8]| bytes.len() >= pos + s.len() && bytes[pos..pos + s.len()].eq_ignore_ascii_case(s);
+  let mut pos = 0;
+  while pos < bytes.len() {
+    if bytes[pos..].starts_with(b"<!--") {
+      //the dashes of the opening can be part of the closing, like the tokenizer
+      pos += 2;
+      while pos < bytes.len() && !bytes[pos..].starts_with(b"-->") {
+        pos += 1;
+      }
+      pos += 3;
+    } else if starts_with_ci(pos, b"<meta") && bytes.get(pos + 5).is_some_and(|b| is_whitespace(*b) || *b == b'/') {
+      pos += 6;
+      let mut seen = Vec::new();
+      let mut got_pragma = false;
+      let mut need_pragma = None;
+      let mut charset = None;
+      while let Some((name, value)) = get_attribute(bytes, &mut pos) {
+        if seen.contains(&name) {
+          continue;
+        }
+        let value = String::from_utf8_lossy(&value);
+        match name.as_slice() {
+          b"http-equiv" => got_pragma = got_pragma || value == "content-type",
+          b"content" if charset.is_none() => {
+            if let Some(label) = charset_param(&value) {
+              charset = Some(Encoding::for_label(label.as_bytes()));
+              need_pragma = Some(true);
+            }
+          },
+          b"charset" => {
+            charset = Some(Encoding::for_label(value.as_bytes()));
+            need_pragma = Some(false);
+          },
+          _ => {},
+        }
+        seen.push(name);
+      }
+      if need_pragma.is_none() || (need_pragma == Some(true) && !got_pragma) {
+        continue;
+      }
+      if let Some(Some(encoding)) = charset {
+        //a document that can be prescanned like this can't really be utf-16
+        return Some(if encoding == X_USER_DEFINED { WINDOWS_1252 } else { encoding.output_encoding() });
+      }
+    } else if bytes[pos] == b'<' && bytes.get(pos + 1).is_some_and(|b| b.is_ascii_alphabetic() || (*b == b'/' && bytes.get(pos + 2).is_some_and(|b| b.is_ascii_alphabetic()))) {
+      //some other tag. its attributes might have a > in them, so they need to be skipped properly
+      while pos < bytes.len() && !is_whitespace(bytes[pos]) && bytes[pos] != b'>' {
+        pos += 1;
+      }
+      while get_attribute(bytes, &mut pos).is_some() {}
+      pos += 1;
+    } else if bytes[pos..].starts_with(b"<!") || bytes[pos..].starts_with(b"</") || bytes[pos..].starts_with(b"<?") {
+      while pos < bytes.len() && bytes[pos] != b'>' {
+        pos += 1;
+      }
+      pos += 1;
+    } else {
+      pos += 1;
+    }
+  }
+  None
+}
+
+//how much decoded text looks like actual writing
+fn plausibility(text: &str) -> i64 {
+  text.chars().filter(|c| !c.is_ascii()).map(|c| match c as u32 {
+    0x3040..=0x30FF => 2, //hiragana and katakana
+    0xAC00..=0xD7A3 => 2, //hangul syllables
+    0x4E00..=0x9FFF => if COMMON_HANZI.contains(c) { 3 } else { 1 },
+    0x3000..=0x303F | 0xFF01..=0xFF5E => 1, //cjk punctuation, fullwidth forms
+    _ => -1, //halfwidth katakana, private use, box drawing, etc. all possible, but unlikely
+  }).sum()
+}
+
+//when nothing says what the encoding is. utf-8 if it's valid, otherwise the cjk encoding that makes the most sense of it, otherwise windows-1252
+fn guess(bytes: &[u8]) -> &'static Encoding {
+  if std::str::from_utf8(bytes).is_ok() {
+    return UTF_8;
+  }
+  let mut best: Option<(&'static Encoding, i64)> = None;
+  for encoding in GUESSES {
+    //anything with malformed sequences is out
+    if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+      let score = plausibility(&text);
+      if score > 0 && best.is_none_or(|(_, best_score)| score > best_score) {
+        best = Some((encoding, score));
+      }
+    }
+  }
+  best.map_or(WINDOWS_1252, |(encoding, _)| encoding)
+}
+
+/// Work out the encoding of a page from its bytes and `Content-Type` header (if it has one).
+pub fn detect(bytes: &[u8], content_type: Option<&str>) -> &'static Encoding {
+  if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+    encoding
+  } else if let Some(encoding) = content_type.and_then(charset_param).and_then(|label| Encoding::for_label(label.as_bytes())) {
+    encoding
+  } else if let Some(encoding) = prescan(bytes) {
+    encoding
+  } else {
+    guess(bytes)
+  }
+}
+
+/// Decode a page from its bytes and `Content-Type` header (if it has one). Malformed sequences become U+FFFD.
+pub fn decode(bytes: &[u8], content_type: Option<&str>) -> String {
+  //decode also strips a bom, and a bom overrides the encoding given
+  detect(bytes, content_type).decode(bytes).0.into_owned()
+}
+
+#[test]
+fn test_prescan() {
+  assert_eq!(prescan(b"<html><head><meta charset=\"Shift_JIS\">"), Some(SHIFT_JIS));
+  assert_eq!(prescan(b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=euc-kr\">"), Some(EUC_KR));
+  //content without http-equiv doesn't count
+  assert_eq!(prescan(b"<meta content=\"text/html; charset=euc-kr\"><p>"), None);
+  assert_eq!(prescan(b"<meta name=description content='charset=gbk'><meta charset=big5>"), Some(BIG5));
+  //not inside comments or other attributes
+  assert_eq!(prescan(b"<!-- <meta charset=gbk> --><meta charset=windows-1252>"), Some(WINDOWS_1252));
+  assert_eq!(prescan(b"<div title='<meta charset=gbk>'><meta charset=latin1>"), Some(WINDOWS_1252));
+  //utf-16 is impossible here
+  assert_eq!(prescan(b"<meta charset=utf-16le>"), Some(UTF_8));
+  assert_eq!(prescan(b"<meta charset=nonsense>"), None);
+  let mut late = vec![b' '; PRESCAN_LENGTH];
+  late.extend_from_slice(b"<meta charset=gbk>");
+  assert_eq!(prescan(&late), None);
+}
+
+#[test]
+fn test_detect() {
+  assert_eq!(charset_param("text/html; Charset=\"Big5\""), Some("Big5"));
+  assert_eq!(charset_param("text/html; charset=\"Big5"), None);
+  assert_eq!(detect(b"\xEF\xBB\xBFhi", Some("text/html; charset=gbk")), UTF_8);
+  assert_eq!(detect(b"<meta charset=gbk>", Some("text/html; charset=euc-kr")), EUC_KR);
+  assert_eq!(detect(b"<meta charset=gbk>", Some("text/html")), GBK);
+  //guessing
+  for (text, encoding) in [("鄭成功は、明の軍人。日本の平戸で生まれた。", SHIFT_JIS), ("국성야는 명나라의 장군이다. 일본에서 태어났다.", EUC_KR), ("郑成功是明朝末年的军事将领，他出生于日本。", GBK), ("鄭成功是明朝末年的軍事將領，他出生於日本。", BIG5)] {
+    let (bytes, _, _) = encoding.encode(text);
+    assert_eq!(detect(&bytes, None), encoding);
+    assert_eq!(decode(&bytes, None), text);
+  }
+  assert_eq!(decode(b"caf\xE9 na\xEFve", None), "café naïve");
+  assert_eq!(decode("鄭成功".as_bytes(), None), "鄭成功");
+}
@@ -0,0 +1,109 @@
+//! Reading local files through `file://` urls. Directories become generated index pages.
+
+use std::fs::{ metadata, read, read_dir };
+use std::io;
+use std::path::{ Path, PathBuf };
+
+use crate::url::{ percent_decode, percent_encode, Url };
+use crate::xml::escape_html;
+
+/// The `file://` url of an absolute path.
+pub fn path_to_url(path: &Path) -> Url {
+  let encoded: Vec<String> = path.to_string_lossy().split('/').map(percent_encode).collect();
+  Url::new(format!("file://{}", encoded.join("/")))
+}
+
+/// The path of a `file://` url, if it is one (on this machine).
+pub fn url_to_path(url: &Url) -> Option<PathBuf> {
+  if url.scheme.as_deref() == Some("file") && (url.host_str().is_empty() || url.host_str() == "localhost") && url.path.starts_with('/') {
+    Some(PathBuf::from(percent_decode(&url.path)))
+  } else {
+    None
+  }
+}
+
+/// Guess the mime type of a file from its extension, or if that doesn't say, whether it looks like text.
+pub fn mime(path: &Path, bytes: &[u8]) -> &'static str {
+  match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+    Some("html") | Some("htm") | Some("xhtml") => "text/html",
+    Some("gmi") | Some("gemini") => "text/gemini",
+    Some("txt") | Some("rs") | Some("toml") => "text/plain",
+    Some("md") | Some("markdown") => "text/markdown",
+    Some("json") => "application/json",
+    Some("png") => "image/png",
+    Some("jpg") | Some("jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("pdf") => "application/pdf",
+    Some("zip") => "application/zip",
+    Some("gz") => "application/gzip",
+    //nul bytes don't show up in text
+    _ if bytes.iter().take(1024).any(|b| *b == 0) => "application/octet-stream",
+    _ => "text/plain",
+  }
+}
+
+//an index page for a directory, with links to its entries (directories first) and its parent
+fn index_page(url: &Url, path: &Path) -> io::Result<String> {
+  let mut base = url.clone();
+  base.query = None;
+  base.fragment = None;
+  if !base.path.ends_with('/') {
+    base.path.push('/');
+  }
+  let mut entries: Vec<(bool, String)> = read_dir(path)?.filter_map(|e| e.ok()).map(|e| {
+    (e.file_type().is_ok_and(|t| t.is_dir()), e.file_name().to_string_lossy().to_string())
+  }).collect();
+  entries.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+  let title = escape_html(&format!("Index of {}", percent_decode(&base.path)));
+  let mut body = String::new();
+  if base.path != "/" {
+    body += &format!("<p><a href=\"{}\">../</a></p>", base.resolve("..").to_string().replace("\"", "%22"));
+  }
+  for (is_dir, name) in entries {
+    let slash = if is_dir { "/" } else { "" };
+    body += &format!("<p><a href=\"{}{}{}\">{}{}</a></p>", base, percent_encode(&name), slash, escape_html(&name), slash);
+  }
+  Ok(format!("<html><head><title>{}</title></head><body><h1>{}</h1>{}</body></html>", title, title, body))
+}
+
+/// Read a `file://` url. Returns the mime type (see [`mime`]) and contents. Directories are an html index page.
+pub fn get(url: &Url) -> io::Result<(&'static str, Vec<u8>)> {
+  let path = url_to_path(url).ok_or(io::Error::new(io::ErrorKind::InvalidInput, "not a local file url"))?;
+  if metadata(&path)?.is_dir() {
+    Ok(("text/html", index_page(url, &path)?.into_bytes()))
+  } else {
+    let bytes = read(&path)?;
+    Ok((mime(&path, &bytes), bytes))
+  }
+}
+
+#[test]
+fn test_file_urls() {
+  let url = path_to_url(Path::new("/home/koxinga/鄭 成功/100%.html"));
+  assert_eq!(url.to_string(), "file:///home/koxinga/%E9%84%AD%20%E6%88%90%E5%8A%9F/100%25.html");
+  assert_eq!(url_to_path(&url), Some(PathBuf::from("/home/koxinga/鄭 成功/100%.html")));
+  assert_eq!(url_to_path(&Url::new("file://localhost/etc/hosts".to_string())), Some(PathBuf::from("/etc/hosts")));
+  assert_eq!(url_to_path(&Url::new("file://example.org/etc/hosts".to_string())), None);
+  assert_eq!(mime(Path::new("a.HTML"), b""), "text/html");
+  assert_eq!(mime(Path::new("README"), b"hello"), "text/plain");
+  assert_eq!(mime(Path::new("README.md"), b"# hello"), "text/markdown");
+  assert_eq!(mime(Path::new("a.out"), b"\x7fELF\x00"), "application/octet-stream");
+}
+
+#[test]
+fn test_index_page() {
+  let dir = std::env::temp_dir().join(format!("koxinga-file-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(dir.join("sub dir")).unwrap();
+  std::fs::write(dir.join("b.txt"), "hi").unwrap();
+  std::fs::write(dir.join("a.html"), "<p>hi</p>").unwrap();
+  let url = path_to_url(&dir);
+  let (mime, bytes) = get(&url).unwrap();
+  assert_eq!(mime, "text/html");
+  let page = String::from_utf8(bytes).unwrap();
+  let base = url.to_string() + "/";
+  let parent = path_to_url(dir.parent().unwrap()).to_string() + "/";
+  assert!(page.contains(&format!("<a href=\"{}\">../</a></p><p><a href=\"{}sub%20dir/\">sub dir/</a></p><p><a href=\"{}a.html\">a.html</a></p><p><a href=\"{}b.txt\">b.txt</a>", parent, base, base, base)), "{}", page);
+  assert_eq!(get(&Url::new(base + "b.txt")).unwrap(), ("text/plain", b"hi".to_vec()));
+  let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,484 @@
+//! Fetching pages over gemini, and turning gemtext (text/gemini) into [`OutputType`]s.
+//!
+//! Gemini servers almost always have self-signed certificates, so instead of checking them against certificate authorities, the certificate a host presents the first time is remembered (trust on first use), and any different certificate after that is refused.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{ create_dir_all, read_to_string, write };
+use std::io::{ Read, Write };
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+
+use native_tls::TlsConnector;
+
+use crate::dirs::config_dir;
+use crate::url::Url;
+use crate::xml::{ OutputType, Style };
+
+const DEFAULT_PORT: u16 = 1965;
+const MAX_REDIRECTS: usize = 5; //what the spec suggests
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Why a gemini page could not be fetched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeminiError {
+  /// The url has no host, or isn't a gemini url.
+  InvalidUrl,
+  /// The server could not be connected to, or the connection broke.
+  Connect(String),
+  /// The TLS handshake failed.
+  Tls(String),
+  /// The host presented a different certificate than the one it did the first time. Could be an attack, or could just be a new certificate.
+  CertificateChanged(String),
+  /// More than the maximum number of redirects were followed.
+  TooManyRedirects,
+  /// A redirect to something other than gemini (the url), which isn't followed automatically.
+  Redirect(String),
+  /// The response header was malformed.
+  BadResponse,
+  /// A failure (4x), or a client certificate being required (6x), with the status code and the server's message.
+  Status(u8, String),
+}
+
+impl GeminiError {
+  /// The status code, if the server responded with one.
+  pub fn status(&self) -> Option<u8> {
+    if let GeminiError::Status(status, _) = self {
+      Some(*status)
+    } else {
+      None
+    }
+  }
+
+  /// A name for the status code, eg: "Not Found" for 51.
+  pub fn reason(&self) -> Option<&'static str> {
+    Some(match self.status()? {
+      41 => "Server Unavailable",
+      42 => "CGI Error",
+      43 => "Proxy Error",
+      44 => "Slow Down",
+      51 => "Not Found",
+      52 => "Gone",
+      53 => "Proxy Request Refused",
+      59 => "Bad Request",
+      61 => "Certificate Not Authorised",
+      62 => "Certificate Not Valid",
+      40..=49 => "Temporary Failure",
+      60..=69 => "Client Certificate Required",
+      _ => "Permanent Failure",
+    })
+  }
+}
+
+impl fmt::Display for GeminiError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GeminiError::InvalidUrl => write!(f, "invalid url"),
+      GeminiError::Connect(message) => write!(f, "could not connect to the server: {}", message),
+      GeminiError::Tls(message) => write!(f, "secure connection failed: {}", message),
+      GeminiError::CertificateChanged(host) => write!(f, "the certificate of {} is not the one it had before. if it was changed on purpose, remove {} from koxinga/gemini_hosts in the config directory", host, host),
+      GeminiError::TooManyRedirects => write!(f, "too many redirects"),
+      GeminiError::Redirect(url) => write!(f, "redirect to {}", url),
+      GeminiError::BadResponse => write!(f, "the server sent an invalid response"),
+      GeminiError::Status(status, message) => {
+        write!(f, "{} {}", status, self.reason().unwrap_or_default())?;
+        if !message.is_empty() {
+          write!(f, ": {}", message)?;
+        }
+        Ok(())
+      },
+    }
+  }
+}
+
+impl Error for GeminiError {}
+
+/// A response that isn't an error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeminiResponse {
+  /// The page (2x), with its mime type (parameters and all), body and final url, after redirects.
+  Success {
+    /// Mime type. text/gemini if the server didn't say.
+    mime: String,
+    /// The body, as sent.
+    body: Vec<u8>,
+    /// The final url.
+    url: Url,
+  },
+  /// The server wants some input (1x). It should be sent as the query of `url`.
+  Input {
+    /// What to ask the user.
+    prompt: String,
+    /// Whether the input is something like a password, that shouldn't be shown as it is typed.
+    sensitive: bool,
+    /// Url to send the input to.
+    url: Url,
+  },
+}
+
+//sha-256 (fips 180-4), only for certificate fingerprints
+fn sha256(data: &[u8]) -> [u8; 32] {
+  const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+  ];
+  let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+  let mut message = data.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+  for chunk in message.chunks(64) {
+    let mut w = [0u32; 64];
+    for i in 0..16 {
+      w[i] = u32::from_be_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+    for i in 0..64 {
+      let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+      let ch = (e & f) ^ (!e & g);
+      let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+      let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+      let maj = (a & b) ^ (a & c) ^ (b & c);
+      let t2 = s0.wrapping_add(maj);
+      hh = g;
+      g = f;
+      f = e;
+      e = d.wrapping_add(t1);
+      d = c;
+      c = b;
+      b = a;
+      a = t1.wrapping_add(t2);
+    }
+    for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+      *x = x.wrapping_add(y);
+    }
+  }
+  let mut hash = [0; 32];
+  for (i, x) in h.iter().enumerate() {
+    hash[i * 4..i * 4 + 4].copy_from_slice(&x.to_be_bytes());
+  }
+  hash
+}
+
+fn fingerprint(der: &[u8]) -> String {
+  sha256(der).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//certificate fingerprints of hosts seen before
+#[derive(Default)]
+struct KnownHosts {
+  hosts: Vec<(String, String)>, //host:port, fingerprint
+  file: Option<PathBuf>, //None to only keep them in memory
+}
+
+impl KnownHosts {
+  fn load() -> Self {
+    let file = config_dir().map(|mut c| {
+      c.push("koxinga");
+      c.push("gemini_hosts");
+      c
+    });
+    let hosts = file.as_ref().and_then(|f| read_to_string(f).ok()).map_or(Vec::new(), |contents| {
+      contents.lines().filter_map(|l| l.split_once('\t')).map(|(host, fingerprint)| (host.to_string(), fingerprint.to_string())).collect()
+    });
+    Self { hosts, file }
+  }
+
+  fn save(&self) {
+    if let Some(file) = &self.file {
+      let contents: Vec<String> = self.hosts.iter().map(|(host, fingerprint)| format!("{}\t{}", host, fingerprint)).collect();
+      if let Some(parent) = file.parent() {
+        let _ = create_dir_all(parent);
+      }
+      let _ = write(file, contents.join("\n"));
+    }
+  }
+
+  //trust on first use
+  fn check(&mut self, host: &str, fingerprint: &str) -> Result<(), GeminiError> {
+    if let Some((_, known)) = self.hosts.iter().find(|(h, _)| h == host) {
+      if known == fingerprint {
+        Ok(())
+      } else {
+        Err(GeminiError::CertificateChanged(host.to_string()))
+      }
+    } else {
+      self.hosts.push((host.to_string(), fingerprint.to_string()));
+      self.save();
+      Ok(())
+    }
+  }
+}
+
+//send the request line and read the whole response. returns the status, meta and body
+fn request<S: Read + Write>(stream: &mut S, url: &Url) -> Result<(u8, String, Vec<u8>), GeminiError> {
+  //fragments are for the client
+  let mut url = url.clone();
+  url.fragment = None;
+  stream.write_all(format!("{}\r\n", url).as_bytes()).map_err(|e| GeminiError::Connect(e.to_string()))?;
+  let mut response = Vec::new();
+  if let Err(e) = stream.read_to_end(&mut response) {
+    //lots of servers close the connection without a tls close_notify, which is an error, but the response is all there
+    if response.is_empty() {
+      return Err(GeminiError::Connect(e.to_string()));
+    }
+  }
+  let header_end = response.windows(2).position(|w| w == b"\r\n").ok_or(GeminiError::BadResponse)?;
+  let header = std::str::from_utf8(&response[..header_end]).map_err(|_| GeminiError::BadResponse)?;
+  let status = header.get(..2).filter(|s| s.bytes().all(|b| b.is_ascii_digit())).and_then(|s| s.parse::<u8>().ok()).ok_or(GeminiError::BadResponse)?;
+  let meta = header[2..].trim().to_string();
+  Ok((status, meta, response[header_end + 2..].to_vec()))
+}
+
+//follow redirects, connecting with connect. generic so tests can stand in for a server
+fn get_with<S: Read + Write>(url: &Url, mut connect: impl FnMut(&Url) -> Result<S, GeminiError>) -> Result<GeminiResponse, GeminiError> {
+  let mut url = url.clone();
+  for _ in 0..=MAX_REDIRECTS {
+    if url.scheme.as_deref() != Some("gemini") || url.host.as_ref().is_none_or(|h| h.is_empty()) {
+      return Err(GeminiError::InvalidUrl);
+    }
+    let (status, meta, body) = request(&mut connect(&url)?, &url)?;
+    match status / 10 {
+      1 => return Ok(GeminiResponse::Input { prompt: meta, sensitive: status == 11, url }),
+      2 => return Ok(GeminiResponse::Success {
+        mime: if meta.is_empty() { "text/gemini; charset=utf-8".to_string() } else { meta },
+        body,
+        url,
+      }),
+      3 => {
+        let mut new_url = url.resolve(&meta);
+        if new_url.scheme.as_deref() != Some("gemini") {
+          return Err(GeminiError::Redirect(new_url.to_string()));
+        }
+        //fragments survive redirects, unless the redirect has its own
+        if new_url.fragment.is_none() {
+          new_url.fragment = url.fragment;
+        }
+        url = new_url;
+      },
+      4..=6 => return Err(GeminiError::Status(status, meta)),
+      _ => return Err(GeminiError::BadResponse),
+    }
+  }
+  Err(GeminiError::TooManyRedirects)
+}
+
+/// A gemini client that pins the certificates of hosts the first time they are seen (saved to the config dir).
+///
+/// Client certificates are not supported, so pages that need them (6x) are errors.
+///
+/// Cloning is cheap, the known certificates are shared, so a clone can be sent to another thread.
+#[derive(Clone)]
+pub struct GeminiClient {
+  known_hosts: Arc<Mutex<KnownHosts>>,
+}
+
+impl std::default::Default for GeminiClient {
+  fn default() -> Self {
+    Self {
+      known_hosts: Arc::new(Mutex::new(KnownHosts::load())),
+    }
+  }
+}
+
+impl GeminiClient {
+  fn connect(&self, url: &Url) -> Result<native_tls::TlsStream<TcpStream>, GeminiError> {
+    let host = url.host_str();
+    let port = url.port.unwrap_or(DEFAULT_PORT);
+    let tcp = TcpStream::connect((host, port)).map_err(|e| GeminiError::Connect(e.to_string()))?;
+    let _ = tcp.set_read_timeout(Some(TIMEOUT));
+    let _ = tcp.set_write_timeout(Some(TIMEOUT));
+    //the certificate is checked against the known hosts instead
+    let connector = TlsConnector::builder().danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true).build().map_err(|e| GeminiError::Tls(e.to_string()))?;
+    let stream = connector.connect(host, tcp).map_err(|e| GeminiError::Tls(e.to_string()))?;
+    let der = stream.peer_certificate().ok().flatten().and_then(|c| c.to_der().ok()).ok_or(GeminiError::Tls("no certificate".to_string()))?;
+    self.known_hosts.lock().unwrap().check(&format!("{}:{}", host, port), &fingerprint(&der))?;
+    Ok(stream)
+  }
+
+  /// Fetch a page, following redirects (to other gemini urls only).
+  pub fn get(&self, url: &Url) -> Result<GeminiResponse, GeminiError> {
+    get_with(url, |url| self.connect(url))
+  }
+}
+
+/// The title (the first heading) and outputs of a gemtext page.
+pub fn gemtext_outputs(text: &str) -> (Option<String>, Vec<OutputType>) {
+  let mut title = None;
+  let mut outputs = Vec::new();
+  let mut preformatted = false;
+  for line in text.lines() {
+    if line.starts_with("```") {
+      //anything after the backticks is alt text, which there's nowhere to show
+      preformatted = !preformatted;
+      outputs.push(OutputType::Preformatted(preformatted));
+      continue;
+    } else if preformatted {
+      outputs.push(OutputType::Text(line.to_string()));
+    } else if let Some(link) = line.strip_prefix("=>") {
+      let link = link.trim();
+      let (link_url, label) = link.split_once(|c: char| c.is_whitespace()).map_or((link, ""), |(u, l)| (u, l.trim()));
+      if link_url.is_empty() {
+        continue;
+      }
+      //quoted, like an href
+      outputs.push(OutputType::StartLink(format!("\"{}\"", link_url)));
+      outputs.push(OutputType::Text(if label.is_empty() { link_url } else { label }.to_string()));
+      outputs.push(OutputType::EndLink);
+    } else if line.starts_with('#') {
+      let heading = line.trim_start_matches('#').trim();
+      if title.is_none() && !heading.is_empty() {
+        title = Some(heading.to_string());
+      }
+      //only #, ## and ### are headings, but more are probably meant as the smallest
+      let level = (line.len() - line.trim_start_matches('#').len()).min(3) as u8;
+      outputs.push(OutputType::StartStyle(Style { heading: level, ..Style::default() }));
+      outputs.push(OutputType::Text(heading.to_string()));
+      outputs.push(OutputType::EndStyle);
+    } else if let Some(item) = line.strip_prefix("* ") {
+      outputs.push(OutputType::Text(format!("• {}", item.trim())));
+    } else if let Some(quote) = line.strip_prefix('>') {
+      outputs.push(OutputType::Indent(32));
+      outputs.push(OutputType::Text(quote.trim().to_string()));
+      outputs.push(OutputType::Newline);
+      outputs.push(OutputType::Indent(0));
+      continue;
+    } else if !line.is_empty() {
+      outputs.push(OutputType::Text(line.to_string()));
+    }
+    outputs.push(OutputType::Newline);
+  }
+  (title, outputs)
+}
+
+//a server stand-in. reads the request into written, and responds with response
+#[cfg(test)]
+struct MockStream {
+  response: std::io::Cursor<Vec<u8>>,
+  written: Vec<u8>,
+}
+
+#[cfg(test)]
+impl Read for MockStream {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.response.read(buf)
+  }
+}
+
+#[cfg(test)]
+impl Write for MockStream {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.written.write(buf)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+fn mock_server(responses: &'static [(&'static str, &'static str)]) -> impl FnMut(&Url) -> Result<MockStream, GeminiError> {
+  move |url: &Url| {
+    let mut url = url.clone();
+    url.fragment = None;
+    let response = responses.iter().find(|(u, _)| *u == url.to_string()).map_or("51 nope\r\n", |(_, r)| *r);
+    Ok(MockStream {
+      response: std::io::Cursor::new(response.as_bytes().to_vec()),
+      written: Vec::new(),
+    })
+  }
+}
+
+#[test]
+fn test_gemini_get() {
+  let responses = &[
+    ("gemini://example.org/", "20 text/gemini; lang=zh\r\n# 鄭成功\n"),
+    ("gemini://example.org/old", "31 /new\r\n"),
+    ("gemini://example.org/new", "20\r\nhi"),
+    ("gemini://example.org/search", "10 Search for\r\n"),
+    ("gemini://example.org/login", "11 Password\r\n"),
+    ("gemini://example.org/loop", "30 gemini://example.org/loop\r\n"),
+    ("gemini://example.org/web", "31 https://example.org/\r\n"),
+    ("gemini://example.org/cert", "60\r\n"),
+    ("gemini://example.org/bad", "hello\r\n"),
+  ];
+  let get = |url: &str| get_with(&Url::new(url.to_string()), mock_server(responses));
+  assert_eq!(get("gemini://example.org/"), Ok(GeminiResponse::Success {
+    mime: "text/gemini; lang=zh".to_string(),
+    body: "# 鄭成功\n".as_bytes().to_vec(),
+    url: Url::new("gemini://example.org/".to_string()),
+  }));
+  assert_eq!(get("gemini://example.org/old#top"), Ok(GeminiResponse::Success {
+    mime: "text/gemini; charset=utf-8".to_string(),
+    body: b"hi".to_vec(),
+    url: Url::new("gemini://example.org/new#top".to_string()),
+  }));
+  assert!(matches!(get("gemini://example.org/search"), Ok(GeminiResponse::Input { sensitive: false, .. })));
+  assert!(matches!(get("gemini://example.org/login"), Ok(GeminiResponse::Input { sensitive: true, .. })));
+  assert_eq!(get("gemini://example.org/loop"), Err(GeminiError::TooManyRedirects));
+  assert_eq!(get("gemini://example.org/web"), Err(GeminiError::Redirect("https://example.org/".to_string())));
+  assert_eq!(get("gemini://example.org/missing"), Err(GeminiError::Status(51, "nope".to_string())));
+  assert_eq!(get("gemini://example.org/missing").unwrap_err().to_string(), "51 Not Found: nope");
+  assert_eq!(get("gemini://example.org/cert").unwrap_err().reason(), Some("Client Certificate Required"));
+  assert_eq!(get("gemini://example.org/bad"), Err(GeminiError::BadResponse));
+  assert_eq!(get("https://example.org/"), Err(GeminiError::InvalidUrl));
+  //what gets sent
+  let mut stream = mock_server(responses)(&Url::new("gemini://example.org/".to_string())).unwrap();
+  request(&mut stream, &Url::new("gemini://example.org/?q#frag".to_string())).unwrap();
+  assert_eq!(stream.written, b"gemini://example.org/?q\r\n");
+}
+
+#[test]
+fn test_known_hosts() {
+  assert_eq!(fingerprint(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+  assert_eq!(fingerprint(&[b'a'; 1000]), "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3");
+  let mut known_hosts: KnownHosts = Default::default();
+  assert!(known_hosts.check("example.org:1965", "aa").is_ok());
+  assert!(known_hosts.check("example.org:1965", "aa").is_ok());
+  assert!(known_hosts.check("example.org:1966", "bb").is_ok());
+  assert_eq!(known_hosts.check("example.org:1965", "bb"), Err(GeminiError::CertificateChanged("example.org:1965".to_string())));
+}
+
+#[test]
+fn test_gemtext_outputs() {
+  let (title, outputs) = gemtext_outputs("intro\n\n## Koxinga\n=> gemini://a.org/b A link\n=>/c\n* one\n> quoted\n```ascii art\n  =>  not a link\n```\n");
+  assert_eq!(title.as_deref(), Some("Koxinga"));
+  assert_eq!(outputs, vec![
+    OutputType::Text("intro".to_string()),
+    OutputType::Newline,
+    OutputType::Newline,
+    OutputType::StartStyle(Style { heading: 2, ..Style::default() }),
+    OutputType::Text("Koxinga".to_string()),
+    OutputType::EndStyle,
+    OutputType::Newline,
+    OutputType::StartLink("\"gemini://a.org/b\"".to_string()),
+    OutputType::Text("A link".to_string()),
+    OutputType::EndLink,
+    OutputType::Newline,
+    OutputType::StartLink("\"/c\"".to_string()),
+    OutputType::Text("/c".to_string()),
+    OutputType::EndLink,
+    OutputType::Newline,
+    OutputType::Text("• one".to_string()),
+    OutputType::Newline,
+    OutputType::Indent(32),
+    OutputType::Text("quoted".to_string()),
+    OutputType::Newline,
+    OutputType::Indent(0),
+    OutputType::Preformatted(true),
+    OutputType::Text("  =>  not a link".to_string()),
+    OutputType::Newline,
+    OutputType::Preformatted(false),
+  ]);
+}
@@ -0,0 +1,231 @@
+//! Fetching pages over gopher, and turning gopher menus into [`OutputType`]s.
+//!
+//! Urls are as in rfc 4266: `gopher://host:port/<item type><selector>`, with a search (for type 7 items) either after a `%09` or, more conveniently, as the query.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{ Read, Write };
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::url::{ percent_decode, Url };
+use crate::xml::OutputType;
+
+const DEFAULT_PORT: u16 = 70;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Why a gopher page could not be fetched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GopherError {
+  /// The url has no host, or isn't a gopher url.
+  InvalidUrl,
+  /// The server could not be connected to, or the connection broke.
+  Connect(String),
+}
+
+impl fmt::Display for GopherError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GopherError::InvalidUrl => write!(f, "invalid url"),
+      GopherError::Connect(message) => write!(f, "could not connect to the server: {}", message),
+    }
+  }
+}
+
+impl Error for GopherError {}
+
+/// The item type, selector and search (if any) of a gopher url. Urls without an item type are menus (type 1).
+pub fn split_url(url: &Url) -> (char, String, Option<String>) {
+  let path = url.path.strip_prefix('/').unwrap_or(&url.path);
+  let mut chars = path.chars();
+  let item_type = chars.next().unwrap_or('1');
+  let rest = chars.as_str();
+  let (selector, search) = if let Some((selector, search)) = rest.split_once("%09") {
+    //a "%09" after that is the start of gopher+ stuff, which isn't supported
+    (selector, Some(percent_decode(search.split("%09").next().unwrap_or(""))))
+  } else {
+    (rest, None)
+  };
+  let search = url.query.as_ref().map(|q| percent_decode(&q.replace('+', " "))).or(search);
+  (item_type, percent_decode(selector), search)
+}
+
+/// The mime type of an item type, if it is something that can be shown (a menu, text or html).
+pub fn mime(item_type: char) -> Option<&'static str> {
+  match item_type {
+    '0' => Some("text/plain"),
+    '1' | '7' => Some("application/gopher-menu"),
+    'h' => Some("text/html"),
+    _ => None,
+  }
+}
+
+//selectors can have anything but tabs and newlines, so escape whatever a url can't have
+fn encode_selector(selector: &str) -> String {
+  selector.bytes().map(|b| if b.is_ascii_alphanumeric() || b"-._~/!$&'()*+,;=:@".contains(&b) {
+    (b as char).to_string()
+  } else {
+    format!("%{:02X}", b)
+  }).collect()
+}
+
+//send the selector (and search) and read the whole response
+fn request<S: Read + Write>(stream: &mut S, selector: &str, search: Option<&str>) -> Result<Vec<u8>, GopherError> {
+  let line = if let Some(search) = search {
+    format!("{}\t{}\r\n", selector, search)
+  } else {
+    format!("{}\r\n", selector)
+  };
+  stream.write_all(line.as_bytes()).map_err(|e| GopherError::Connect(e.to_string()))?;
+  let mut response = Vec::new();
+  stream.read_to_end(&mut response).map_err(|e| GopherError::Connect(e.to_string()))?;
+  Ok(response)
+}
+
+/// Fetch a gopher url. Returns the item type and the body, as sent.
+pub fn get(url: &Url) -> Result<(char, Vec<u8>), GopherError> {
+  if url.scheme.as_deref() != Some("gopher") || url.host.as_ref().is_none_or(|h| h.is_empty()) {
+    return Err(GopherError::InvalidUrl);
+  }
+  let (item_type, selector, search) = split_url(url);
+  let mut stream = TcpStream::connect((url.host_str(), url.port.unwrap_or(DEFAULT_PORT))).map_err(|e| GopherError::Connect(e.to_string()))?;
+  let _ = stream.set_read_timeout(Some(TIMEOUT));
+  let _ = stream.set_write_timeout(Some(TIMEOUT));
+  Ok((item_type, request(&mut stream, &selector, search.as_deref())?))
+}
+
+/// A text file (type 0) without the "." line that ends it, and with any dot stuffing undone.
+pub fn text_file(text: &str) -> String {
+  let mut lines = Vec::new();
+  for line in text.lines() {
+    if line == "." {
+      break;
+    }
+    lines.push(line.strip_prefix("..").map_or(line.to_string(), |l| format!(".{}", l)));
+  }
+  lines.join("\n")
+}
+
+/// The outputs of a gopher menu (type 1, or the results of a type 7 search). Items that can be followed become links, labelled with what they are.
+pub fn menu_outputs(text: &str) -> Vec<OutputType> {
+  let mut outputs = Vec::new();
+  for line in text.lines() {
+    if line == "." {
+      break;
+    }
+    let mut chars = line.chars();
+    let Some(item_type) = chars.next() else {
+      outputs.push(OutputType::Newline);
+      continue;
+    };
+    let mut fields = chars.as_str().split('\t');
+    let display = fields.next().unwrap_or("");
+    let selector = fields.next().unwrap_or("");
+    let host = fields.next().unwrap_or("");
+    let port = fields.next().unwrap_or("").trim();
+    let label = match item_type {
+      '0' => "TXT",
+      '1' => "DIR",
+      '7' => "???",
+      'h' => "HTM",
+      '4' | '5' | '6' | '9' => "BIN",
+      'g' | 'I' | 'p' => "IMG",
+      's' | '<' => "SND",
+      ';' => "VID",
+      'd' => "DOC",
+      _ => "",
+    };
+    let href = if item_type == 'h' && selector.starts_with("URL:") {
+      //a link to somewhere that isn't gopher
+      Some(selector[4..].to_string())
+    } else if label.is_empty() || host.is_empty() {
+      //info (i), errors (3), telnet, and whatever else
+      None
+    } else {
+      let port = if port == "70" || port.is_empty() { String::new() } else { format!(":{}", port) };
+      Some(format!("gopher://{}{}/{}{}", host, port, item_type, encode_selector(selector)))
+    };
+    if let Some(href) = href {
+      outputs.push(OutputType::Text(format!("[{}] ", label)));
+      //quoted, like an href
+      outputs.push(OutputType::StartLink(format!("\"{}\"", href)));
+      outputs.push(OutputType::Text(display.to_string()));
+      outputs.push(OutputType::EndLink);
+    } else if !display.is_empty() {
+      outputs.push(OutputType::Text(display.to_string()));
+    }
+    outputs.push(OutputType::Newline);
+  }
+  outputs
+}
+
+//a server stand-in, that responds to anything with response
+#[cfg(test)]
+struct MockStream {
+  response: std::io::Cursor<Vec<u8>>,
+  written: Vec<u8>,
+}
+
+#[cfg(test)]
+impl Read for MockStream {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.response.read(buf)
+  }
+}
+
+#[cfg(test)]
+impl Write for MockStream {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.written.write(buf)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+#[test]
+fn test_gopher_urls() {
+  let split = |url: &str| split_url(&Url::new(url.to_string()));
+  assert_eq!(split("gopher://example.org"), ('1', String::new(), None));
+  assert_eq!(split("gopher://example.org/"), ('1', String::new(), None));
+  assert_eq!(split("gopher://example.org/0/about%20us.txt"), ('0', "/about us.txt".to_string(), None));
+  assert_eq!(split("gopher://example.org/7/search%09koxinga"), ('7', "/search".to_string(), Some("koxinga".to_string())));
+  assert_eq!(split("gopher://example.org/7/search?%E9%84%AD+family"), ('7', "/search".to_string(), Some("鄭 family".to_string())));
+  let mut stream = MockStream { response: std::io::Cursor::new(b"hi\r\n.\r\n".to_vec()), written: Vec::new() };
+  assert_eq!(request(&mut stream, "/search", Some("koxinga")), Ok(b"hi\r\n.\r\n".to_vec()));
+  assert_eq!(stream.written, b"/search\tkoxinga\r\n");
+  assert_eq!(text_file("hi\r\n..dotted\r\n.\r\nafter"), "hi\n.dotted");
+  assert_eq!(get(&Url::new("gemini://example.org/".to_string())), Err(GopherError::InvalidUrl));
+}
+
+#[test]
+fn test_menu_outputs() {
+  let menu = "iWelcome\t\terror.host\t1\r\n1Phlog\t/phlog\texample.org\t70\r\n0About us\t/about us.txt\texample.org\t7070\r\n7Search\t/search\texample.org\t70\r\nhWeb\tURL:https://example.org/\texample.org\t70\r\n3Oops\t\terror.host\t1\r\n.\r\n1After the end\t/\texample.org\t70\r\n";
+  assert_eq!(menu_outputs(menu), vec![
+    OutputType::Text("Welcome".to_string()),
+    OutputType::Newline,
+    OutputType::Text("[DIR] ".to_string()),
+    OutputType::StartLink("\"gopher://example.org/1/phlog\"".to_string()),
+    OutputType::Text("Phlog".to_string()),
+    OutputType::EndLink,
+    OutputType::Newline,
+    OutputType::Text("[TXT] ".to_string()),
+    OutputType::StartLink("\"gopher://example.org:7070/0/about%20us.txt\"".to_string()),
+    OutputType::Text("About us".to_string()),
+    OutputType::EndLink,
+    OutputType::Newline,
+    OutputType::Text("[???] ".to_string()),
+    OutputType::StartLink("\"gopher://example.org/7/search\"".to_string()),
+    OutputType::Text("Search".to_string()),
+    OutputType::EndLink,
+    OutputType::Newline,
+    OutputType::Text("[HTM] ".to_string()),
+    OutputType::StartLink("\"https://example.org/\"".to_string()),
+    OutputType::Text("Web".to_string()),
+    OutputType::EndLink,
+    OutputType::Newline,
+    OutputType::Text("Oops".to_string()),
+    OutputType::Newline,
+  ]);
+}
@@ -1,103 +1,444 @@
-use std::collections::HashMap;
+//! Fetching pages over http(s).
 
-//use ming_wm_lib::logging::log;
-use ming_wm_lib::utils::get_rest_of_split;
+use std::error::Error;
+use std::fmt;
+use std::fs::{ create_dir_all, remove_file, File };
+use std::io::{ ErrorKind, Read, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex };
 
 use crate::url::Url;
+use crate::cookies::{ now, CookieJar };
+use crate::cache::HttpCache;
+use crate::encoding::decode;
+use crate::document::is_text;
+use crate::url::percent_decode;
+use crate::dirs::unused_path;
 
-use reqwest::blocking::Client;
+use reqwest::blocking::{ Client, RequestBuilder };
+use reqwest::StatusCode;
+
+const MAX_REDIRECTS: usize = 10;
+
+/// Why a page could not be fetched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HttpError {
+  /// The url could not be turned into a request.
+  InvalidUrl,
+  /// The host name could not be resolved.
+  Dns,
+  /// The server could not be connected to (refused, unreachable, etc).
+  Connect(String),
+  /// The TLS handshake failed, eg: a bad certificate.
+  Tls(String),
+  /// The server took too long to respond.
+  Timeout,
+  /// More than the maximum number of redirects were followed.
+  TooManyRedirects,
+  /// The server responded, but with a non-success status code.
+  Status(u16),
+  /// A download was cancelled (see [`HttpClient::download`]).
+  Cancelled,
+  /// Anything else, like a body that could not be read.
+  Other(String),
+}
+
+impl HttpError {
+  /// The http status code, if the server responded with one.
+  pub fn status(&self) -> Option<u16> {
+    if let HttpError::Status(status) = self {
+      Some(*status)
+    } else {
+      None
+    }
+  }
+
+  /// The standard reason phrase for the status code, eg: "Not Found" for 404.
+  pub fn reason(&self) -> Option<&'static str> {
+    StatusCode::from_u16(self.status()?).ok()?.canonical_reason()
+  }
+}
+
+impl fmt::Display for HttpError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      HttpError::InvalidUrl => write!(f, "invalid url"),
+      HttpError::Dns => write!(f, "could not find the server"),
+      HttpError::Connect(message) => write!(f, "could not connect to the server: {}", message),
+      HttpError::Tls(message) => write!(f, "secure connection failed: {}", message),
+      HttpError::Timeout => write!(f, "the server took too long to respond"),
+      HttpError::TooManyRedirects => write!(f, "too many redirects"),
+      HttpError::Status(status) => write!(f, "{} {}", status, self.reason().unwrap_or("Unknown Status")),
+      HttpError::Cancelled => write!(f, "cancelled"),
+      HttpError::Other(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl Error for HttpError {}
+
+impl From<reqwest::Error> for HttpError {
+  fn from(e: reqwest::Error) -> Self {
+    if e.is_timeout() {
+      return HttpError::Timeout;
+    } else if e.is_builder() {
+      return HttpError::InvalidUrl;
+    }
+    //reqwest doesn't say what kind of connect error it was, but the underlying errors do
+    let mut messages = Vec::new();
+    let mut source: Option<&dyn Error> = Some(&e);
+    while let Some(s) = source {
+      messages.push(s.to_string());
+      source = s.source();
+    }
+    let innermost = messages.last().cloned().unwrap_or_default();
+    let all = messages.join(": ").to_lowercase();
+    if all.contains("dns error") || all.contains("failed to lookup address") {
+      HttpError::Dns
+    } else if all.contains("certificate") || all.contains("tls") || all.contains("ssl") {
+      HttpError::Tls(innermost)
+    } else if e.is_connect() {
+      HttpError::Connect(innermost)
+    } else {
+      HttpError::Other(innermost)
+    }
+  }
+}
+
+/// A successful (2xx) response.
+#[derive(Debug)]
+pub struct Response {
+  /// The status code.
+  pub status: u16,
+  /// The body, decoded to text (see [`crate::encoding`]). Empty if the content type isn't text (see [`is_text`]) or it is an attachment, since there is nothing to show; it is left in `stream` to be saved instead.
+  pub body: String,
+  /// The body, not yet read, if it isn't going to be shown (when `body` is empty).
+  pub stream: Option<BodyStream>,
+  /// The `Content-Type` header, if there was one.
+  pub content_type: Option<String>,
+  /// Whether the server asked for it to be saved rather than shown (`Content-Disposition: attachment`).
+  pub attachment: bool,
+  /// The final url, which may differ from the requested url because of redirects.
+  pub url: Url,
+}
+
+/// The body of a response that is to be saved rather than shown, not read yet so it doesn't have to fit in memory, or be fetched again.
+#[derive(Debug)]
+pub struct BodyStream {
+  resp: reqwest::blocking::Response,
+  /// The name the server gives in `Content-Disposition`, or else the last part of the url.
+  pub filename: String,
+  /// The size, if the server said.
+  pub size: Option<u64>,
+}
+
+impl BodyStream {
+  fn new(resp: reqwest::blocking::Response, url: &Url) -> Self {
+    let disposition = resp.headers().get("Content-Disposition").and_then(|c| c.to_str().ok());
+    let filename = disposition.and_then(disposition_filename).unwrap_or_else(|| url_filename(url));
+    let size = resp.content_length();
+    Self { resp, filename, size }
+  }
+
+  /// Save the body into `dir` (created if needed), under [`BodyStream::filename`]. Existing files are not overwritten, a number is added to the name instead. Returns where it was saved.
+  ///
+  /// The body is streamed to the file. `progress` is called with the path, the bytes received so far and the total size (if the server said) as it goes, and can return false to cancel. Cancelled or failed downloads are deleted.
+  pub fn save(mut self, dir: &Path, mut progress: impl FnMut(&Path, u64, Option<u64>) -> bool) -> Result<PathBuf, HttpError> {
+    create_dir_all(dir).map_err(|e| HttpError::Other(e.to_string()))?;
+    let path = unused_path(dir, &self.filename);
+    let mut file = File::create(&path).map_err(|e| HttpError::Other(e.to_string()))?;
+    let mut received = 0;
+    let mut buf = vec![0; 64 * 1024];
+    let result = loop {
+      if !progress(&path, received, self.size) {
+        break Err(HttpError::Cancelled);
+      }
+      match self.resp.read(&mut buf) {
+        Ok(0) => break Ok(()),
+        Ok(n) => {
+          if let Err(e) = file.write_all(&buf[..n]) {
+            break Err(HttpError::Other(e.to_string()));
+          }
+          received += n as u64;
+        },
+        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+        Err(e) => break Err(HttpError::Other(e.to_string())),
+      }
+    };
+    if let Err(e) = result {
+      drop(file);
+      let _ = remove_file(&path);
+      return Err(e);
+    }
+    Ok(path)
+  }
+}
 
 //for now, just a thin wrapper
+/// A blocking http client that follows redirects and keeps cookies (saved to the config dir, so they last between sessions).
+///
+/// GET responses are cached on disk (in the cache dir), following their `Cache-Control`, `Expires`, `ETag` and `Last-Modified` headers. Stale responses are revalidated with `If-None-Match`/`If-Modified-Since`, and the least recently used are evicted once the cache gets too big.
+///
+/// Cloning is cheap, the underlying client, cookie jar and cache are shared, so a clone can be sent to another thread.
+#[derive(Clone)]
 pub struct HttpClient {
   client: Client,
-  no_redirect_client: Client,
+  cookie_jar: Arc<Mutex<CookieJar>>,
+  cache: Arc<Mutex<HttpCache>>,
 }
 
 impl std::default::Default for HttpClient {
   fn default() -> Self {
+    Self::new(CookieJar::load(), HttpCache::load())
+  }
+}
+
+impl HttpClient {
+  //tests use a cookie jar and cache that aren't saved anywhere, so they don't touch the user's
+  pub(crate) fn new(cookie_jar: CookieJar, cache: HttpCache) -> Self {
     //we lie cause otherwise people block us. can't be honest no more
-    let client = Client::builder().user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.3").build().unwrap();
-    let no_redirect_client = Client::builder().user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.3").redirect(reqwest::redirect::Policy::none()).build().unwrap();
+    //redirects are followed manually, so cookies set along the way don't get lost
+    let client = Client::builder().user_agent("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.3").redirect(reqwest::redirect::Policy::none()).build().unwrap();
     Self {
       client,
-      no_redirect_client,
+      cookie_jar: Arc::new(Mutex::new(cookie_jar)),
+      cache: Arc::new(Mutex::new(cache)),
     }
   }
-}
-
-fn serialise_cookies(cookies: &HashMap<String, String>) -> String {
-  let mut c_header = String::new();
-  for (name, value) in cookies {
-    c_header += &format!("{}{}={}", if !c_header.is_empty() {
-      "; "
-    } else {
-      ""
-    }, name, value);
-  }
-  c_header
-}
 
-impl HttpClient {
-  //the second return value, the final url, may differ from the input url, because of redirects
-  pub fn get(&self, url: &str, cookies: Option<&HashMap<String, String>>) -> Option<(String, String)> {
-    let mut req = self.client.get(url);
+  fn with_cookies(&self, req: RequestBuilder, url: &Url) -> RequestBuilder {
     //nom nom nom
-    if let Some(cookies) = cookies {
-      let c_header = serialise_cookies(cookies);
-      //set cookie header
-      if !c_header.is_empty() {
-        req = req.header("Cookie", c_header);
-      }
-    }
-    if let Ok(resp) = req.send() {
-      let final_url = resp.url().as_str().to_string();
-      if let Ok(text) = resp.text() {
-        return Some((text, final_url));
-      }
+    if let Some(c_header) = self.cookie_jar.lock().unwrap().cookie_header(url) {
+      req.header("Cookie", c_header)
+    } else {
+      req
     }
-    None
   }
 
-  //todo: POST for form submit for cookies
-  pub fn post(&self, url: Url, body: String, from_url: Url, cookies: Option<&HashMap<String, String>>) -> Option<(Url, Vec<(String, String)>)> {
+  //send the request, following redirects (as GETs) and storing any cookies along the way
+  //returns the last response and its url
+  fn send(&self, req: RequestBuilder, url: Url) -> Result<(reqwest::blocking::Response, Url), HttpError> {
     let mut url = url;
-    let mut req = self.no_redirect_client.post(url.to_string()).body(body).header("Content-Type", "application/x-www-form-urlencoded").header("Origin", format!("https://{}", from_url.hostname));
-    if let Some(cookies) = cookies {
-      let c_header = serialise_cookies(cookies);
-      if !c_header.is_empty() {
-        req = req.header("Cookie", c_header);
-      }
-    }
-    let mut cookies: Vec<(String, String)> = Vec::new();
+    let mut req = self.with_cookies(req, &url);
     let mut redirect_count = 0;
     loop {
-      if redirect_count > 5 {
-        break;
-      }
-      if let Ok(resp) = req.send() {
-        let c_headers = resp.headers().get_all("Set-Cookie");
+      let resp = req.send()?;
+      let c_headers = resp.headers().get_all("Set-Cookie");
+      if c_headers.iter().next().is_some() {
+        let mut cookie_jar = self.cookie_jar.lock().unwrap();
         for header in c_headers {
           if let Ok(value) = header.to_str() {
-            let mut parts = value.split(";").next().unwrap().split("=");
-            let name = parts.next().unwrap_or_default().to_string();
-            let value = get_rest_of_split(&mut parts, Some("="));
-            cookies.push((name, value));
+            cookie_jar.set_cookie(value, &url);
           }
         }
-        if resp.status().is_redirection() {
-          redirect_count += 1;
-          //follow location resp header
-          if let Some(location) = resp.headers().get("Location") {
-            url = Url::new_maybe_relative(location.to_str().unwrap_or_default().to_string(), url);
-            req = self.no_redirect_client.get(url.to_string());
-            continue;
+        cookie_jar.save();
+      }
+      if resp.status().is_redirection() {
+        //follow location resp header
+        if let Some(location) = resp.headers().get("Location") {
+          if redirect_count == MAX_REDIRECTS {
+            return Err(HttpError::TooManyRedirects);
           }
+          redirect_count += 1;
+          url = url.resolve(location.to_str().unwrap_or_default());
+          req = self.with_cookies(self.client.get(url.to_string()), &url);
+          continue;
+        }
+      }
+      return Ok((resp, url));
+    }
+  }
+
+  //non-success statuses are errors
+  fn finish(resp: reqwest::blocking::Response, url: Url) -> Result<Response, HttpError> {
+    let status = resp.status();
+    if !status.is_success() {
+      return Err(HttpError::Status(status.as_u16()));
+    }
+    let content_type = resp.headers().get("Content-Type").and_then(|c| c.to_str().ok()).map(|c| c.to_string());
+    let attachment = resp.headers().get("Content-Disposition").and_then(|c| c.to_str().ok()).is_some_and(|c| c.trim_start().to_ascii_lowercase().starts_with("attachment"));
+    //no point reading (maybe huge) files that are going to be saved, when they can be streamed to the file
+    let (body, stream) = if content_type.as_deref().is_none_or(is_text) && !attachment {
+      (decode(&resp.bytes()?, content_type.as_deref()), None)
+    } else {
+      (String::new(), Some(BodyStream::new(resp, &url)))
+    };
+    Ok(Response {
+      status: status.as_u16(),
+      body,
+      stream,
+      content_type,
+      attachment,
+      url,
+    })
+  }
+
+  fn fetch(&self, url: &Url, use_cache: bool) -> Result<Response, HttpError> {
+    let key = url.to_string();
+    let mut req = self.client.get(url.to_string());
+    if use_cache {
+      if let Some((entry, body)) = self.cache.lock().unwrap().lookup(&key) {
+        if entry.is_fresh(now()) {
+          return Ok(Response {
+            status: 200,
+            body,
+            stream: None,
+            content_type: entry.content_type,
+            attachment: false,
+            url: Url::new(entry.final_url),
+          });
+        }
+        //stale, but the server can tell us if it's still good
+        if let Some(etag) = &entry.etag {
+          req = req.header("If-None-Match", etag);
         }
-        return Some((url, cookies)); //break out
-      } else {
+        if let Some(last_modified) = &entry.last_modified {
+          req = req.header("If-Modified-Since", last_modified);
+        }
+      }
+    } else {
+      //tell any caches along the way to not use what they have either
+      req = req.header("Cache-Control", "no-cache").header("Pragma", "no-cache");
+    }
+    let (resp, final_url) = self.send(req, url.clone())?;
+    if resp.status() == StatusCode::NOT_MODIFIED {
+      if let Some((entry, body)) = self.cache.lock().unwrap().revalidated(&key, resp.headers(), now()) {
+        return Ok(Response {
+          status: 200,
+          body,
+          stream: None,
+          content_type: entry.content_type,
+          attachment: false,
+          url: Url::new(entry.final_url),
+        });
+      }
+    }
+    let headers = resp.headers().clone();
+    let resp = Self::finish(resp, final_url)?;
+    if resp.status == 200 && resp.content_type.as_deref().is_none_or(is_text) && !resp.attachment {
+      self.cache.lock().unwrap().store(&key, &resp.url.to_string(), &headers, &resp.body, now());
+    }
+    Ok(resp)
+  }
+
+  /// Fetch a page, following redirects. Fresh responses come from the cache.
+  pub fn get(&self, url: &Url) -> Result<Response, HttpError> {
+    self.fetch(url, true)
+  }
+
+  /// Like [`HttpClient::get`], but always goes to the server, ignoring (though still updating) the cache.
+  pub fn get_uncached(&self, url: &Url) -> Result<Response, HttpError> {
+    self.fetch(url, false)
+  }
+
+  /// Download url into `dir`, whether it would be shown or not (see [`BodyStream::save`]). Returns where it was saved.
+  pub fn download(&self, url: &Url, dir: &Path, progress: impl FnMut(&Path, u64, Option<u64>) -> bool) -> Result<PathBuf, HttpError> {
+    let (resp, url) = self.send(self.client.get(url.to_string()), url.clone())?;
+    if !resp.status().is_success() {
+      return Err(HttpError::Status(resp.status().as_u16()));
+    }
+    BodyStream::new(resp, &url).save(dir, progress)
+  }
+
+  /// Submit a form (`body` is application/x-www-form-urlencoded) from the page at `from_url`, following redirects.
+  pub fn post(&self, url: Url, body: String, from_url: &Url) -> Result<Response, HttpError> {
+    let req = self.client.post(url.to_string()).body(body).header("Content-Type", "application/x-www-form-urlencoded").header("Origin", format!("{}://{}", from_url.scheme.as_deref().unwrap_or("https"), from_url.host_str()));
+    let (resp, url) = self.send(req, url)?;
+    Self::finish(resp, url)
+  }
+}
+
+//the filename (or filename*, which can be non-ascii) parameter of a Content-Disposition header, without any path
+fn disposition_filename(disposition: &str) -> Option<String> {
+  let mut filename = None;
+  for param in disposition.split(';').skip(1) {
+    let Some((name, value)) = param.split_once('=') else {
+      continue;
+    };
+    let name = name.trim().to_ascii_lowercase();
+    let value = value.trim();
+    if name == "filename*" {
+      //eg: UTF-8''%E9%84%AD.txt. always percent encoded utf-8 in practice
+      if let Some(encoded) = value.splitn(3, '\'').nth(2) {
+        filename = Some(percent_decode(encoded));
         break;
       }
+    } else if name == "filename" {
+      filename = Some(value.trim_matches('"').to_string());
     }
-    None
   }
+  filename.map(|f| sanitise_filename(&f)).filter(|f| !f.is_empty())
+}
+
+//the last segment of the url's path, or "download" if there is none
+fn url_filename(url: &Url) -> String {
+  let last = url.path.rsplit('/').next().unwrap_or("");
+  Some(sanitise_filename(&percent_decode(last))).filter(|f| !f.is_empty()).unwrap_or("download".to_string())
+}
+
+//servers don't get to pick the directory, or make hidden files
+fn sanitise_filename(name: &str) -> String {
+  let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+  name.trim_start_matches('.').chars().filter(|c| !c.is_control()).collect()
+}
+
+//serve one canned response on localhost
+#[cfg(test)]
+pub(crate) fn serve_once(response: &'static str) -> Url {
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+  std::thread::spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut buf = [0; 1024];
+    let _ = std::io::Read::read(&mut stream, &mut buf);
+    std::io::Write::write_all(&mut stream, response.as_bytes()).unwrap();
+  });
+  Url::new(format!("http://127.0.0.1:{}/", port))
+}
+
+#[test]
+fn test_http_errors() {
+  let client = HttpClient::new(Default::default(), Default::default());
+  let url = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi");
+  let resp = client.get(&url).unwrap();
+  assert_eq!((resp.status, resp.body.as_str()), (200, "hi"));
+  let url = serve_once("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+  let err = client.get(&url).unwrap_err();
+  assert_eq!(err, HttpError::Status(404));
+  assert_eq!(err.to_string(), "404 Not Found");
+  //nothing listening once the listener is dropped
+  let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+  let err = client.get(&Url::new(format!("http://127.0.0.1:{}/", port))).unwrap_err();
+  assert!(matches!(err, HttpError::Connect(_)), "{:?}", err);
+}
+
+#[test]
+fn test_download() {
+  let client = HttpClient::new(Default::default(), Default::default());
+  let response = "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Disposition: inline; filename=\"../.koxinga.png\"\r\nContent-Length: 4\r\nConnection: close\r\n\r\nPNG!";
+  //binary bodies aren't read, and neither are attachments
+  let resp = client.get(&serve_once(response)).unwrap();
+  assert_eq!((resp.content_type.as_deref(), resp.body.as_str(), resp.attachment), (Some("image/png"), "", false));
+  //but kept, to save without fetching it again
+  let stream = resp.stream.unwrap();
+  assert_eq!((stream.filename.as_str(), stream.size), ("koxinga.png", Some(4)));
+  let resp = client.get(&serve_once("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Disposition: attachment\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi")).unwrap();
+  assert_eq!((resp.body.as_str(), resp.attachment), ("", true));
+  let dir = std::env::temp_dir().join(format!("koxinga-download-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  let mut sizes = Vec::new();
+  assert_eq!(client.download(&serve_once(response), &dir, |_, received, size| {
+    sizes.push((received, size));
+    true
+  }).unwrap(), dir.join("koxinga.png"));
+  assert_eq!(sizes, vec![(0, Some(4)), (4, Some(4))]);
+  assert_eq!(std::fs::read(dir.join("koxinga.png")).unwrap(), b"PNG!");
+  assert_eq!(stream.save(&dir, |_, _, _| true).unwrap(), dir.join("koxinga (1).png"));
+  assert_eq!(std::fs::read(dir.join("koxinga (1).png")).unwrap(), b"PNG!");
+  //cancelled downloads don't leave anything behind
+  assert_eq!(client.download(&serve_once(response), &dir, |_, _, _| false), Err(HttpError::Cancelled));
+  assert!(!dir.join("koxinga (2).png").exists());
+  assert_eq!(disposition_filename("attachment; filename*=UTF-8''%E9%84%AD.txt; filename=\"fallback.txt\""), Some("鄭.txt".to_string()));
+  assert_eq!(url_filename(&Url::new("https://example.org/".to_string())), "download");
+  let _ = std::fs::remove_dir_all(&dir);
 }
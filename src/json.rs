@@ -0,0 +1,321 @@
+//! Parsing json, and pretty printing it as [`OutputType`]s, with objects and arrays that can be folded away.
+//!
+//! Each object and array has a toggle link, to `#fold-<n>` (where n counts objects and arrays in the order they start), so following it can fold or unfold it (see [`crate::document::Document::toggle`]).
+
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::xml::OutputType;
+
+const INDENT: usize = 26; //two columns in a dump
+const MAX_DEPTH: usize = 512; //parsing, printing and counting all recurse, so deeper would risk overflowing the stack
+
+/// Prefix of the fragment of fold toggle links.
+pub const FOLD_PREFIX: &str = "fold-";
+
+/// A json value. Numbers are kept as written, and object keys in order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+  /// `null`
+  Null,
+  /// `true` or `false`
+  Bool(bool),
+  /// A number, as written.
+  Number(String),
+  /// A string, unescaped.
+  String(String),
+  /// An array.
+  Array(Vec<Json>),
+  /// An object's keys and values, in the order they were in.
+  Object(Vec<(String, Json)>),
+}
+
+struct Parser<'a> {
+  text: &'a str,
+  chars: Peekable<CharIndices<'a>>,
+  depth: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn error(&mut self, expected: &str) -> String {
+    match self.chars.peek() {
+      Some((i, c)) => format!("expected {} at byte {}, found '{}'", expected, i, c),
+      None => format!("expected {}, found the end", expected),
+    }
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.chars.next_if(|(_, c)| c.is_ascii_whitespace()).is_some() {}
+  }
+
+  fn eat(&mut self, c: char) -> bool {
+    self.chars.next_if(|(_, n)| *n == c).is_some()
+  }
+
+  fn expect(&mut self, c: char) -> Result<(), String> {
+    if self.eat(c) {
+      Ok(())
+    } else {
+      Err(self.error(&format!("'{}'", c)))
+    }
+  }
+
+  fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+    for c in keyword.chars() {
+      self.expect(c)?;
+    }
+    Ok(value)
+  }
+
+  fn value(&mut self) -> Result<Json, String> {
+    self.skip_whitespace();
+    if let Some((i, '{' | '[')) = self.chars.peek() {
+      if self.depth == MAX_DEPTH {
+        return Err(format!("nested too deeply at byte {}", i));
+      }
+    }
+    let value = match self.chars.peek().map(|(_, c)| *c) {
+      Some('{') => {
+        self.chars.next();
+        self.depth += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if !self.eat('}') {
+          loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            if self.eat('}') {
+              break;
+            }
+            self.expect(',')?;
+          }
+        }
+        self.depth -= 1;
+        Json::Object(members)
+      },
+      Some('[') => {
+        self.chars.next();
+        self.depth += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if !self.eat(']') {
+          loop {
+            items.push(self.value()?);
+            if self.eat(']') {
+              break;
+            }
+            self.expect(',')?;
+          }
+        }
+        self.depth -= 1;
+        Json::Array(items)
+      },
+      Some('"') => Json::String(self.string()?),
+      Some('t') => self.keyword("true", Json::Bool(true))?,
+      Some('f') => self.keyword("false", Json::Bool(false))?,
+      Some('n') => self.keyword("null", Json::Null)?,
+      Some(c) if c == '-' || c.is_ascii_digit() => {
+        let start = self.chars.peek().unwrap().0;
+        let mut end = start;
+        while let Some((i, c)) = self.chars.next_if(|(_, c)| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+          end = i + c.len_utf8();
+        }
+        let number = &self.text[start..end];
+        if number.parse::<f64>().is_err() {
+          return Err(format!("invalid number {} at byte {}", number, start));
+        }
+        Json::Number(number.to_string())
+      },
+      _ => return Err(self.error("a value")),
+    };
+    self.skip_whitespace();
+    Ok(value)
+  }
+
+  fn hex4(&mut self) -> Result<u32, String> {
+    let mut n = 0;
+    for _ in 0..4 {
+      let Some(digit) = self.chars.peek().and_then(|(_, c)| c.to_digit(16)) else {
+        return Err(self.error("a hex digit"));
+      };
+      self.chars.next();
+      n = n * 16 + digit;
+    }
+    Ok(n)
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    self.expect('"')?;
+    let mut s = String::new();
+    loop {
+      match self.chars.next().map(|(_, c)| c) {
+        Some('"') => return Ok(s),
+        Some('\\') => {
+          let escaped = match self.chars.next().map(|(_, c)| c) {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('u') => {
+              let mut n = self.hex4()?;
+              //surrogate pairs are two escapes
+              if (0xD800..0xDC00).contains(&n) && self.eat('\\') && self.eat('u') {
+                let low = self.hex4()?;
+                n = 0x10000 + ((n - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+              }
+              char::from_u32(n).unwrap_or('\u{FFFD}')
+            },
+            Some(c) => c, //", \ and /
+            None => return Err(self.error("an escape")),
+          };
+          s.push(escaped);
+        },
+        Some(c) => s.push(c),
+        None => return Err(self.error("'\"'")),
+      }
+    }
+  }
+}
+
+/// Parse json text. The error says what was expected where.
+pub fn parse_json(text: &str) -> Result<Json, String> {
+  let mut parser = Parser { text, chars: text.char_indices().peekable(), depth: 0 };
+  let value = parser.value()?;
+  if parser.chars.peek().is_some() {
+    return Err(parser.error("the end"));
+  }
+  Ok(value)
+}
+
+//a string, quoted and escaped as it would be in json
+fn quote(s: &str) -> String {
+  let mut quoted = "\"".to_string();
+  for c in s.chars() {
+    match c {
+      '"' => quoted += "\\\"",
+      '\\' => quoted += "\\\\",
+      '\n' => quoted += "\\n",
+      '\t' => quoted += "\\t",
+      '\r' => quoted += "\\r",
+      c if c.is_control() => quoted += &format!("\\u{:04x}", c as u32),
+      c => quoted.push(c),
+    }
+  }
+  quoted + "\""
+}
+
+fn push_value(outputs: &mut Vec<OutputType>, value: &Json, depth: usize, comma: &str, collapsed: &HashSet<usize>, counter: &mut usize) {
+  let (open, close, len) = match value {
+    Json::Array(items) => ("[", "]", items.len()),
+    Json::Object(members) => ("{", "}", members.len()),
+    Json::Null => {
+      outputs.push(OutputType::Text(format!("null{}", comma)));
+      return;
+    },
+    Json::Bool(b) => {
+      outputs.push(OutputType::Text(format!("{}{}", b, comma)));
+      return;
+    },
+    Json::Number(n) => {
+      outputs.push(OutputType::Text(format!("{}{}", n, comma)));
+      return;
+    },
+    Json::String(s) => {
+      outputs.push(OutputType::Text(format!("{}{}", quote(s), comma)));
+      return;
+    },
+  };
+  let id = *counter;
+  *counter += 1;
+  if len == 0 {
+    outputs.push(OutputType::Text(format!("{}{}{}", open, close, comma)));
+    return;
+  }
+  let folded = collapsed.contains(&id);
+  //quoted, like an href
+  outputs.push(OutputType::StartLink(format!("\"#{}{}\"", FOLD_PREFIX, id)));
+  outputs.push(OutputType::Text(if folded { "[+]" } else { "[-]" }.to_string()));
+  outputs.push(OutputType::EndLink);
+  if folded {
+    let what = if let Json::Array(_) = value { "item" } else { "key" };
+    outputs.push(OutputType::Text(format!(" {} {} {}{} {}{}", open, len, what, if len == 1 { "" } else { "s" }, close, comma)));
+    //the ids inside still need using up, so the ones after stay the same
+    *counter += count_containers(value) - 1;
+    return;
+  }
+  outputs.push(OutputType::Text(format!(" {}", open)));
+  outputs.push(OutputType::Newline);
+  outputs.push(OutputType::Indent((depth + 1) * INDENT));
+  let push_child = |outputs: &mut Vec<OutputType>, i: usize, key: Option<&str>, child: &Json, counter: &mut usize| {
+    if let Some(key) = key {
+      outputs.push(OutputType::Text(format!("{}: ", quote(key))));
+    }
+    push_value(outputs, child, depth + 1, if i + 1 < len { "," } else { "" }, collapsed, counter);
+    outputs.push(OutputType::Newline);
+    //a nested value may have changed the indent
+    outputs.push(OutputType::Indent((depth + 1) * INDENT));
+  };
+  match value {
+    Json::Array(items) => for (i, item) in items.iter().enumerate() {
+      push_child(outputs, i, None, item, counter);
+    },
+    Json::Object(members) => for (i, (key, member)) in members.iter().enumerate() {
+      push_child(outputs, i, Some(key), member, counter);
+    },
+    _ => {},
+  }
+  outputs.push(OutputType::Indent(depth * INDENT));
+  outputs.push(OutputType::Text(format!("{}{}", close, comma)));
+}
+
+//number of objects and arrays in value, including itself
+fn count_containers(value: &Json) -> usize {
+  match value {
+    Json::Array(items) => 1 + items.iter().map(count_containers).sum::<usize>(),
+    Json::Object(members) => 1 + members.iter().map(|(_, m)| count_containers(m)).sum::<usize>(),
+    _ => 0,
+  }
+}
+
+/// Pretty print `value`, one item or key per line, indented by depth. Objects and arrays whose number is in `collapsed` are folded to one line.
+pub fn json_outputs(value: &Json, collapsed: &HashSet<usize>) -> Vec<OutputType> {
+  let mut outputs = Vec::new();
+  push_value(&mut outputs, value, 0, "", collapsed, &mut 0);
+  outputs.push(OutputType::Newline);
+  outputs
+}
+
+#[test]
+fn test_parse_json() {
+  assert_eq!(parse_json(" {\"a\": [1, -2.5e3, true, null], \"b\\u00e9\\ud83d\\ude00\": \"x\\\"\\n\"} "), Ok(Json::Object(vec![
+    ("a".to_string(), Json::Array(vec![Json::Number("1".to_string()), Json::Number("-2.5e3".to_string()), Json::Bool(true), Json::Null])),
+    ("bé😀".to_string(), Json::String("x\"\n".to_string())),
+  ])));
+  assert_eq!(parse_json("[]"), Ok(Json::Array(Vec::new())));
+  assert_eq!(parse_json("[1,]"), Err("expected a value at byte 3, found ']'".to_string()));
+  assert_eq!(parse_json("{\"a\" 1}"), Err("expected ':' at byte 5, found '1'".to_string()));
+  assert_eq!(parse_json("[1] 2"), Err("expected the end at byte 4, found '2'".to_string()));
+  assert_eq!(parse_json("\"open"), Err("expected '\"', found the end".to_string()));
+  //deep enough to overflow the stack is an error instead
+  assert!(parse_json(&("[".repeat(512) + &"]".repeat(512))).is_ok());
+  assert_eq!(parse_json(&"[{\"a\":".repeat(100_000)), Err("nested too deeply at byte 1536".to_string()));
+}
+
+#[test]
+fn test_json_outputs() {
+  let value = parse_json("{\"a\": [1, {}], \"b\": {\"c\": \"d\"}}").unwrap();
+  let texts = |outputs: Vec<OutputType>| outputs.into_iter().filter_map(|o| match o {
+    OutputType::Text(t) => Some(t),
+    OutputType::StartLink(l) => Some(l),
+    OutputType::Newline => Some("\n".to_string()),
+    _ => None,
+  }).collect::<String>();
+  assert_eq!(texts(json_outputs(&value, &HashSet::new())), "\"#fold-0\"[-] {\n\"a\": \"#fold-1\"[-] [\n1,\n{}\n],\n\"b\": \"#fold-3\"[-] {\n\"c\": \"d\"\n}\n}\n");
+  //folding the array keeps the numbers of what comes after the same
+  assert_eq!(texts(json_outputs(&value, &HashSet::from([1]))), "\"#fold-0\"[-] {\n\"a\": \"#fold-1\"[+] [ 2 items ],\n\"b\": \"#fold-3\"[-] {\n\"c\": \"d\"\n}\n}\n");
+}
@@ -0,0 +1,760 @@
+//! Laying out [`OutputType`]s into lines of positioned text, independent of any particular drawing or fonts.
+//!
+//! Widths come from a `measure` function given by the caller, in whatever units it likes (pixels for the window, columns for [`crate::dump`]), and so do line heights, since text in different [`Style`]s can be different sizes.
+//! Lines start at x = 2, and the first starts at y = 2. Each line is as tall as the tallest text on it (or text in the default style, if it is empty).
+
+use std::collections::HashMap;
+
+use unicode_linebreak::{ linebreaks, BreakOpportunity };
+
+use crate::xml::{ Node, OutputType, Style, Table };
+
+/// Usual distance between the tops of two lines, of text in the default style.
+pub const LINE_HEIGHT: usize = 18;
+
+const BORDER_SPACE: usize = 4; //extra space above each row of a table with borders, for the border
+
+/// What a piece of laid out text is, so frontends can colour it differently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subtype {
+  /// Plain text.
+  Text,
+  /// Text of a link.
+  Link,
+  /// A text input (or a named submit button).
+  TextInput,
+  /// A form's submit button.
+  Button,
+  //
+}
+
+impl Subtype {
+  /// Whether the subtype only applies to the one output, rather than lasting until something ends it (like [`OutputType::EndLink`]).
+  pub fn is_one_off(&self) -> bool {
+    //button, text input, stuff that we don't expect other subtypes to be in (well, buttons might, but whatever)
+    self == &Subtype::TextInput || self == &Subtype::Button
+  }
+}
+
+/// A laid out page.
+#[derive(Debug, Default)]
+pub struct Layout {
+  /// x, y, text, subtype and style of each run of text. A line may have several runs.
+  pub page: Vec<(usize, usize, String, Subtype, Style)>,
+  /// Number of lines.
+  pub line_count: usize,
+  /// Furthest x anything on the page reaches. Unless laid out with wrapping, this can be past the width given, eg by long lines of preformatted text.
+  pub width: usize,
+  /// y of the top of each line, then where the last line ends. See [`line_at_y`].
+  pub line_ys: Vec<usize>,
+  /// For each line, the number of chars placed before it. Unlike line numbers, these don't change when the width does, so they can be used to keep the same text in view.
+  pub line_offsets: Vec<usize>,
+  /// Line each element id (from [`OutputType::Anchor`]) is on.
+  pub anchors: HashMap<String, usize>,
+  /// x, y, width and height of the lines of table borders, to be filled in.
+  pub borders: Vec<(usize, usize, usize, usize)>,
+}
+
+/// The line that y is on, given the tops of the lines (see [`Layout::line_ys`]).
+pub fn line_at_y(line_ys: &[usize], y: usize) -> usize {
+  line_ys.partition_point(|line_y| *line_y <= y).saturating_sub(1)
+}
+
+/// The title (from `<head>`) and outputs of the body of a parsed page. If there is no `<body>`, the outputs are of whatever there is.
+pub fn page_outputs(top_level_nodes: &[Box<Node>]) -> (Option<String>, Vec<OutputType>) {
+  let mut title = None;
+  let mut outputs = Vec::new();
+  let html_index = top_level_nodes.iter().position(|n| n.tag_name == "html");
+  if let Some(html_index) = html_index {
+    for n in &top_level_nodes[html_index].children {
+      if n.tag_name == "head" {
+        //look for title, if any
+        for hn in &n.children {
+          if hn.tag_name == "title" && hn.children.len() > 0 && hn.children[0].text_node {
+            title = Some(hn.children[0].tag_name.clone());
+          }
+        }
+      } else if n.tag_name == "body" {
+        outputs = n.to_output();
+        break;
+      }
+    }
+    //handle if no <body> tag (wtf wikimedia error page)
+    if outputs.is_empty() {
+      //hey, why not at that point...
+      outputs = top_level_nodes[html_index].to_output();
+    }
+  } else {
+    //not even an <html>, probably a snippet
+    for n in top_level_nodes {
+      outputs.extend(n.to_output());
+    }
+  }
+  (title, outputs)
+}
+
+const TAB_WIDTH: usize = 8;
+
+//tabs to spaces, up to the next tab stop. col is the column the text starts at
+fn expand_tabs(s: &str, col: &mut usize) -> String {
+  let mut expanded = String::new();
+  for c in s.chars() {
+    if c == '\t' {
+      let spaces = TAB_WIDTH - *col % TAB_WIDTH;
+      expanded += &" ".repeat(spaces);
+      *col += spaces;
+    } else if c == '\n' {
+      expanded.push(c);
+      *col = 0;
+    } else {
+      expanded.push(c);
+      *col += 1;
+    }
+  }
+  expanded
+}
+
+//leading and trailing whitespace is probably a mistake, so at most one space
+fn normalise_text(s: &str) -> String {
+  //no font has a glyph for non-breaking spaces, and outside preformatted text a newline is just somewhere the line can break
+  let s = s.replace(['\u{A0}', '\n', '\r'], " ");
+  let start = if s.starts_with(" ") {
+    " "
+  } else {
+    ""
+  };
+  let end = if s.ends_with(" ") {
+    " "
+  } else {
+    ""
+  };
+  start.to_string() + s.trim() + end
+}
+
+/// Lay out `outputs` into lines no wider than `width`, breaking them at line break opportunities (unicode annex #14).
+///
+/// `measure` gives the width of a char in a style, and `line_height` the height of a line of text in a style. `label` gives the text shown for outputs other than [`OutputType::Text`], if any (eg link numbers, inputs, submit buttons).
+///
+/// Tables are laid out in columns as wide as their cells need, shrunk to fit if the page is too narrow.
+///
+/// If `wrap` is true, everything is made to fit `width`: preformatted text is wrapped like any other, and tables too wide even when shrunk are laid out one cell after another. Otherwise preformatted lines only end where the text says, and tables stay tables, so both can go past `width` (see [`Layout::width`]).
+pub fn layout(outputs: Vec<OutputType>, width: usize, wrap: bool, mut measure: impl FnMut(char, Style) -> usize, mut line_height: impl FnMut(Style) -> usize, mut label: impl FnMut(&OutputType) -> Option<String>) -> Layout {
+  let mut metrics = Metrics { measure: &mut measure, line_height: &mut line_height };
+  let wrapper = lay_out(outputs, width, wrap, &mut metrics, &mut label);
+  let mut anchors = HashMap::new();
+  for (id, line) in wrapper.placed_anchors {
+    //first element with the id wins
+    anchors.entry(id).or_insert(line);
+  }
+  Layout {
+    page: wrapper.page,
+    line_count: wrapper.line_count,
+    width: wrapper.widest,
+    line_ys: wrapper.line_ys,
+    line_offsets: wrapper.line_offsets,
+    anchors,
+    borders: wrapper.borders,
+  }
+}
+
+//the caller's measuring functions. tables lay out their cells with them too, so they're trait objects rather than generic
+struct Metrics<'a> {
+  measure: &'a mut dyn FnMut(char, Style) -> usize,
+  line_height: &'a mut dyn FnMut(Style) -> usize,
+}
+
+fn lay_out(outputs: Vec<OutputType>, width: usize, wrap: bool, metrics: &mut Metrics, label: &mut dyn FnMut(&OutputType) -> Option<String>) -> LineWrapper {
+  let mut wrapper = LineWrapper::new(width, wrap);
+  wrapper.place_outputs(outputs, metrics, label);
+  wrapper.place_word(metrics);
+  wrapper.finish_line(metrics);
+  wrapper
+}
+
+//how narrow outputs could be laid out without breaking words, and how wide they would be without wrapping at all
+fn measure(outputs: Vec<OutputType>, wrap: bool, metrics: &mut Metrics) -> (usize, usize) {
+  let mut wrapper = LineWrapper::new(usize::MAX / 2, wrap);
+  wrapper.measuring = true;
+  wrapper.place_outputs(outputs, metrics, &mut |_| None);
+  wrapper.place_word(metrics);
+  (wrapper.longest_word, wrapper.widest - 2)
+}
+
+//lays out text into lines, breaking them at line break opportunities (unicode annex #14)
+//text is placed a word (everything up to the next break opportunity) at a time, so a word can span several outputs, eg a link inside a word
+struct LineWrapper {
+  width: usize,
+  wrap: bool, //whether preformatted text and wide tables are made to fit width too
+  page: Vec<(usize, usize, String, Subtype, Style)>, //x, y, text, subtype, style
+  line_offsets: Vec<usize>, //number of chars placed before the start of each line
+  line_ys: Vec<usize>, //top of each line, then the bottom of the last
+  borders: Vec<(usize, usize, usize, usize)>, //x, y, width, height
+  line_count: usize,
+  indent: usize,
+  x: usize,
+  y: usize,
+  tallest: usize, //height of the tallest text on the line so far
+  widest: usize, //furthest x any text (or table) has reached
+  nowrap: bool, //in preformatted text, lines only end where the text says
+  longest_word: usize, //width of the widest word (plus indent), so the narrowest this could be laid out without breaking words
+  measuring: bool, //only longest_word and widest matter, so tables aren't laid out, just measured
+  line: String, //text not yet added to page, all the same subtype and style
+  line_x: usize,
+  line_subtype: Subtype,
+  line_style: Style,
+  chars_placed: usize,
+  word: Vec<(char, Subtype, Style)>, //not yet placed, since we don't know if the word continues in the next output
+  anchors: Vec<String>, //not yet placed, they go on the line of the next char placed
+  placed_anchors: Vec<(String, usize)>, //id, line number
+}
+
+impl LineWrapper {
+  fn new(width: usize, wrap: bool) -> Self {
+    Self {
+      width,
+      wrap,
+      page: Vec::new(),
+      line_offsets: vec![0],
+      line_ys: vec![2],
+      borders: Vec::new(),
+      line_count: 0,
+      indent: 0,
+      x: 2,
+      y: 2,
+      tallest: 0,
+      widest: 2,
+      nowrap: false,
+      longest_word: 0,
+      measuring: false,
+      line: String::new(),
+      line_x: 2,
+      line_subtype: Subtype::Text,
+      line_style: Style::default(),
+      chars_placed: 0,
+      word: Vec::new(),
+      anchors: Vec::new(),
+      placed_anchors: Vec::new(),
+    }
+  }
+
+  fn place_outputs(&mut self, outputs: Vec<OutputType>, metrics: &mut Metrics, label: &mut dyn FnMut(&OutputType) -> Option<String>) {
+    let mut subtype = Subtype::Text;
+    let mut styles: Vec<Style> = Vec::new(); //each is the style of everything started so far
+    let mut preformatted = false;
+    let mut column = 0; //only kept track of in preformatted text, for tabs
+    for o in outputs {
+      let output_string = match &o {
+        OutputType::Text(s) if preformatted => Some(expand_tabs(s, &mut column)),
+        OutputType::Text(s) => Some(normalise_text(s)),
+        OutputType::StartLink(_) => {
+          subtype = Subtype::Link;
+          label(&o)
+        },
+        OutputType::Form(_) => {
+          //yeah, in future properly render the submit button
+          subtype = Subtype::Button;
+          label(&o)
+        },
+        OutputType::TextInput(_, _) => {
+          subtype = Subtype::TextInput;
+          label(&o)
+        },
+        _ => None,
+      };
+      if let Some(s) = output_string {
+        let style = styles.last().copied().unwrap_or_default();
+        let style = if preformatted { style.add(&Style { monospace: true, ..Style::default() }) } else { style };
+        self.add_text(&s, subtype, style, metrics);
+      }
+      if subtype.is_one_off() {
+        //so button and textinput subtypes don't persist
+        //really we should allow multiple subtypes at once or something, idk
+        //but this is fine for now
+        subtype = Subtype::Text;
+      }
+      if let OutputType::Anchor(id) = o {
+        self.anchors.push(id);
+      } else if let OutputType::Indent(space) = o {
+        self.place_word(metrics);
+        self.indent = space;
+      } else if o == OutputType::Newline {
+        self.place_word(metrics);
+        self.newline(metrics);
+        column = 0;
+      } else if let OutputType::Preformatted(start) = o {
+        self.place_word(metrics);
+        preformatted = start;
+        self.nowrap = start && !self.wrap;
+        column = 0;
+      } else if o == OutputType::EndLink {
+        subtype = Subtype::Text;
+      } else if let OutputType::StartStyle(style) = o {
+        styles.push(styles.last().copied().unwrap_or_default().add(&style));
+      } else if o == OutputType::EndStyle {
+        styles.pop();
+      } else if let OutputType::Table(table) = o {
+        self.place_word(metrics);
+        self.place_table(table, metrics, label);
+      } else if let OutputType::ListMarker(marker) = o {
+        self.place_word(metrics);
+        self.place_marker(marker, styles.last().copied().unwrap_or_default(), metrics);
+      }
+    }
+  }
+
+  fn add_text(&mut self, s: &str, subtype: Subtype, style: Style, metrics: &mut Metrics) {
+    //break opportunities depend on what comes before, so include the unplaced word
+    let offset: usize = self.word.iter().map(|(c, _, _)| c.len_utf8()).sum();
+    let combined = self.word.iter().map(|(c, _, _)| c).collect::<String>() + s;
+    let mut start = 0;
+    for (i, opportunity) in linebreaks(&combined) {
+      if i < offset {
+        continue;
+      } else if i == offset {
+        //can break between the unplaced word and this text
+        self.place_word(metrics);
+        continue;
+      }
+      //the end of the text is always reported as a mandatory break, even if it isn't really one
+      let mandatory = opportunity == BreakOpportunity::Mandatory;
+      if i == combined.len() && !(mandatory && s.ends_with(['\n', '\r', '\x0B', '\x0C', '\u{85}', '\u{2028}', '\u{2029}'])) {
+        break;
+      }
+      self.word.extend(s[start..i - offset].chars().map(|c| (c, subtype, style)));
+      self.place_word(metrics);
+      if mandatory {
+        self.newline(metrics);
+      }
+      start = i - offset;
+    }
+    self.word.extend(s[start..].chars().map(|c| (c, subtype, style)));
+  }
+
+  fn place_word(&mut self, metrics: &mut Metrics) {
+    let word = std::mem::take(&mut self.word);
+    let widths: Vec<usize> = word.iter().map(|(c, _, style)| if c.is_control() { 0 } else { (metrics.measure)(*c, *style) }).collect();
+    //trailing whitespace is allowed to hang off the end of the line
+    let visible_len = word.len() - word.iter().rev().take_while(|(c, _, _)| c.is_whitespace()).count();
+    let visible_width: usize = widths[..visible_len].iter().sum();
+    let line_start = 2 + self.indent;
+    self.longest_word = self.longest_word.max(self.indent + visible_width);
+    if !self.nowrap && visible_width > 0 && self.x > line_start && self.x + visible_width > self.width {
+      self.newline(metrics);
+    }
+    for ((c, subtype, style), c_width) in word.into_iter().zip(widths) {
+      if c.is_control() {
+        continue;
+      }
+      if self.x == 2 {
+        self.x += self.indent;
+      }
+      //word is longer than a whole line, so break it wherever
+      if !self.nowrap && self.x > line_start && self.x + c_width > self.width && !c.is_whitespace() {
+        self.newline(metrics);
+        self.x += self.indent;
+      }
+      if subtype != self.line_subtype || style != self.line_style || self.line.is_empty() {
+        self.end_line();
+        self.line_x = self.x;
+        self.line_subtype = subtype;
+        self.line_style = style;
+        self.tallest = self.tallest.max((metrics.line_height)(style));
+      }
+      for id in self.anchors.drain(..) {
+        self.placed_anchors.push((id, self.line_count));
+      }
+      self.line.push(c);
+      self.x += c_width;
+      if !c.is_whitespace() {
+        self.widest = self.widest.max(self.x);
+      }
+      if self.nowrap {
+        //the whole line has to fit
+        self.longest_word = self.longest_word.max(self.x - 2);
+      }
+      self.chars_placed += 1;
+    }
+  }
+
+  //on a new line, hanging off to the left of the indent (unless there isn't room)
+  fn place_marker(&mut self, marker: String, style: Style, metrics: &mut Metrics) {
+    if self.x != 2 {
+      self.newline(metrics);
+    }
+    let width: usize = marker.chars().map(|c| (metrics.measure)(c, style)).sum();
+    let x = (2 + self.indent).saturating_sub(width).max(2);
+    for id in self.anchors.drain(..) {
+      self.placed_anchors.push((id, self.line_count));
+    }
+    self.tallest = self.tallest.max((metrics.line_height)(style));
+    self.chars_placed += marker.chars().count();
+    self.page.push((x, self.y, marker, Subtype::Text, style));
+    self.x = x + width;
+    self.widest = self.widest.max(self.x);
+    self.longest_word = self.longest_word.max(self.x - 2);
+  }
+
+  fn place_table(&mut self, table: Table, metrics: &mut Metrics, label: &mut dyn FnMut(&OutputType) -> Option<String>) {
+    if self.x != 2 {
+      self.newline(metrics);
+    }
+    //where each cell goes: row, column, cell
+    let mut cells = Vec::new();
+    let mut taken: Vec<Vec<bool>> = vec![Vec::new(); table.rows.len()];
+    let row_count = table.rows.len();
+    for (r, row) in table.rows.iter().enumerate() {
+      let mut c = 0;
+      for cell in row {
+        while taken[r].get(c) == Some(&true) {
+          c += 1;
+        }
+        for spanned in &mut taken[r..(r + cell.rowspan).min(row_count)] {
+          if spanned.len() < c + cell.colspan {
+            spanned.resize(c + cell.colspan, false);
+          }
+          spanned[c..c + cell.colspan].fill(true);
+        }
+        cells.push((r, c, cell));
+        c += cell.colspan;
+      }
+    }
+    let column_count = taken.iter().map(|t| t.len()).max().unwrap_or(0);
+    if column_count == 0 {
+      return;
+    }
+    //the space between columns, with the border down the middle
+    let gap = 2 * (metrics.measure)(' ', Style::default()).max(1);
+    let edges = if table.border { gap } else { 0 };
+    let available = self.width.saturating_sub(2 + self.indent + edges + (column_count - 1) * gap);
+    //narrowest each column can be without breaking words, and how wide it would be without wrapping at all
+    let (mut min_widths, mut max_widths) = (vec![0; column_count], vec![0; column_count]);
+    let cell_widths: Vec<(usize, usize)> = cells.iter().map(|(_, _, cell)| measure(cell.outputs.clone(), self.wrap, metrics)).collect();
+    //cells spanning several columns only widen them if they have to, so do the others first
+    let mut order: Vec<usize> = (0..cells.len()).collect();
+    order.sort_by_key(|i| cells[*i].2.colspan);
+    for i in order {
+      let (_, c, cell) = cells[i];
+      let spanned = c..c + cell.colspan;
+      let spanned_gaps = (cell.colspan - 1) * gap;
+      for (widths, needed) in [(&mut min_widths, cell_widths[i].0), (&mut max_widths, cell_widths[i].1)] {
+        let has = widths[spanned.clone()].iter().sum::<usize>() + spanned_gaps;
+        if needed > has {
+          let extra = needed - has;
+          for (j, width) in widths[spanned.clone()].iter_mut().enumerate() {
+            //the first columns get any remainder
+            *width += extra / cell.colspan + if j < extra % cell.colspan { 1 } else { 0 };
+          }
+        }
+      }
+    }
+    for (min, max) in min_widths.iter().zip(max_widths.iter_mut()) {
+      *max = (*max).max(*min);
+    }
+    let (min_total, max_total) = (min_widths.iter().sum::<usize>(), max_widths.iter().sum::<usize>());
+    if self.measuring {
+      //that's all a table in a cell being measured needs. laying out its cells too would make nested tables take twice as long each level down
+      let spacing = self.indent + edges + (column_count - 1) * gap;
+      self.longest_word = self.longest_word.max(spacing + min_total);
+      self.widest = self.widest.max(2 + spacing + max_total);
+      return;
+    }
+    if min_total > available && self.wrap {
+      //too wide to be a table
+      self.place_outputs(table.linearise(), metrics, label);
+      self.place_word(metrics);
+      if self.x != 2 {
+        self.newline(metrics);
+      }
+      return;
+    }
+    let widths: Vec<usize> = if max_total <= available {
+      max_widths
+    } else if min_total >= available {
+      //goes off the side however narrow it is
+      min_widths
+    } else {
+      //each column gets its minimum, then the rest is shared out by how much more they'd like
+      let (extra, wanted) = (available - min_total, max_total - min_total);
+      min_widths.iter().zip(&max_widths).map(|(min, max)| min + (max - min) * extra / wanted).collect()
+    };
+    let mut column_xs = vec![2 + self.indent + edges / 2];
+    for width in &widths[..column_count - 1] {
+      column_xs.push(column_xs.last().unwrap() + width + gap);
+    }
+    self.widest = self.widest.max(column_xs[column_count - 1] + widths[column_count - 1] + if table.border { edges / 2 + 1 } else { 0 });
+    //lay out the cells for real, in order so labels (eg link numbers) are in order too
+    let laid_out: Vec<LineWrapper> = cells.iter().map(|(_, c, cell)| {
+      let width = widths[*c..*c + cell.colspan].iter().sum::<usize>() + (cell.colspan - 1) * gap;
+      lay_out(cell.outputs.clone(), 2 + width, self.wrap, metrics, label)
+    }).collect();
+    //each row is as many lines as its tallest cell. cells spanning rows can use the lines of all of them
+    let used_lines = |wrapper: &LineWrapper| wrapper.page.iter().map(|p| line_at_y(&wrapper.line_ys, p.1) + 1).max().unwrap_or(0);
+    let mut row_lines = vec![1; row_count];
+    for ((r, _, cell), wrapper) in cells.iter().zip(&laid_out) {
+      if cell.rowspan == 1 {
+        row_lines[*r] = row_lines[*r].max(used_lines(wrapper));
+      }
+    }
+    for ((r, _, cell), wrapper) in cells.iter().zip(&laid_out) {
+      let last = (r + cell.rowspan).min(row_count) - 1;
+      let has: usize = row_lines[*r..=last].iter().sum();
+      row_lines[last] += used_lines(wrapper).saturating_sub(has);
+    }
+    let mut row_starts = vec![0]; //the table line each row starts on, then the end
+    for lines in &row_lines {
+      row_starts.push(row_starts.last().unwrap() + lines);
+    }
+    let line_count = *row_starts.last().unwrap();
+    //lines are as tall as the tallest text on them in any cell
+    let mut heights = vec![0; line_count];
+    for ((r, _, _), wrapper) in cells.iter().zip(&laid_out) {
+      for line in 0..used_lines(wrapper) {
+        let height = wrapper.line_ys[line + 1] - wrapper.line_ys[line];
+        heights[row_starts[*r] + line] = heights[row_starts[*r] + line].max(height);
+      }
+    }
+    let default_height = (metrics.line_height)(Style::default());
+    for (line, height) in heights.iter_mut().enumerate() {
+      if *height == 0 {
+        *height = default_height;
+      }
+      //space for the border above each row
+      if table.border && row_starts.contains(&line) {
+        *height += BORDER_SPACE;
+      }
+    }
+    let mut line_ys = vec![self.y];
+    for height in &heights {
+      line_ys.push(line_ys.last().unwrap() + height);
+    }
+    let mut page = Vec::new();
+    let mut line_chars = vec![0; line_count];
+    for ((r, c, cell), wrapper) in cells.iter().zip(laid_out) {
+      let first_line = row_starts[*r];
+      let used = used_lines(&wrapper);
+      //from the cell's own lines to the table's
+      let table_y = |y: usize| {
+        let line = line_at_y(&wrapper.line_ys, y);
+        if line < used {
+          line_ys[first_line + line] + y - wrapper.line_ys[line]
+        } else {
+          line_ys[first_line + used]
+        }
+      };
+      for (x, y, text, subtype, style) in &wrapper.page {
+        let line = first_line + line_at_y(&wrapper.line_ys, *y);
+        line_chars[line] += text.chars().count();
+        page.push((column_xs[*c] + x - 2, line_ys[line], text.clone(), *subtype, *style));
+      }
+      //borders of tables in the cell
+      for (x, y, width, height) in &wrapper.borders {
+        let top = table_y(*y);
+        let bottom = if *height > 1 { table_y(y + height) } else { top + height };
+        self.borders.push((column_xs[*c] + x - 2, top, *width, bottom - top));
+      }
+      for (id, line) in &wrapper.placed_anchors {
+        self.placed_anchors.push((id.clone(), self.line_count + first_line + (*line).min(used.saturating_sub(1))));
+      }
+      if table.border {
+        let x = column_xs[*c] - gap / 2;
+        let width = widths[*c..*c + cell.colspan].iter().sum::<usize>() + cell.colspan * gap;
+        let (top, bottom) = (line_ys[first_line], line_ys[row_starts[(r + cell.rowspan).min(row_count)]]);
+        self.borders.extend([(x, top, width + 1, 1), (x, bottom, width + 1, 1), (x, top, 1, bottom - top), (x + width, top, 1, bottom - top)]);
+      }
+    }
+    //in reading order, so drawing can stop at the first thing off screen
+    page.sort_by_key(|p| (p.1, p.0));
+    self.page.extend(page);
+    for id in self.anchors.drain(..) {
+      self.placed_anchors.push((id, self.line_count));
+    }
+    for (height, chars) in heights.into_iter().zip(line_chars) {
+      self.tallest = height;
+      self.chars_placed += chars;
+      self.newline(metrics);
+    }
+  }
+
+  //add the text so far to the page (but don't actually go to a new line)
+  fn end_line(&mut self) {
+    if !self.line.is_empty() {
+      self.page.push((self.line_x, self.y, std::mem::take(&mut self.line), self.line_subtype, self.line_style));
+    }
+  }
+
+  //now the line is done, we know how tall it is
+  fn finish_line(&mut self, metrics: &mut Metrics) {
+    self.end_line();
+    if self.tallest == 0 {
+      self.tallest = (metrics.line_height)(Style::default());
+    }
+    self.y += std::mem::take(&mut self.tallest);
+    self.line_ys.push(self.y);
+  }
+
+  fn newline(&mut self, metrics: &mut Metrics) {
+    self.finish_line(metrics);
+    self.x = 2;
+    self.line_count += 1;
+    self.line_offsets.push(self.chars_placed);
+  }
+}
+
+#[test]
+fn test_preformatted() {
+  let outputs = vec![
+    OutputType::Text("  collapsed  ".to_string()),
+    OutputType::Newline,
+    OutputType::Text("one\ntwo".to_string()),
+    OutputType::Newline,
+    OutputType::Preformatted(true),
+    OutputType::Text("  kept\tas is".to_string()),
+    OutputType::Newline,
+    OutputType::Text("a  b\nc".to_string()),
+    OutputType::Preformatted(false),
+  ];
+  let layout = layout(outputs, 1000, false, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  let lines: Vec<(usize, usize, &str)> = layout.page.iter().map(|p| (p.0, p.1, p.2.as_str())).collect();
+  //a newline only ends the line in preformatted text
+  assert_eq!(lines, vec![(2, 2, " collapsed "), (2, 20, "one two"), (2, 38, "  kept  as is"), (2, 56, "a  b"), (2, 74, "c")]);
+  assert!(layout.page[2..].iter().all(|p| p.4.monospace) && !layout.page[1].4.monospace);
+  //not wrapped, however long
+  let outputs = vec![OutputType::Preformatted(true), OutputType::Text("a long line".to_string()), OutputType::Preformatted(false), OutputType::Text(" wraps here".to_string())];
+  let outputs_again = outputs.clone();
+  let layout = super::layout(outputs, 62, false, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  assert_eq!(layout.page.iter().map(|p| (p.1, p.2.as_str())).collect::<Vec<(usize, &str)>>(), vec![(2, "a long line"), (2, " "), (20, "wraps "), (38, "here")]);
+  assert_eq!(layout.width, 112);
+  //unless wrapping everything
+  let layout = super::layout(outputs_again, 62, true, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  assert_eq!(layout.page.iter().map(|p| (p.1, p.2.as_str())).collect::<Vec<(usize, &str)>>(), vec![(2, "a long "), (20, "line"), (20, " "), (38, "wraps "), (56, "here")]);
+}
+
+#[test]
+fn test_line_wrapper() {
+  //10 wide chars, so 2 + 5 chars fit on a line of width 55
+  let (mut measure, mut line_height) = (|_, _| 10, |_| LINE_HEIGHT);
+  let mut metrics = Metrics { measure: &mut measure, line_height: &mut line_height };
+  let mut wrapper = LineWrapper::new(55, false);
+  wrapper.add_text("ab cd efghijklm", Subtype::Text, Style::default(), &mut metrics);
+  wrapper.add_text("no", Subtype::Link, Style::default(), &mut metrics);
+  wrapper.place_word(&mut metrics);
+  wrapper.end_line();
+  let lines: Vec<(usize, &str)> = wrapper.page.iter().map(|p| (p.1, p.2.as_str())).collect();
+  //the long word gets broken anywhere, and the link continues the word so it doesn't start its own line
+  assert_eq!(lines, vec![(2, "ab cd "), (20, "efghi"), (38, "jklm"), (38, "n"), (56, "o")]);
+  assert_eq!(wrapper.line_offsets, vec![0, 6, 11, 16]);
+  //cjk can break between any two characters, but not before punctuation like ，
+  for (text, expected) in [("鄭成功本名森", vec!["鄭成功本", "名森"]), ("鄭成功本，名", vec!["鄭成功", "本，名"])] {
+    let mut wrapper = LineWrapper::new(45, false);
+    wrapper.add_text(text, Subtype::Text, Style::default(), &mut metrics);
+    wrapper.place_word(&mut metrics);
+    wrapper.end_line();
+    assert_eq!(wrapper.page.iter().map(|p| p.2.as_str()).collect::<Vec<&str>>(), expected);
+  }
+}
+
+#[test]
+fn test_styles() {
+  let bold = Style { bold: true, ..Style::default() };
+  let outputs = vec![
+    OutputType::StartStyle(Style { heading: 1, ..Style::default() }),
+    OutputType::Text("Big ".to_string()),
+    OutputType::StartStyle(bold),
+    OutputType::Text("bold".to_string()),
+    OutputType::EndStyle,
+    OutputType::EndStyle,
+    OutputType::Newline,
+    OutputType::Text("small".to_string()),
+    OutputType::Newline,
+    OutputType::Newline,
+    OutputType::StartStyle(bold),
+    OutputType::Text("end".to_string()),
+  ];
+  //headings are twice as wide and tall
+  let layout = layout(outputs, 1000, false, |_, s| if s.heading > 0 { 20 } else { 10 }, |s| if s.heading > 0 { 36 } else { 18 }, |_| None);
+  let heading_bold = Style { heading: 1, bold: true, ..Style::default() };
+  let runs: Vec<(usize, usize, &str, Style)> = layout.page.iter().map(|p| (p.0, p.1, p.2.as_str(), p.4)).collect();
+  assert_eq!(runs, vec![(2, 2, "Big ", Style { heading: 1, ..Style::default() }), (82, 2, "bold", heading_bold), (2, 38, "small", Style::default()), (2, 74, "end", bold)]);
+  assert_eq!(layout.line_ys, vec![2, 38, 56, 74, 92]);
+  assert_eq!(line_at_y(&layout.line_ys, 37), 0);
+  assert_eq!(line_at_y(&layout.line_ys, 38), 1);
+  assert_eq!(line_at_y(&layout.line_ys, 1), 0);
+}
+
+#[cfg(test)]
+fn cell(text: &str, colspan: usize, rowspan: usize) -> crate::xml::TableCell {
+  crate::xml::TableCell { outputs: vec![OutputType::Text(text.to_string())], colspan, rowspan }
+}
+
+#[test]
+fn test_table() {
+  let table = |border| OutputType::Table(Table {
+    rows: vec![
+      vec![cell("a", 1, 2), cell("bb", 1, 1), cell("c", 1, 1)],
+      vec![cell("dddd eeee", 2, 1)],
+    ],
+    border,
+  });
+  let runs = |layout: &Layout| layout.page.iter().map(|p| (p.0, p.1, p.2.clone())).collect::<Vec<(usize, usize, String)>>();
+  //gaps between columns are two spaces, and the spanning cell widens the columns it spans
+  let laid_out = layout(vec![OutputType::Text("x".to_string()), table(false), OutputType::Text("y".to_string())], 1000, false, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  assert_eq!(runs(&laid_out), vec![
+    (2, 2, "x".to_string()),
+    (2, 20, "a".to_string()),
+    (32, 20, "bb".to_string()),
+    (92, 20, "c".to_string()),
+    (32, 38, "dddd eeee".to_string()),
+    (2, 56, "y".to_string()),
+  ]);
+  assert!(laid_out.borders.is_empty());
+  //too narrow for the spanning cell on one line, so it wraps
+  let laid_out = layout(vec![table(true)], 120, false, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  assert_eq!(runs(&laid_out), vec![
+    (12, 2, "a".to_string()),
+    (42, 2, "bb".to_string()),
+    (91, 2, "c".to_string()),
+    (42, 24, "dddd ".to_string()),
+    (42, 46, "eeee".to_string()),
+  ]);
+  //rows have space above them for the border
+  assert_eq!(laid_out.line_ys, vec![2, 24, 46, 64, 82]);
+  assert_eq!(laid_out.borders[..4], [(2, 2, 31, 1), (2, 64, 31, 1), (2, 2, 1, 62), (32, 2, 1, 62)]);
+  //too narrow even for that, so one cell after another
+  let laid_out = layout(vec![table(false)], 60, true, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  assert_eq!(laid_out.page.iter().map(|p| p.2.as_str()).collect::<Vec<&str>>(), vec!["a", "bb", "c", "dddd ", "eeee"]);
+  //unless not wrapping, when it stays a table and goes off the side
+  let laid_out = layout(vec![table(false)], 60, false, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  assert_eq!(runs(&laid_out), vec![
+    (2, 2, "a".to_string()),
+    (32, 2, "bb".to_string()),
+    (72, 2, "c".to_string()),
+    (32, 20, "dddd ".to_string()),
+    (32, 38, "eeee".to_string()),
+  ]);
+  assert_eq!(laid_out.width, 82);
+  //tables in tables are measured once a level, not twice, so deep ones don't take forever
+  let mut nested = OutputType::Text("deep".to_string());
+  for _ in 0..40 {
+    nested = OutputType::Table(Table { rows: vec![vec![crate::xml::TableCell { outputs: vec![nested], colspan: 1, rowspan: 1 }]], border: false });
+  }
+  let laid_out = layout(vec![nested], 1000, false, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  assert_eq!(runs(&laid_out), vec![(2, 2, "deep".to_string())]);
+}
+
+#[test]
+fn test_list_markers() {
+  let outputs = vec![
+    OutputType::Indent(40),
+    OutputType::ListMarker("1. ".to_string()),
+    OutputType::Text("one two".to_string()),
+    OutputType::Newline,
+    OutputType::ListMarker("10. ".to_string()),
+    OutputType::Text("ten".to_string()),
+    OutputType::Newline,
+    OutputType::Indent(0),
+  ];
+  //markers end at the indent, and wrapped lines line up with the text, not the marker
+  let laid_out = layout(outputs, 80, false, |_, _| 10, |_| LINE_HEIGHT, |_| None);
+  let runs: Vec<(usize, usize, &str)> = laid_out.page.iter().map(|p| (p.0, p.1, p.2.as_str())).collect();
+  assert_eq!(runs, vec![(12, 2, "1. "), (42, 2, "one "), (42, 20, "two"), (2, 38, "10. "), (42, 38, "ten")]);
+}
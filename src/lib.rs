@@ -0,0 +1,38 @@
+//! Koxinga is a web browser supporting text and links. This is everything but the window: fetching, parsing and laying out pages.
+//!
+//! ```no_run
+//! use koxinga::{ HttpClient, Url, Document };
+//!
+//! let url = Url::new("https://en.wikipedia.org/wiki/Koxinga".to_string());
+//! let resp = HttpClient::default().get(&url).unwrap();
+//! let document = Document::new("text/html", &resp.body);
+//! print!("{}", koxinga::dump::dump(&document, &resp.url, 80, true));
+//! ```
+
+#![deny(missing_docs)]
+
+pub mod url;
+pub mod http;
+pub mod download;
+pub mod encoding;
+pub mod gemini;
+pub mod gopher;
+pub mod file;
+pub mod json;
+mod markdown;
+mod cookies;
+mod cache;
+pub mod xml;
+mod entities;
+pub mod layout;
+pub mod document;
+pub mod dump;
+pub mod save;
+pub mod config;
+mod dirs;
+
+pub use crate::url::Url;
+pub use crate::http::HttpClient;
+pub use crate::xml::{ parse, Node, OutputType };
+pub use crate::layout::{ layout, Layout, Subtype };
+pub use crate::document::Document;
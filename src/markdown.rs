@@ -0,0 +1,633 @@
+//turning markdown (commonmark, more or less) into outputs, and outputs back into markdown
+//not a full commonmark parser, just enough of one for readmes and the like: headings, paragraphs, lists, quotes, code, links and images
+//emphasis is shown with styles. markers that aren't closed are dropped
+
+use std::collections::HashMap;
+
+use crate::url::Url;
+use crate::xml::{ remove_quotes, OutputType, Style, BULLETS };
+
+const QUOTE_INDENT: usize = 32; //same as gemtext quotes
+const LIST_INDENT: usize = 26;
+
+//where the text of a list item starts, and the marker (as shown), if the line is a list item
+fn list_item(line: &str) -> Option<(usize, String, &str)> {
+  let leading = line.len() - line.trim_start_matches(' ').len();
+  let rest = &line[leading..];
+  if let Some(text) = rest.strip_prefix(['-', '*', '+']) {
+    if text.is_empty() || text.starts_with(' ') {
+      return Some((leading, "• ".to_string(), text.trim_start()));
+    }
+  }
+  let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+  if (1..10).contains(&digits) {
+    let text = &rest[digits..];
+    if let Some(text) = text.strip_prefix(['.', ')']) {
+      if text.is_empty() || text.starts_with(' ') {
+        return Some((leading, format!("{}. ", &rest[..digits]), text.trim_start()));
+      }
+    }
+  }
+  None
+}
+
+//three or more -, * or _ (and nothing else but spaces)
+fn is_rule(line: &str) -> bool {
+  let chars: Vec<char> = line.chars().filter(|c| *c != ' ').collect();
+  chars.len() >= 3 && ['-', '*', '_'].contains(&chars[0]) && chars.iter().all(|c| *c == chars[0])
+}
+
+//[label]: url "title"
+fn reference_definition(line: &str) -> Option<(String, String)> {
+  let line = line.trim_start();
+  let (label, rest) = line.strip_prefix('[')?.split_once("]:")?;
+  let url = rest.split_whitespace().next()?;
+  Some((label.to_lowercase(), url.trim_start_matches('<').trim_end_matches('>').to_string()))
+}
+
+//index of the ] matching the [ just before start, skipping nested brackets and escapes
+fn closing_bracket(chars: &[char], start: usize) -> Option<usize> {
+  let mut depth = 0;
+  let mut i = start;
+  while i < chars.len() {
+    match chars[i] {
+      '\\' => i += 1,
+      '[' => depth += 1,
+      ']' if depth == 0 => return Some(i),
+      ']' => depth -= 1,
+      _ => {},
+    }
+    i += 1;
+  }
+  None
+}
+
+//the destination of a link whose text ends at close, and the index after the whole link
+fn link_destination(chars: &[char], close: usize, text: &str, references: &HashMap<String, String>) -> Option<(String, usize)> {
+  match chars.get(close + 1) {
+    Some('(') => {
+      let end = close + 1 + chars[close + 1..].iter().position(|c| *c == ')')?;
+      let inside: String = chars[close + 2..end].iter().collect();
+      let inside = inside.trim();
+      let url = if let Some(bracketed) = inside.strip_prefix('<') {
+        bracketed.split('>').next().unwrap_or("")
+      } else {
+        //anything after a space is the title
+        inside.split_whitespace().next().unwrap_or("")
+      };
+      Some((url.to_string(), end + 1))
+    },
+    Some('[') => {
+      let end = closing_bracket(chars, close + 2)?;
+      let label: String = chars[close + 2..end].iter().collect();
+      //[text][] uses the text as the label
+      let label = if label.is_empty() { text.to_lowercase() } else { label.to_lowercase() };
+      references.get(&label).map(|url| (url.clone(), end + 1))
+    },
+    _ => references.get(&text.to_lowercase()).map(|url| (url.clone(), close + 1)),
+  }
+}
+
+//whether a run of *, _ or ~ is an emphasis or strikethrough marker, rather than eg: a * b, or snake_case
+fn is_marker(chars: &[char], start: usize, end: usize) -> bool {
+  let before = if start == 0 { None } else { Some(chars[start - 1]) };
+  let after = chars.get(end).copied();
+  let left_flanking = after.is_some_and(|c| !c.is_whitespace());
+  let right_flanking = before.is_some_and(|c| !c.is_whitespace());
+  match chars[start] {
+    '_' => (left_flanking || right_flanking) && !(before.is_some_and(|c| c.is_alphanumeric()) && after.is_some_and(|c| c.is_alphanumeric())),
+    '~' => end - start == 2 && (left_flanking || right_flanking),
+    _ => left_flanking || right_flanking,
+  }
+}
+
+//*emphasis*, **strong**, ***both*** and ~~strikethrough~~
+fn marker_style(c: char, run: usize) -> Style {
+  let style = Style::default();
+  match (c, run) {
+    ('~', _) => Style { strikethrough: true, ..style },
+    (_, 1) => Style { italic: true, ..style },
+    (_, 2) => Style { bold: true, ..style },
+    _ => Style { bold: true, italic: true, ..style },
+  }
+}
+
+fn flush_text(outputs: &mut Vec<OutputType>, text: &mut String) {
+  if !text.is_empty() {
+    outputs.push(OutputType::Text(std::mem::take(text)));
+  }
+}
+
+//the outputs of the text of a paragraph, heading, etc. newlines are hard line breaks
+fn inline_outputs(s: &str, references: &HashMap<String, String>) -> Vec<OutputType> {
+  let chars: Vec<char> = s.chars().collect();
+  let mut outputs = Vec::new();
+  let mut text = String::new();
+  let mut open: Vec<(char, usize)> = Vec::new(); //markers with their styles started, innermost last
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    if c == '\\' && chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) {
+      text.push(chars[i + 1]);
+      i += 2;
+    } else if c == '`' {
+      let run = chars[i..].iter().take_while(|c| **c == '`').count();
+      let after = i + run;
+      //the code ends at the next run of the same length
+      let mut end = None;
+      let mut j = after;
+      while j < chars.len() {
+        let other_run = chars[j..].iter().take_while(|c| **c == '`').count();
+        if other_run == run {
+          end = Some(j);
+          break;
+        }
+        j += other_run.max(1);
+      }
+      if let Some(end) = end {
+        let code: String = chars[after..end].iter().collect();
+        flush_text(&mut outputs, &mut text);
+        outputs.push(OutputType::StartStyle(Style { monospace: true, ..Style::default() }));
+        outputs.push(OutputType::Text(code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')).unwrap_or(&code).to_string()));
+        outputs.push(OutputType::EndStyle);
+        i = end + run;
+      } else {
+        text += &"`".repeat(run);
+        i = after;
+      }
+    } else if c == '[' || (c == '!' && chars.get(i + 1) == Some(&'[')) {
+      let image = c == '!';
+      let start = if image { i + 2 } else { i + 1 };
+      let link = closing_bracket(&chars, start).and_then(|close| {
+        let link_text: String = chars[start..close].iter().collect();
+        link_destination(&chars, close, &link_text, references).map(|(url, end)| (link_text, url, end))
+      });
+      if let Some((link_text, url, end)) = link {
+        flush_text(&mut outputs, &mut text);
+        //quoted, like an href
+        outputs.push(OutputType::StartLink(format!("\"{}\"", url)));
+        if image {
+          outputs.push(OutputType::Text(if link_text.is_empty() { "[image]".to_string() } else { format!("[{}]", link_text) }));
+        } else {
+          outputs.extend(inline_outputs(&link_text, references).into_iter().filter(|o| !matches!(o, OutputType::StartLink(_) | OutputType::EndLink)));
+        }
+        outputs.push(OutputType::EndLink);
+        i = end;
+      } else {
+        text.push(c);
+        i += 1;
+      }
+    } else if c == '<' {
+      //autolinks, eg: <https://example.org>
+      let end = chars[i..].iter().position(|c| *c == '>').map(|p| i + p);
+      let inside: Option<String> = end.map(|end| chars[i + 1..end].iter().collect());
+      if let (Some(end), Some(url)) = (end, inside.filter(|u| (u.contains("://") || u.starts_with("mailto:")) && !u.contains(' '))) {
+        flush_text(&mut outputs, &mut text);
+        outputs.push(OutputType::StartLink(format!("\"{}\"", url)));
+        outputs.push(OutputType::Text(url));
+        outputs.push(OutputType::EndLink);
+        i = end + 1;
+      } else {
+        text.push(c);
+        i += 1;
+      }
+    } else if c == '*' || c == '_' || c == '~' {
+      let end = i + chars[i..].iter().take_while(|o| **o == c).count();
+      let marker = (c, end - i);
+      if !is_marker(&chars, i, end) {
+        text.extend(&chars[i..end]);
+      } else if let Some(position) = open.iter().position(|m| *m == marker) {
+        //end it, and anything started inside it, then start those again
+        flush_text(&mut outputs, &mut text);
+        let inside = open.split_off(position + 1);
+        open.pop();
+        outputs.extend((0..=inside.len()).map(|_| OutputType::EndStyle));
+        for (c, run) in inside {
+          outputs.push(OutputType::StartStyle(marker_style(c, run)));
+          open.push((c, run));
+        }
+      } else if (end..chars.len()).any(|j| chars[j] == c && (j == 0 || chars[j - 1] != c) && chars[j..].iter().take_while(|o| **o == c).count() == marker.1 && is_marker(&chars, j, j + marker.1)) {
+        //only started if it is ended later
+        flush_text(&mut outputs, &mut text);
+        outputs.push(OutputType::StartStyle(marker_style(c, marker.1)));
+        open.push(marker);
+      }
+      i = end;
+    } else if c == '\n' {
+      flush_text(&mut outputs, &mut text);
+      outputs.push(OutputType::Newline);
+      i += 1;
+    } else {
+      text.push(c);
+      i += 1;
+    }
+  }
+  flush_text(&mut outputs, &mut text);
+  outputs.extend(open.iter().map(|_| OutputType::EndStyle));
+  outputs
+}
+
+struct Converter {
+  outputs: Vec<OutputType>,
+  references: HashMap<String, String>,
+  title: Option<String>,
+  paragraph: String,
+  base_indent: usize, //from quotes
+  list_indent: Option<usize>, //indent of the text of the current list item, if in a list
+  blank: bool, //whether the last line was blank
+}
+
+impl Converter {
+  fn flush_paragraph(&mut self) {
+    if !self.paragraph.is_empty() {
+      let paragraph = std::mem::take(&mut self.paragraph);
+      self.outputs.extend(inline_outputs(paragraph.trim_end(), &self.references));
+      self.outputs.push(OutputType::Newline);
+    }
+  }
+
+  fn heading(&mut self, text: &str, level: u8) {
+    let outputs = inline_outputs(text, &self.references);
+    if self.title.is_none() {
+      //the first heading is the title, like gemtext
+      self.title = Some(outputs.iter().filter_map(|o| if let OutputType::Text(t) = o { Some(t.as_str()) } else { None }).collect());
+    }
+    self.outputs.push(OutputType::StartStyle(Style { heading: level, ..Style::default() }));
+    self.outputs.extend(outputs);
+    self.outputs.push(OutputType::EndStyle);
+    self.outputs.push(OutputType::Newline);
+  }
+
+  fn end_list(&mut self) {
+    if self.list_indent.take().is_some() {
+      self.outputs.push(OutputType::Indent(self.base_indent));
+    }
+  }
+
+  fn line(&mut self, line: &str) {
+    let trimmed = line.trim();
+    let leading = line.len() - line.trim_start_matches(' ').len();
+    if trimmed.is_empty() {
+      self.flush_paragraph();
+      if !self.blank {
+        self.outputs.push(OutputType::Newline);
+      }
+      self.blank = true;
+      return;
+    }
+    let was_blank = self.blank;
+    self.blank = false;
+    if let Some((item_leading, marker, text)) = list_item(line).filter(|_| !is_rule(line)) {
+      self.flush_paragraph();
+      //every two spaces in is a level deeper
+      let depth = item_leading / 2;
+      let indent = self.base_indent + (depth + 1) * LIST_INDENT;
+      let marker = if marker == "• " { format!("{} ", BULLETS[depth.min(BULLETS.len() - 1)]) } else { marker };
+      self.outputs.push(OutputType::Indent(indent));
+      self.outputs.push(OutputType::ListMarker(marker));
+      self.list_indent = Some(indent);
+      self.add_to_paragraph(text);
+      return;
+    }
+    if was_blank && self.list_indent.is_some() {
+      if leading >= 2 {
+        //another paragraph of the item
+        self.outputs.push(OutputType::Indent(self.list_indent.unwrap()));
+      } else {
+        self.end_list();
+      }
+    }
+    if leading >= 4 && self.paragraph.is_empty() && self.list_indent.is_none() {
+      //indented code
+      self.outputs.push(OutputType::Preformatted(true));
+      self.outputs.push(OutputType::Text(line[4..].to_string()));
+      self.outputs.push(OutputType::Newline);
+      self.outputs.push(OutputType::Preformatted(false));
+    } else if let Some(heading) = trimmed.strip_prefix('#').map(|h| h.trim_start_matches('#')).filter(|h| h.is_empty() || h.starts_with(' ')).filter(|_| trimmed.len() - trimmed.trim_start_matches('#').len() <= 6) {
+      self.flush_paragraph();
+      self.end_list();
+      //closing #s are optional
+      let level = trimmed.len() - trimmed.trim_start_matches('#').len();
+      let heading = heading.trim_end_matches('#').trim().to_string();
+      self.heading(&heading, level as u8);
+    } else if !self.paragraph.is_empty() && self.list_indent.is_none() && (trimmed.chars().all(|c| c == '=') || trimmed.chars().all(|c| c == '-')) {
+      //setext heading, the paragraph so far was the heading
+      let heading = std::mem::take(&mut self.paragraph);
+      self.heading(heading.trim(), if trimmed.starts_with('=') { 1 } else { 2 });
+    } else if is_rule(line) {
+      self.flush_paragraph();
+      self.end_list();
+      self.outputs.push(OutputType::Text("─".repeat(20)));
+      self.outputs.push(OutputType::Newline);
+    } else {
+      self.add_to_paragraph(line);
+    }
+  }
+
+  fn add_to_paragraph(&mut self, line: &str) {
+    //two spaces or a backslash at the end is a hard line break
+    if let Some(line) = line.trim_start().strip_suffix('\\') {
+      self.paragraph += line;
+      self.paragraph += "\n";
+    } else {
+      self.paragraph += line.trim();
+      self.paragraph += if line.ends_with("  ") { "\n" } else { " " };
+    }
+  }
+}
+
+//number of > at the start, and the rest of the line
+fn strip_quotes(line: &str) -> (usize, &str) {
+  let mut depth = 0;
+  let mut rest = line;
+  while let Some(inner) = rest.trim_start().strip_prefix('>') {
+    depth += 1;
+    rest = inner.strip_prefix(' ').unwrap_or(inner);
+  }
+  (depth, rest)
+}
+
+//the first heading is the title
+pub fn markdown_outputs(text: &str) -> (Option<String>, Vec<OutputType>) {
+  let mut converter = Converter {
+    outputs: Vec::new(),
+    references: text.lines().filter_map(reference_definition).collect(),
+    title: None,
+    paragraph: String::new(),
+    base_indent: 0,
+    list_indent: None,
+    blank: true,
+  };
+  let mut fence: Option<String> = None;
+  for line in text.lines() {
+    let (depth, line) = strip_quotes(line);
+    if fence.is_none() && depth * QUOTE_INDENT != converter.base_indent {
+      converter.flush_paragraph();
+      converter.list_indent = None;
+      converter.base_indent = depth * QUOTE_INDENT;
+      converter.outputs.push(OutputType::Indent(converter.base_indent));
+    }
+    let trimmed = line.trim_start();
+    if let Some(marker) = &fence {
+      if trimmed.starts_with(marker.as_str()) && trimmed.trim_start_matches(marker.chars().next().unwrap()).trim().is_empty() {
+        converter.outputs.push(OutputType::Preformatted(false));
+        fence = None;
+      } else {
+        converter.outputs.push(OutputType::Text(line.to_string()));
+        converter.outputs.push(OutputType::Newline);
+      }
+    } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+      //anything after the fence is the language, which there's nothing to do with
+      converter.flush_paragraph();
+      let marker_char = trimmed.chars().next().unwrap();
+      fence = Some(trimmed.chars().take_while(|c| *c == marker_char).collect());
+      converter.outputs.push(OutputType::Preformatted(true));
+      converter.blank = false;
+    } else if reference_definition(line).is_some() && converter.paragraph.is_empty() {
+      continue;
+    } else {
+      converter.line(line);
+    }
+  }
+  if fence.is_some() {
+    converter.outputs.push(OutputType::Preformatted(false));
+  }
+  converter.flush_paragraph();
+  (converter.title, converter.outputs)
+}
+
+//escape anything that would mean something in markdown
+fn escape(text: &str, line_start: bool) -> String {
+  let mut escaped = String::new();
+  for (i, c) in text.chars().enumerate() {
+    if "\\`*_[]<".contains(c) || (i == 0 && line_start && "#>".contains(c)) {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+//tables, as one cell after another
+fn linearise_tables(outputs: Vec<OutputType>) -> Vec<OutputType> {
+  outputs.into_iter().flat_map(|o| match o {
+    OutputType::Table(table) => linearise_tables(table.linearise()),
+    o => vec![o],
+  }).collect()
+}
+
+//line was taken or replaced, so styles and links still open start from here in the new one
+fn restart(styles: &mut [(String, usize)], link: &mut Option<(String, usize)>, at: usize) {
+  for (_, start) in styles {
+    *start = at;
+  }
+  if let Some((_, start)) = link {
+    *start = at;
+  }
+}
+
+//a page's outputs as markdown, with links resolved against url. every line (block) is its own paragraph
+pub fn to_markdown(title: Option<&str>, outputs: Vec<OutputType>, url: &Url) -> String {
+  let mut blocks = Vec::new();
+  if let Some(title) = title {
+    blocks.push(format!("# {}", escape(title, false)));
+  }
+  let mut line = String::new();
+  let mut link: Option<(String, usize)> = None; //url, where its text starts in line
+  let mut preformatted: Option<String> = None;
+  let mut styles: Vec<(String, usize)> = Vec::new(); //marker, where the styled text starts in line
+  for o in linearise_tables(outputs) {
+    match o {
+      OutputType::Text(text) => if let Some(code) = &mut preformatted {
+        *code += &text;
+      } else {
+        //runs of whitespace are one space, like when laid out
+        let mut collapsed = String::new();
+        for c in text.chars() {
+          if !c.is_whitespace() {
+            collapsed.push(c);
+          } else if !collapsed.ends_with(' ') && !(collapsed.is_empty() && (line.is_empty() || line.ends_with(' '))) {
+            collapsed.push(' ');
+          }
+        }
+        if styles.iter().any(|(marker, _)| marker == "`") {
+          line += &collapsed;
+        } else {
+          line += &escape(&collapsed, line.is_empty());
+        }
+      },
+      OutputType::StartStyle(style) => {
+        if style.heading > 0 && line.trim().is_empty() {
+          line = "#".repeat(style.heading as usize) + " ";
+          restart(&mut styles, &mut link, line.len());
+        }
+        let marker = match style {
+          Style { monospace: true, .. } => "`",
+          Style { bold: true, italic: true, .. } => "***",
+          Style { bold: true, .. } => "**",
+          Style { italic: true, .. } => "*",
+          Style { strikethrough: true, .. } => "~~",
+          _ => "",
+        };
+        styles.push((marker.to_string(), line.len()));
+      },
+      OutputType::EndStyle => if let Some((marker, start)) = styles.pop() {
+        //markers go right next to the text, not around spaces
+        let styled = line.get(start..).unwrap_or("");
+        let text = styled.trim();
+        if !marker.is_empty() && !text.is_empty() {
+          let text_start = line.len() - styled.trim_start().len();
+          let text_end = text_start + text.len();
+          line.insert_str(text_end, &marker);
+          line.insert_str(text_start, &marker);
+        }
+      },
+      OutputType::StartLink(href) => {
+        link = Some((url.resolve(&remove_quotes(href)).to_string(), line.len()));
+      },
+      OutputType::EndLink => if let Some((href, start)) = link.take() {
+        let start = if line.is_char_boundary(start) { start } else { line.len() };
+        if line.len() == start {
+          line += &escape(&href, false);
+        }
+        line.insert(start, '[');
+        line += &format!("]({})", href.replace(' ', "%20").replace(')', "%29"));
+      },
+      OutputType::Newline => if let Some(code) = &mut preformatted {
+        code.push('\n');
+      } else if !line.trim().is_empty() {
+        //a link across lines just starts on the next one
+        blocks.push(std::mem::take(&mut line).trim().to_string());
+        restart(&mut styles, &mut link, 0);
+      } else {
+        line.clear();
+        restart(&mut styles, &mut link, 0);
+      },
+      OutputType::ListMarker(marker) => {
+        if !line.trim().is_empty() {
+          blocks.push(std::mem::take(&mut line).trim().to_string());
+        }
+        let number = marker.trim_end().strip_suffix('.').filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        line = match number {
+          Some(number) => format!("{}. ", number),
+          None if marker.starts_with(BULLETS) => "- ".to_string(),
+          None => format!("- {}", escape(&marker, false)),
+        };
+        restart(&mut styles, &mut link, line.len());
+      },
+      OutputType::Preformatted(true) => {
+        if !line.trim().is_empty() {
+          blocks.push(std::mem::take(&mut line).trim().to_string());
+        }
+        line.clear();
+        restart(&mut styles, &mut link, 0);
+        preformatted = Some(String::new());
+      },
+      OutputType::Preformatted(false) => if let Some(code) = preformatted.take() {
+        //a fence longer than any run of backticks in the code
+        let longest = code.split(|c| c != '`').map(|run| run.len()).max().unwrap_or(0);
+        let fence = "`".repeat(longest.max(2) + 1);
+        blocks.push(format!("{}\n{}\n{}", fence, code.trim_end_matches('\n'), fence));
+      },
+      _ => {},
+    }
+  }
+  if !line.trim().is_empty() {
+    blocks.push(line.trim().to_string());
+  }
+  blocks.join("\n\n") + "\n"
+}
+
+#[test]
+fn test_markdown_outputs() {
+  let text = "Koxinga\n=======\n\nSome *emphasised* and **strong** text_with_underscores,\na [link](https://example.org \"title\") and `a * b`.  \nNew line, [ref] and ![alt](i.png).\n\n- one\n- two\n  1. nested\n\nafter\n> quoted\n\n```rust\n  let  x;\n```\n***\n[ref]: https://example.org/ref\n";
+  let (title, outputs) = markdown_outputs(text);
+  assert_eq!(title.as_deref(), Some("Koxinga"));
+  let link = |url: &str| OutputType::StartLink(format!("\"{}\"", url));
+  let text = |t: &str| OutputType::Text(t.to_string());
+  let style = |s: Style| OutputType::StartStyle(s);
+  assert_eq!(outputs, vec![
+    style(Style { heading: 1, ..Style::default() }),
+    text("Koxinga"),
+    OutputType::EndStyle,
+    OutputType::Newline,
+    OutputType::Newline,
+    text("Some "),
+    style(Style { italic: true, ..Style::default() }),
+    text("emphasised"),
+    OutputType::EndStyle,
+    text(" and "),
+    style(Style { bold: true, ..Style::default() }),
+    text("strong"),
+    OutputType::EndStyle,
+    text(" text_with_underscores, a "),
+    link("https://example.org"),
+    text("link"),
+    OutputType::EndLink,
+    text(" and "),
+    style(Style { monospace: true, ..Style::default() }),
+    text("a * b"),
+    OutputType::EndStyle,
+    text("."),
+    OutputType::Newline,
+    text("New line, "),
+    link("https://example.org/ref"),
+    text("ref"),
+    OutputType::EndLink,
+    text(" and "),
+    link("i.png"),
+    text("[alt]"),
+    OutputType::EndLink,
+    text("."),
+    OutputType::Newline,
+    OutputType::Newline,
+    OutputType::Indent(26),
+    OutputType::ListMarker("• ".to_string()),
+    text("one"),
+    OutputType::Newline,
+    OutputType::Indent(26),
+    OutputType::ListMarker("• ".to_string()),
+    text("two"),
+    OutputType::Newline,
+    OutputType::Indent(52),
+    OutputType::ListMarker("1. ".to_string()),
+    text("nested"),
+    OutputType::Newline,
+    OutputType::Newline,
+    OutputType::Indent(0),
+    text("after"),
+    OutputType::Newline,
+    OutputType::Indent(32),
+    text("quoted"),
+    OutputType::Newline,
+    OutputType::Indent(0),
+    OutputType::Newline,
+    OutputType::Preformatted(true),
+    text("  let  x;"),
+    OutputType::Newline,
+    OutputType::Preformatted(false),
+    text(&"─".repeat(20)),
+    OutputType::Newline,
+  ]);
+}
+
+#[test]
+fn test_markdown_emphasis() {
+  let style = |s: Style| OutputType::StartStyle(s);
+  let text = |t: &str| OutputType::Text(t.to_string());
+  let italic = Style { italic: true, ..Style::default() };
+  let strikethrough = Style { strikethrough: true, ..Style::default() };
+  //unclosed markers are dropped, and overlapping ones end what was started inside them
+  assert_eq!(inline_outputs("*a ~~b* c~~ *d", &HashMap::new()), vec![
+    style(italic),
+    text("a "),
+    style(strikethrough),
+    text("b"),
+    OutputType::EndStyle,
+    OutputType::EndStyle,
+    style(strikethrough),
+    text(" c"),
+    OutputType::EndStyle,
+    text(" d"),
+  ]);
+}
@@ -0,0 +1,69 @@
+//! Saving pages to disk, to keep a copy: as their source, as laid out text (see [`crate::dump::render`]), or as markdown.
+
+use std::fs::{ create_dir_all, write };
+use std::io;
+use std::path::{ Path, PathBuf };
+
+use crate::dirs::unused_path;
+use crate::document::Document;
+use crate::markdown::to_markdown;
+use crate::url::Url;
+
+const MAX_NAME_CHARS: usize = 80;
+
+/// A file name for a page: its title, or if it has none, the last part of its url, made safe (only letters, numbers and dashes), then `.extension`.
+pub fn file_name(title: Option<&str>, url: &Url, extension: &str) -> String {
+  let from_url = || url.path.trim_end_matches('/').rsplit('/').next().filter(|l| !l.is_empty()).map_or(url.host_str().to_string(), |l| l.to_string());
+  let name = title.filter(|t| !t.trim().is_empty()).map_or_else(from_url, |t| t.to_string());
+  let mut safe = String::new();
+  for c in name.chars() {
+    if c.is_alphanumeric() {
+      safe.extend(c.to_lowercase());
+    } else if !safe.is_empty() && !safe.ends_with('-') {
+      safe.push('-');
+    }
+  }
+  let safe: String = safe.chars().take(MAX_NAME_CHARS).collect();
+  let safe = safe.trim_end_matches('-');
+  format!("{}.{}", if safe.is_empty() { "page" } else { safe }, extension)
+}
+
+/// The page as markdown, with the title as a heading and links resolved against url.
+pub fn markdown(document: &Document, url: &Url) -> String {
+  let (title, outputs) = document.outputs();
+  to_markdown(title.as_deref(), outputs, url)
+}
+
+/// Write `contents` to `name` in `dir` (created if needed). An existing file is never overwritten, a number is added to the name instead. Returns where it was saved.
+pub fn save(dir: &Path, name: &str, contents: &str) -> io::Result<PathBuf> {
+  create_dir_all(dir)?;
+  let path = unused_path(dir, name);
+  write(&path, contents)?;
+  Ok(path)
+}
+
+#[test]
+fn test_save() {
+  let url = Url::new("https://en.wikipedia.org/wiki/Koxinga".to_string());
+  assert_eq!(file_name(Some("Koxinga - Wikipedia"), &url, "html"), "koxinga-wikipedia.html");
+  assert_eq!(file_name(Some("  鄭成功: 1624–1662!  "), &url, "md"), "鄭成功-1624-1662.md");
+  assert_eq!(file_name(None, &url, "txt"), "koxinga.txt");
+  assert_eq!(file_name(None, &Url::new("https://example.org/".to_string()), "txt"), "example-org.txt");
+  assert_eq!(file_name(Some("***"), &url, "txt"), "page.txt");
+  let html = "<html><head><title>Koxinga</title></head><body><p>A *pirate*, see <a href=\"/wiki/Ming\">the\n  Ming</a>.</p><p>[1] <a href=\"/x\"></a></p><ol start=3><li>Ming<li>Qing</ol><h2>Life</h2><p><b>Bold </b><a href=\"/y\"><i>and</i></a> <code>a*b</code></p></body></html>";
+  let document = Document::new("text/html", html);
+  assert_eq!(markdown(&document, &url), "# Koxinga\n\nA \\*pirate\\*, see [the Ming](https://en.wikipedia.org/wiki/Ming).\n\n\\[1\\] [https://en.wikipedia.org/x](https://en.wikipedia.org/x)\n\n3. Ming\n\n4. Qing\n\n## Life\n\n**Bold** [*and*](https://en.wikipedia.org/y) `a*b`\n");
+  let document = Document::new("text/gemini", "# Code\n```\n  let  x;\n```\n");
+  assert_eq!(markdown(&document, &url), "# Code\n\n# Code\n\n```\n  let  x;\n```\n");
+  //pre inside a heading, code or a table cell starts a new line, with the style still open
+  for html in ["<h3>Install <pre>make</pre></h3>", "<h2><pre>", "<li><th><pre>", "鄭<code><pre>", "<a href=\"/z\">鄭<pre>x</pre></a>"] {
+    markdown(&Document::new("text/html", html), &url);
+  }
+  assert_eq!(markdown(&Document::new("text/html", "<h3>Install <pre>make</pre> it</h3>"), &url), "### Install\n\n```\nmake\n```\n\nit\n");
+  let dir = std::env::temp_dir().join(format!("koxinga-save-test-{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  assert_eq!(save(&dir, "a.md", "one").unwrap(), dir.join("a.md"));
+  assert_eq!(save(&dir, "a.md", "two").unwrap(), dir.join("a (1).md"));
+  assert_eq!(std::fs::read_to_string(dir.join("a.md")).unwrap(), "one");
+  let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,75 +1,336 @@
+//! Urls (really, uri references), as in rfc 3986.
+
 use std::vec::Vec;
 use std::fmt;
 
-const VALID_SCHEMES: [&'static str; 2] = ["HTTP", "HTTPS"]; //more to come in future?? who knows
+const VALID_SCHEMES: [&'static str; 5] = ["HTTP", "HTTPS", "GEMINI", "GOPHER", "FILE"]; //more to come in future?? who knows
 
-//for the moment, we don't care about query params or fragments and the like
-#[derive(Clone)]
+/// A uri reference, as in rfc 3986. May be relative (no scheme), in which case it should be resolved against a base with [`Url::resolve`].
+///
+/// Components are stored without their delimiters (no "?" at the start of query, etc). Displaying a `Url` puts them back together.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Url {
-  scheme: String, //http or https, probably
-  pub valid_scheme: bool,
-  pub hostname: String,
-  path: Vec<String>,
-  query: Option<String>, //empty or somethign like ?value1=yes&value2=abcd
+  /// Lowercase. http or https, probably.
+  pub scheme: Option<String>,
+  /// Whatever is before the @ in the authority.
+  pub userinfo: Option<String>,
+  /// Lowercase. None if there is no authority (eg "about:history"), can be Some("") (eg "file:///").
+  pub host: Option<String>,
+  /// Only if given explicitly.
+  pub port: Option<u16>,
+  /// Still percent encoded. Possibly empty.
+  pub path: String,
+  /// Without the "?".
+  pub query: Option<String>,
+  /// Without the "#".
+  pub fragment: Option<String>,
 }
 
 impl fmt::Display for Url {
+  //recomposition, rfc 3986 5.3
   fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-    if self.scheme != "" {
-      fmt.write_str(&(self.scheme.clone() + "://" + &self.hostname + "/" + &self.path.join("/") + &self.query.as_ref().map_or("", |v| v)))?;
+    if let Some(scheme) = &self.scheme {
+      fmt.write_str(&format!("{}:", scheme))?;
+    }
+    if let Some(host) = &self.host {
+      fmt.write_str("//")?;
+      if let Some(userinfo) = &self.userinfo {
+        fmt.write_str(&format!("{}@", userinfo))?;
+      }
+      fmt.write_str(host)?;
+      if let Some(port) = self.port {
+        fmt.write_str(&format!(":{}", port))?;
+      }
+    }
+    fmt.write_str(&self.path)?;
+    if let Some(query) = &self.query {
+      fmt.write_str(&format!("?{}", query))?;
+    }
+    if let Some(fragment) = &self.fragment {
+      fmt.write_str(&format!("#{}", fragment))?;
     }
     Ok(())
   }
 }
 
+fn is_scheme(s: &str) -> bool {
+  //ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )
+  let mut chars = s.chars();
+  chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+//rfc 3986 5.2.4
+fn remove_dot_segments(path: &str) -> String {
+  let mut input = path;
+  let mut output: Vec<&str> = Vec::new(); //each segment includes its leading "/", if any
+  while !input.is_empty() {
+    if let Some(rest) = input.strip_prefix("../") {
+      input = rest;
+    } else if let Some(rest) = input.strip_prefix("./") {
+      input = rest;
+    } else if input.starts_with("/./") {
+      input = &input[2..];
+    } else if input == "/." {
+      input = "/";
+    } else if input.starts_with("/../") || input == "/.." {
+      input = if input == "/.." { "/" } else { &input[3..] };
+      output.pop();
+    } else if input == "." || input == ".." {
+      input = "";
+    } else {
+      //move the first segment (and its leading "/", if any) to the output
+      let start = if input.starts_with("/") { 1 } else { 0 };
+      let end = input[start..].find("/").map_or(input.len(), |i| i + start);
+      output.push(&input[..end]);
+      input = &input[end..];
+    }
+  }
+  output.concat()
+}
+
+/// Decode %XX escapes. Invalid utf-8 gets replaced, malformed escapes are left as is.
+pub fn percent_decode(s: &str) -> String {
+  let bytes = s.as_bytes();
+  let mut decoded = Vec::new();
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' {
+      if let Some(byte) = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+        decoded.push(byte);
+        i += 3;
+        continue;
+      }
+    }
+    decoded.push(bytes[i]);
+    i += 1;
+  }
+  String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Percent encode everything but unreserved characters (letters, digits, "-", ".", "_" and "~"), eg: for a query.
+pub fn percent_encode(s: &str) -> String {
+  s.bytes().map(|b| if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+    (b as char).to_string()
+  } else {
+    format!("%{:02X}", b)
+  }).collect()
+}
+
 impl Url {
+  /// Parse a url or relative reference. This never fails: anything that isn't a scheme or authority ends up in the path.
   pub fn new(url: String) -> Url {
-    let mut queries = url.split("?");
-    let mut p = queries.next().unwrap().split("://");
-    let scheme = p.next().unwrap_or("").to_string();
-    let valid_scheme = VALID_SCHEMES.contains(&scheme.to_uppercase().as_str());
-    p = p.next().unwrap_or("").split("/");
-    let hostname = p.next().unwrap_or("").to_string();
-    let path = p.filter(|s| *s != "").map(|s| s.to_string()).collect();
-    let query = match queries.next() {
-      Some(q) => Some(format!("?{}", q)),
-      None => None,
-    };
-    Self { scheme, valid_scheme, hostname, path, query }
+    //rfc 3986 appendix b: ^(([^:/?#]+):)?(//([^/?#]*))?([^?#]*)(\?([^#]*))?(#(.*))?
+    let mut rest = url.trim();
+    let mut parsed: Url = Default::default();
+    if let Some(hash) = rest.find("#") {
+      parsed.fragment = Some(rest[hash + 1..].to_string());
+      rest = &rest[..hash];
+    }
+    if let Some(question) = rest.find("?") {
+      parsed.query = Some(rest[question + 1..].to_string());
+      rest = &rest[..question];
+    }
+    if let Some(colon) = rest.find(":") {
+      //colon must be before any "/" for it to be a scheme (otherwise it's a relative path with a colon in it)
+      if is_scheme(&rest[..colon]) {
+        parsed.scheme = Some(rest[..colon].to_lowercase());
+        rest = &rest[colon + 1..];
+      }
+    }
+    if let Some(after) = rest.strip_prefix("//") {
+      let end = after.find("/").unwrap_or(after.len());
+      let mut authority = &after[..end];
+      rest = &after[end..];
+      if let Some(at) = authority.rfind("@") {
+        parsed.userinfo = Some(authority[..at].to_string());
+        authority = &authority[at + 1..];
+      }
+      //port comes after the last colon, unless that colon is inside an ipv6 literal like [::1]
+      if let Some(colon) = authority.rfind(":").filter(|c| !authority[*c..].contains("]")) {
+        parsed.port = authority[colon + 1..].parse::<u16>().ok();
+        authority = &authority[..colon];
+      }
+      parsed.host = Some(authority.to_lowercase());
+    }
+    parsed.path = rest.to_string();
+    parsed
   }
 
-  pub fn new_maybe_relative(url: String, current_url: Url) -> Url {
-    if url.split("://").count() < 2 {
-      let mut use_url = current_url;
-      if url.starts_with("/") {
-        use_url.pop_to_root();
-      };
-      use_url.append(url);
-      use_url
+  /// Resolve a reference (eg, a href) against this url as the base, as in rfc 3986 5.2.2.
+  pub fn resolve(&self, reference: &str) -> Url {
+    let r = Url::new(reference.to_string());
+    let mut t: Url = Default::default();
+    if r.scheme.is_some() {
+      t = r;
+      t.path = remove_dot_segments(&t.path);
+      return t;
+    }
+    if r.host.is_some() {
+      t.userinfo = r.userinfo;
+      t.host = r.host;
+      t.port = r.port;
+      t.path = remove_dot_segments(&r.path);
+      t.query = r.query;
+    } else {
+      if r.path.is_empty() {
+        t.path = self.path.clone();
+        t.query = if r.query.is_some() {
+          r.query
+        } else {
+          self.query.clone()
+        };
+      } else {
+        if r.path.starts_with("/") {
+          t.path = remove_dot_segments(&r.path);
+        } else {
+          t.path = remove_dot_segments(&self.merge(&r.path));
+        }
+        t.query = r.query;
+      }
+      t.userinfo = self.userinfo.clone();
+      t.host = self.host.clone();
+      t.port = self.port;
+    }
+    t.scheme = self.scheme.clone();
+    t.fragment = r.fragment;
+    t
+  }
+
+  //rfc 3986 5.2.3
+  fn merge(&self, path: &str) -> String {
+    if self.host.is_some() && self.path.is_empty() {
+      format!("/{}", path)
+    } else if let Some(last_slash) = self.path.rfind("/") {
+      format!("{}{}", &self.path[..=last_slash], path)
     } else {
-      Url::new(url)
+      path.to_string()
     }
   }
 
-  pub fn pop(&mut self) {
-    self.path.pop();
-    self.query = None;
+  /// Whether this is a url the browser can fetch.
+  pub fn valid_scheme(&self) -> bool {
+    self.scheme.as_ref().is_some_and(|s| VALID_SCHEMES.contains(&s.to_uppercase().as_str()))
   }
-  
-  pub fn pop_to_root(&mut self) {
-    self.path = Vec::new();
-    self.query = None;
+
+  /// Whether this is an internal page (eg about:history), generated by the browser rather than fetched.
+  pub fn is_internal(&self) -> bool {
+    self.scheme.as_deref() == Some("about")
   }
-  
-  pub fn append(&mut self, path: String) {
-    self.path.extend(path.split("/").filter(|s| *s != "").map(|s| s.to_string()));
+
+  /// Whether the two urls are the same page, ie, only differ (if at all) by fragment.
+  pub fn same_document(&self, other: &Url) -> bool {
+    self.scheme == other.scheme && self.userinfo == other.userinfo && self.host == other.host && self.port == other.port && self.path == other.path && self.query == other.query
   }
-  
+
+  /// The host, or "" if there isn't one.
+  pub fn host_str(&self) -> &str {
+    self.host.as_deref().unwrap_or("")
+  }
+
+  /// Add key=value to the query. Both should already be encoded.
   pub fn append_query(&mut self, key: &str, value: &str) {
-    if self.query.is_none() {
-      self.query = Some(format!("?{}={}", key, value));
+    if let Some(query) = &self.query {
+      self.query = Some(format!("{}&{}={}", query, key, value));
     } else {
-      self.query = Some(format!("{}&{}={}", self.query.as_ref().unwrap(), key, value));
+      self.query = Some(format!("{}={}", key, value));
     }
   }
 }
+
+#[test]
+fn test_url_parse() {
+  let url = Url::new("HTTPS://user:pw@Example.com:8080/a/b;c?x=1&y=2#frag".to_string());
+  assert_eq!(url.scheme.as_deref(), Some("https"));
+  assert_eq!(url.userinfo.as_deref(), Some("user:pw"));
+  assert_eq!(url.host.as_deref(), Some("example.com"));
+  assert_eq!(url.port, Some(8080));
+  assert_eq!(url.path, "/a/b;c");
+  assert_eq!(url.query.as_deref(), Some("x=1&y=2"));
+  assert_eq!(url.fragment.as_deref(), Some("frag"));
+  assert_eq!(url.to_string(), "https://user:pw@example.com:8080/a/b;c?x=1&y=2#frag");
+  let url = Url::new("http://[::1]:80/".to_string());
+  assert_eq!(url.host.as_deref(), Some("[::1]"));
+  assert_eq!(url.port, Some(80));
+  let url = Url::new("about:history".to_string());
+  assert!(url.is_internal());
+  assert_eq!(url.path, "history");
+  assert_eq!(url.to_string(), "about:history");
+  let url = Url::new("file:///etc/hosts".to_string());
+  assert_eq!(url.host.as_deref(), Some(""));
+  assert_eq!(url.to_string(), "file:///etc/hosts");
+  assert!(!Url::new("en.wikipedia.org".to_string()).valid_scheme());
+}
+
+#[test]
+fn test_percent_decode() {
+  assert_eq!(percent_decode("Ming_dynasty%23Early"), "Ming_dynasty#Early");
+  assert_eq!(percent_decode("%E9%84%AD%E6%88%90%E5%8A%9F"), "鄭成功");
+  assert_eq!(percent_decode("100%"), "100%");
+  assert_eq!(percent_decode("%zz%4"), "%zz%4");
+  assert_eq!(percent_encode("鄭成功 a+b/c~"), "%E9%84%AD%E6%88%90%E5%8A%9F%20a%2Bb%2Fc~");
+  assert_eq!(percent_decode(&percent_encode("a b&c")), "a b&c");
+}
+
+#[test]
+fn test_url_resolve_normal() {
+  //rfc 3986 5.4.1
+  let base = Url::new("http://a/b/c/d;p?q".to_string());
+  let examples = [
+    ("g:h", "g:h"),
+    ("g", "http://a/b/c/g"),
+    ("./g", "http://a/b/c/g"),
+    ("g/", "http://a/b/c/g/"),
+    ("/g", "http://a/g"),
+    ("//g", "http://g"),
+    ("?y", "http://a/b/c/d;p?y"),
+    ("g?y", "http://a/b/c/g?y"),
+    ("#s", "http://a/b/c/d;p?q#s"),
+    ("g#s", "http://a/b/c/g#s"),
+    ("g?y#s", "http://a/b/c/g?y#s"),
+    (";x", "http://a/b/c/;x"),
+    ("g;x", "http://a/b/c/g;x"),
+    ("g;x?y#s", "http://a/b/c/g;x?y#s"),
+    ("", "http://a/b/c/d;p?q"),
+    (".", "http://a/b/c/"),
+    ("./", "http://a/b/c/"),
+    ("..", "http://a/b/"),
+    ("../", "http://a/b/"),
+    ("../g", "http://a/b/g"),
+    ("../..", "http://a/"),
+    ("../../", "http://a/"),
+    ("../../g", "http://a/g"),
+  ];
+  for (reference, expected) in examples {
+    assert_eq!(base.resolve(reference).to_string(), expected, "resolving {}", reference);
+  }
+}
+
+#[test]
+fn test_url_resolve_abnormal() {
+  //rfc 3986 5.4.2
+  let base = Url::new("http://a/b/c/d;p?q".to_string());
+  let examples = [
+    ("../../../g", "http://a/g"),
+    ("../../../../g", "http://a/g"),
+    ("/./g", "http://a/g"),
+    ("/../g", "http://a/g"),
+    ("g.", "http://a/b/c/g."),
+    (".g", "http://a/b/c/.g"),
+    ("g..", "http://a/b/c/g.."),
+    ("..g", "http://a/b/c/..g"),
+    ("./../g", "http://a/b/g"),
+    ("./g/.", "http://a/b/c/g/"),
+    ("g/./h", "http://a/b/c/g/h"),
+    ("g/../h", "http://a/b/c/h"),
+    ("g;x=1/./y", "http://a/b/c/g;x=1/y"),
+    ("g;x=1/../y", "http://a/b/c/y"),
+    ("g?y/./x", "http://a/b/c/g?y/./x"),
+    ("g?y/../x", "http://a/b/c/g?y/../x"),
+    ("g#s/./x", "http://a/b/c/g#s/./x"),
+    ("g#s/../x", "http://a/b/c/g#s/../x"),
+    ("http:g", "http:g"), //strict parser
+  ];
+  for (reference, expected) in examples {
+    assert_eq!(base.resolve(reference).to_string(), expected, "resolving {}", reference);
+  }
+}
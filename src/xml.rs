@@ -1,30 +1,15 @@
+//! Parsing html into a tree of [`Node`]s, and turning that into [`OutputType`]s to lay out.
+
 use std::vec::Vec;
 use std::boxed::Box;
 use std::collections::HashMap;
 
-use ming_wm_lib::utils::Substring;
-
-//use ming_wm_lib::logging::log;
-
-//try to be xhtml compliant?
-
-//fuck these mfers. self close with a FUCKING slash man.
-//<meta> is bad, <meta/> is good!!
-const SELF_CLOSING: [&'static str; 9] = ["link", "meta", "input", "img", "br", "hr", "source", "track", "!DOCTYPE"];
+use crate::entities::NAMED_REFERENCES;
 
 //not all of them, eg there is intentionally no div
 const BLOCK_LEVEL: [&'static str; 13] = ["p", "br", "li", "tr", "header", "footer", "section", "h1", "h2", "h3", "h4", "h5", "h6"];
 
-pub const REPLACE: [(&'static str, &'static str); 7] = [
-  ("&nbsp;", " "),
-  ("&#x27;", "'"),
-  ("&quot;", "\""),
-  ("&#x2F;", "/"),
-  ("&gt;", ">"),
-  ("&lt;", "<"),
-  ("&amp;", "&"),
-];
-
+/// Percent encodings of some characters that show up in urls, for use with [`handle_escaped`].
 pub const URL_REPLACE: [(&'static str, &'static str); 12] = [
   ("%22", "\""),
   ("%2B", "+"),
@@ -44,6 +29,7 @@ fn is_whitespace(c: char) -> bool {
   c == ' ' || c == '\x09'
 }
 
+/// Replace each of the first strings in `replace_list` with the second, or the other way around if `inverse`.
 pub fn handle_escaped(s: &str, replace_list: Vec<(&str, &str)>, inverse: bool) -> String {
   let mut s = s.to_string();
   for rp in replace_list {
@@ -56,73 +42,399 @@ pub fn handle_escaped(s: &str, replace_list: Vec<(&str, &str)>, inverse: bool) -
   s
 }
 
+//windows-1252, for numeric references from 0x80 to 0x9F (people write &#150; and mean an en dash)
+//the holes in windows-1252 stay as they are
+const C1_REPLACE: [char; 32] = [
+  '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+  '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+  '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+  '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// Decode character references (&amp;, &eacute;, &#8217;, &#x2014;, etc), like the whatwg character reference state.
+///
+/// In attributes, legacy references without a ; aren't decoded if followed by = or alphanumeric, so urls like ?a=1&copy=2 survive.
+/// Anything that isn't a valid reference is left alone. [`parse`] already does this for text and attributes.
+pub fn decode_entities(s: &str, in_attribute: bool) -> String {
+  let mut decoded = String::new();
+  let mut rest = s;
+  while let Some(amp) = rest.find('&') {
+    decoded += &rest[..amp];
+    rest = &rest[amp + 1..];
+    if let Some(numeric) = rest.strip_prefix('#') {
+      let (radix, digits) = if let Some(hex) = numeric.strip_prefix(['x', 'X']) {
+        (16, hex)
+      } else {
+        (10, numeric)
+      };
+      let len = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+      if len == 0 {
+        decoded.push('&');
+        continue;
+      }
+      //too big to parse is also too big to be a char
+      let value = u32::from_str_radix(&digits[..len], radix).unwrap_or(u32::MAX);
+      decoded.push(match value {
+        0x80..=0x9F => C1_REPLACE[value as usize - 0x80],
+        //char::from_u32 rejects surrogates and anything above 0x10FFFF
+        _ => char::from_u32(value).filter(|c| *c != '\0').unwrap_or('\u{FFFD}'),
+      });
+      rest = &digits[len..];
+      rest = rest.strip_prefix(';').unwrap_or(rest);
+    } else {
+      //longest name in the table that the text starts with. names are all ascii alphanumeric, then maybe a ;
+      let name_len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+      let candidate = &rest[..if rest[name_len..].starts_with(';') { name_len + 1 } else { name_len }];
+      let found = (1..=candidate.len()).rev().find_map(|len| {
+        NAMED_REFERENCES.binary_search_by(|(name, _)| name.cmp(&&candidate[..len])).ok().map(|i| (len, NAMED_REFERENCES[i].1))
+      });
+      match found {
+        Some((len, value)) if candidate[..len].ends_with(';') || !in_attribute || !rest[len..].starts_with(|c: char| c == '=' || c.is_ascii_alphanumeric()) => {
+          decoded += value;
+          rest = &rest[len..];
+        },
+        _ => decoded.push('&'),
+      }
+    }
+  }
+  decoded + rest
+}
+
+/// Escape text to put in generated html (eg, internal pages).
+pub fn escape_html(s: &str) -> String {
+  s.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+/// Strip the quotes from an attribute value. [`Node::attributes`] values keep them, as written.
 pub fn remove_quotes(s: String) -> String {
-  //todo: remove only if quotes
-  let s_len = s.len();
-  if s_len > 1 {
-    s.substring(1, s.len() - 1).to_string()
+  //only if actually quoted, attribute values can be unquoted
+  let s_len = s.chars().count();
+  if s_len > 1 && ((s.starts_with("\"") && s.ends_with("\"")) || (s.starts_with("'") && s.ends_with("'"))) {
+    s[1..s.len() - 1].to_string() //quotes are one byte
   } else {
-    s //length is 0 or 1, can't strip no quotes...
+    s //length is 0 or 1, or not quoted, can't strip no quotes...
   }
 }
 
+/// How a form is submitted.
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum FormSubmitMethod {
+  /// Inputs go in the query string.
   Get,
+  /// Inputs go in the body.
   Post,
 }
 
+/// A `<form>`.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Form {
-  pub action: Option<String>, //url, if None, defaults to same url
+  /// Url to submit to. If None, defaults to same url.
+  pub action: Option<String>,
+  /// How to submit.
   pub method: FormSubmitMethod,
+  /// Names of the form's inputs.
   pub input_names: Vec<String>,
 }
 
-#[derive(Debug, PartialEq)]
+/// How text looks. Each element's style is added to the styles of the elements it is in (see [`Style::add`]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Style {
+  /// Heading level, 1 to 6, or 0 if not a heading.
+  pub heading: u8,
+  /// Bold (`<b>`, `<strong>`).
+  pub bold: bool,
+  /// Italic (`<i>`, `<em>`, etc).
+  pub italic: bool,
+  /// Monospace (`<code>`, `<kbd>`, etc).
+  pub monospace: bool,
+  /// Underlined (`<u>`, `<ins>`).
+  pub underline: bool,
+  /// Struck through (`<s>`, `<del>`).
+  pub strikethrough: bool,
+}
+
+impl Style {
+  /// The style of an element, if it has one.
+  pub fn of_tag(tag_name: &str) -> Option<Style> {
+    let style = Style::default();
+    Some(match tag_name {
+      "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Style { heading: tag_name.as_bytes()[1] - b'0', ..style },
+      "b" | "strong" | "th" => Style { bold: true, ..style },
+      "i" | "em" | "cite" | "var" | "dfn" => Style { italic: true, ..style },
+      "code" | "kbd" | "samp" | "tt" => Style { monospace: true, ..style },
+      "u" | "ins" => Style { underline: true, ..style },
+      "s" | "strike" | "del" => Style { strikethrough: true, ..style },
+      _ => return None,
+    })
+  }
+
+  /// This style with `other` on top: the innermost heading level wins, and everything else is on if either has it.
+  pub fn add(&self, other: &Style) -> Style {
+    Style {
+      heading: if other.heading > 0 { other.heading } else { self.heading },
+      bold: self.bold || other.bold,
+      italic: self.italic || other.italic,
+      monospace: self.monospace || other.monospace,
+      underline: self.underline || other.underline,
+      strikethrough: self.strikethrough || other.strikethrough,
+    }
+  }
+}
+
+/// A cell of a [`Table`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableCell {
+  /// What is in it.
+  pub outputs: Vec<OutputType>,
+  /// Number of columns it spans, at least 1.
+  pub colspan: usize,
+  /// Number of rows it spans, at least 1.
+  pub rowspan: usize,
+}
+
+/// A table, laid out in columns by [`crate::layout::layout`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+  /// The cells of each row. Cells spanning several rows are only in the first.
+  pub rows: Vec<Vec<TableCell>>,
+  /// Whether the cells have borders around them.
+  pub border: bool,
+}
+
+impl Table {
+  /// The table as one cell after another, each on its own line, for when it can't be shown as a table.
+  pub fn linearise(self) -> Vec<OutputType> {
+    let mut outputs = Vec::new();
+    for cell in self.rows.into_iter().flatten() {
+      outputs.extend(cell.outputs);
+      outputs.push(OutputType::Newline);
+    }
+    outputs
+  }
+}
+
+//names of text inputs in outputs, including in tables
+fn input_names(outputs: &[OutputType], names: &mut Vec<String>) {
+  for o in outputs {
+    if let OutputType::TextInput(name, _) = o {
+      names.push(name.to_string());
+    } else if let OutputType::Table(table) = o {
+      for cell in table.rows.iter().flatten() {
+        input_names(&cell.outputs, names);
+      }
+    }
+  }
+}
+
+/// What a page is made of, once the tree is flattened. See [`Node::to_output`].
+#[derive(Clone, Debug, PartialEq)]
 pub enum OutputType {
-  StartLink(String), //url
+  /// Start of a link, to the url (still quoted, as written).
+  StartLink(String),
+  /// End of the current link.
   EndLink,
+  /// Some text.
   Text(String),
+  /// End of a line (or of a block element).
   Newline,
   //only support one per line, once indented, will keep being indented until overriden, for now
+  /// Indent (in pixels) of lines from now on.
   Indent(usize),
-  TextInput(String, String), //name, default value
+  /// A text input, with its name and default value.
+  TextInput(String, String),
+  /// End of a form. Its inputs come before it.
   Form(Form),
+  /// The id (or name, for `<a>`) of an element starting here, for #fragment links.
+  Anchor(String),
+  /// Start (true) or end (false) of preformatted text, whose whitespace is kept as is rather than collapsed. It is monospace, and isn't wrapped.
+  Preformatted(bool),
+  /// Start of some styled text. The style is added to any already started, until the matching [`OutputType::EndStyle`].
+  StartStyle(Style),
+  /// End of the most recently started style.
+  EndStyle,
+  /// A table, on lines of its own.
+  Table(Table),
+  /// The bullet or number of a list item, starting a new line. It goes just before the indent, so the item's text (wrapped lines and all) lines up at the indent.
+  ListMarker(String),
 }
 
+const LIST_INDENT: usize = 40;
+
+/// Bullets of unordered lists, by how deeply nested they are: disc, circle, then square for anything deeper.
+pub const BULLETS: [char; 3] = ['•', '◦', '▪'];
+
+//what to_output needs to know about the elements a node is in
+#[derive(Default)]
+struct Context {
+  indent: usize,
+  lists: Vec<List>,
+}
+
+struct List {
+  kind: ListKind,
+  number: i64, //of the next item
+  step: i64, //1, or -1 if reversed
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ListKind {
+  Bullet(char),
+  Decimal,
+  LowerAlpha,
+  UpperAlpha,
+  LowerRoman,
+  UpperRoman,
+}
+
+fn roman(mut n: i64) -> String {
+  const NUMERALS: [(i64, &'static str); 13] = [(1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"), (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i")];
+  let mut s = String::new();
+  for (value, numeral) in NUMERALS {
+    while n >= value {
+      s += numeral;
+      n -= value;
+    }
+  }
+  s
+}
+
+//1 is a, 26 is z, 27 is aa
+fn alphabetic(mut n: i64) -> String {
+  let mut s = Vec::new();
+  while n > 0 {
+    n -= 1;
+    s.push((b'a' + (n % 26) as u8) as char);
+    n /= 26;
+  }
+  s.into_iter().rev().collect()
+}
+
+impl List {
+  fn new(node: &Node, depth: usize) -> Self {
+    let attribute = |name: &str| node.attributes.get(name).map(|v| remove_quotes(v.to_string()));
+    if node.tag_name != "ol" {
+      let bullet = match attribute("type").map(|t| t.to_lowercase()).as_deref() {
+        Some("disc") => BULLETS[0],
+        Some("circle") => BULLETS[1],
+        Some("square") => BULLETS[2],
+        _ => BULLETS[depth.min(BULLETS.len() - 1)],
+      };
+      return List { kind: ListKind::Bullet(bullet), number: 1, step: 1 };
+    }
+    let kind = match attribute("type").as_deref() {
+      Some("a") => ListKind::LowerAlpha,
+      Some("A") => ListKind::UpperAlpha,
+      Some("i") => ListKind::LowerRoman,
+      Some("I") => ListKind::UpperRoman,
+      _ => ListKind::Decimal,
+    };
+    let reversed = node.attributes.contains_key("reversed");
+    //reversed lists count down to 1 by default
+    let items = node.children.iter().filter(|c| c.tag_name == "li").count() as i64;
+    let number = attribute("start").and_then(|s| s.trim().parse().ok()).unwrap_or(if reversed { items } else { 1 });
+    List { kind, number, step: if reversed { -1 } else { 1 } }
+  }
+
+  //the marker for the next item, which is number value if it has one
+  fn marker(&mut self, value: Option<i64>) -> String {
+    let number = value.unwrap_or(self.number);
+    self.number = number + self.step;
+    let label = match self.kind {
+      ListKind::Bullet(bullet) => return format!("{} ", bullet),
+      //letters and numerals can't do zero or less
+      _ if number <= 0 => number.to_string(),
+      ListKind::Decimal => number.to_string(),
+      ListKind::LowerAlpha => alphabetic(number),
+      ListKind::UpperAlpha => alphabetic(number).to_uppercase(),
+      ListKind::LowerRoman => roman(number),
+      ListKind::UpperRoman => roman(number).to_uppercase(),
+    };
+    format!("{}. ", label)
+  }
+}
+
+/// An element or text node of a parsed page.
 #[derive(Clone, Default, Debug, PartialEq)]
 pub struct Node {
-  //for text nodes, tag_name is the content
+  /// Tag name, lowercase. For text nodes, this is the text.
   pub tag_name: String,
+  /// Attribute names (lowercase) and values. Values are kept as written, quotes and all (see [`remove_quotes`]).
   pub attributes: HashMap<String, String>,
+  /// Child nodes.
   pub children: Vec<Box<Node>>,
+  /// Whether this is text rather than an element.
   pub text_node: bool,
 }
 
 impl Node {
+  /// Flatten this node and its children into what should be shown.
   pub fn to_output(&self) -> Vec<OutputType> {
+    self.outputs(&mut Context::default())
+  }
+
+  fn outputs(&self, context: &mut Context) -> Vec<OutputType> {
     let mut output = Vec::new();
     let mut link = false;
     let mut form = None;
-    let mut input_names = Vec::new();
+    let mut input_names_found = Vec::new();
+    if !self.text_node {
+      if let Some(id) = self.attributes.get("id").or(if self.tag_name == "a" { self.attributes.get("name") } else { None }) {
+        output.push(OutputType::Anchor(remove_quotes(id.to_string())));
+      }
+    }
     if Some(&"\"true\"".to_string()) == self.attributes.get("aria-hidden") {
       return output;
     } else if self.text_node {
-      output.push(OutputType::Text(handle_escaped(&self.tag_name.clone(), REPLACE.to_vec(), false)));
+      output.push(OutputType::Text(self.tag_name.clone()));
       return output;
     } else if self.tag_name == "script" || self.tag_name == "style" {
       //ignore script and style tags
       return output;
+    } else if ["pre", "listing", "xmp", "plaintext"].contains(&self.tag_name.as_str()) {
+      output.push(OutputType::Preformatted(true));
+      for c in &self.children {
+        output.extend(c.outputs(context));
+      }
+      //the line ends anyways
+      if let Some(OutputType::Text(last)) = output.last_mut() {
+        if last.ends_with('\n') {
+          last.pop();
+        }
+      }
+      output.push(OutputType::Preformatted(false));
+      output.push(OutputType::Newline);
+      return output;
+    } else if self.tag_name == "table" {
+      output.extend(self.table_outputs());
+      return output;
+    } else if ["ul", "ol", "menu", "dir"].contains(&self.tag_name.as_str()) {
+      //items are indented a level more, then back to how it was
+      let outer_indent = context.indent;
+      context.indent += LIST_INDENT;
+      context.lists.push(List::new(self, context.lists.len()));
+      output.push(OutputType::Indent(context.indent));
+      for c in &self.children {
+        output.extend(c.outputs(context));
+      }
+      context.lists.pop();
+      context.indent = outer_indent;
+      output.push(OutputType::Indent(outer_indent));
+      return output;
     } else if self.tag_name == "li" {
-      output.push(OutputType::Text("-".to_string()));
+      let value = self.attributes.get("value").and_then(|v| remove_quotes(v.to_string()).trim().parse().ok());
+      let marker = match context.lists.last_mut() {
+        Some(list) => list.marker(value),
+        None => "• ".to_string(),
+      };
+      output.push(OutputType::ListMarker(marker));
     } else if let Some(href) = self.attributes.get("href") {
       link = true;
       //check if href is ddg link that fucks us over in lite.duckduckgo.com
-      // //duckduckgo.com/l/?uddg=https%3A%2F%2Fwww.merriam%2Dwebster.com%2Fdictionary%2Ftest&amp;rut=f86942690bea49b300b8ae8d470dbbe18ad217aded1750804e3f33a95da21cf2
+      // //duckduckgo.com/l/?uddg=https%3A%2F%2Fwww.merriam%2Dwebster.com%2Fdictionary%2Ftest&rut=f86942690bea49b300b8ae8d470dbbe18ad217aded1750804e3f33a95da21cf2
+      #[allow(clippy::needless_borrow)]
       let href = if href.starts_with("\"//duckduckgo.com/l/?uddg=") {
         //todo: only take from &amp onward
-        "\"".to_string() + &handle_escaped(&href.chars().skip(26).collect::<String>().split("&amp;").next().unwrap(), URL_REPLACE.to_vec(), false) + "\""
+        "\"".to_string() + &handle_escaped(&href.chars().skip(26).collect::<String>().split("&").next().unwrap(), URL_REPLACE.to_vec(), false) + "\""
       } else {
         href.to_string()
       };
@@ -131,7 +443,8 @@ impl Node {
       //non-standard indent attribute, basically just to support HN
       let indent = remove_quotes(indent.to_string());
       if let Ok(indent) = indent.parse::<usize>() {
-        output.push(OutputType::Indent(indent * 32));
+        context.indent = indent * 32;
+        output.push(OutputType::Indent(context.indent));
       }
     } else if self.tag_name == "input" || self.tag_name == "textarea" {
       if let Some(name) = self.attributes.get("name") {
@@ -162,27 +475,32 @@ impl Node {
       } else {
         Some(FormSubmitMethod::Get)
       };
+      #[allow(clippy::manual_map)]
       if let Some(method) = method {
         form = Some(Form {
-          //wikipedia puts &amp; in the action url??? is that how its supposed to be? do I need to worry about href?
-          action: if let Some(action) = action { Some(handle_escaped(&remove_quotes(action.to_string()), REPLACE.to_vec(), false)) } else { None },
+          action: if let Some(action) = action { Some(remove_quotes(action.to_string())) } else { None },
           method,
           input_names: Vec::new(),
         });
       }
     }
+    let style = Style::of_tag(&self.tag_name);
+    if let Some(style) = style {
+      output.push(OutputType::StartStyle(style));
+    }
     for c in &self.children {
-      let children_output = c.to_output();
+      let children_output = c.outputs(context);
       if form.is_some() {
-        for cc in &children_output {
-          if let OutputType::TextInput(name, _) = cc {
-            input_names.push(name.to_string());
-          }
-        }
+        input_names(&children_output, &mut input_names_found);
       }
       output.extend(children_output);
     }
-    if BLOCK_LEVEL.contains(&self.tag_name.as_str()) {
+    if style.is_some() {
+      output.push(OutputType::EndStyle);
+    }
+    if self.tag_name == "li" && output.iter().rev().find(|o| !matches!(o, OutputType::Indent(_))) == Some(&OutputType::Newline) {
+      //already ended by a nested list
+    } else if BLOCK_LEVEL.contains(&self.tag_name.as_str()) {
       output.push(OutputType::Newline);
     } else if link {
       output.push(OutputType::EndLink);
@@ -190,171 +508,544 @@ impl Node {
       let form = Form {
         action: form.action,
         method: form.method,
-        input_names,
+        input_names: input_names_found,
       };
       output.push(OutputType::Form(form));
     }
     output
   }
-}
 
-fn add_to_parent(top_level_nodes: &mut Vec<Box<Node>>, parent_location: &[usize], node: Node) -> usize {
-  if parent_location.len() == 0 {
-    top_level_nodes.push(Box::new(node));
-    top_level_nodes.len() - 1
-  } else {
-    let mut parent_children = &mut top_level_nodes[parent_location[0]].children;
-    for i in &parent_location[1..] {
-      parent_children = &mut parent_children[*i].children;
+  //the caption (if any) then the table itself
+  fn table_outputs(&self) -> Vec<OutputType> {
+    let mut output = Vec::new();
+    let mut rows = Vec::new();
+    let span = |cell: &Node, name: &str| cell.attributes.get(name).and_then(|s| remove_quotes(s.to_string()).trim().parse::<usize>().ok()).unwrap_or(1).clamp(1, 1000);
+    //rows can be in a thead, tbody or tfoot, or right in the table
+    let row_nodes = self.children.iter().flat_map(|c| if c.tag_name == "tr" { vec![c] } else if ["thead", "tbody", "tfoot"].contains(&c.tag_name.as_str()) { c.children.iter().collect() } else { Vec::new() });
+    for c in &self.children {
+      if c.tag_name == "caption" {
+        output.extend(c.to_output());
+        output.push(OutputType::Newline);
+      }
+    }
+    for row in row_nodes.filter(|r| r.tag_name == "tr") {
+      rows.push(row.children.iter().filter(|c| c.tag_name == "td" || c.tag_name == "th").map(|cell| TableCell {
+        outputs: cell.to_output(),
+        colspan: span(cell, "colspan"),
+        rowspan: span(cell, "rowspan"),
+      }).collect());
     }
-    let loc = parent_children.len();
-    parent_children.push(Box::new(node));
-    loc
+    //wikipedia does its borders with css, but they are what wikitable means
+    let border = self.attributes.get("border").is_some_and(|b| remove_quotes(b.to_string()) != "0") || self.attributes.get("class").is_some_and(|c| c.contains("wikitable"));
+    output.push(OutputType::Table(Table { rows, border }));
+    output
   }
 }
 
-pub fn parse(xml_string: &str) -> Vec<Box<Node>> {
-  let mut top_level_nodes = Vec::new();
-  let mut chars = xml_string.chars().peekable();
-  let mut parent_location: Vec<usize> = Vec::new(); //vec of indexes
-  let mut recording_tag_name = false;
-  let mut whitespace_only = true; //ignore leading whitespace on each line
-  let mut attribute_name = String::new();
-  let mut recording_attribute_value = false;
-  let mut in_string = false;
-  let mut quote_type = None;
-  let mut current_node: Option<Node> = None;
-  loop {
-    let c = chars.next();
-    if c.is_none() {
-      break;
+//tokenizer, mostly following the whatwg html tokenizer states, but less pedantic about parse errors
+//comments (and bogus comments, like <?xml ...>) are skipped entirely
+
+#[derive(Debug, PartialEq)]
+enum Token {
+  Doctype(String), //name, lowercase
+  StartTag(String, Vec<(String, String)>, bool), //name, attributes, self closing
+  EndTag(String),
+  Text(String),
+  CData(String),
+}
+
+fn is_tag_whitespace(c: char) -> bool {
+  c == ' ' || c == '\t' || c == '\n' || c == '\x0C'
+}
+
+struct Tokenizer {
+  chars: Vec<char>,
+  pos: usize,
+  raw_text_end: Option<String>, //set by the tree builder after <script>, <title>, etc
+  cdata_allowed: bool, //only in svg/math, otherwise <![CDATA[ is a bogus comment
+}
+
+impl Tokenizer {
+  fn new(html_string: &str) -> Self {
+    Self {
+      //input stream preprocessing: normalise newlines
+      chars: html_string.replace("\r\n", "\n").replace('\r', "\n").chars().collect(),
+      pos: 0,
+      raw_text_end: None,
+      cdata_allowed: false,
     }
-    let c = c.unwrap();
-    if let Some(ref mut n) = current_node {
-      if n.tag_name == "!--" {
-        //this is a comment... skip!
-        current_node = None;
-        recording_tag_name = false;
-        let mut dash_count = 0;
-        loop {
-          let c2 = chars.next();
-          if c2.is_none() {
-            break;
-          }
-          let c2 = c2.unwrap();
-          if c2 == '>' && dash_count == 2 {
-            break;
-          } else if c2 == '-' {
-            dash_count += 1;
-          } else {
-            dash_count = 0;
+  }
+
+  fn peek(&self, offset: usize) -> Option<char> {
+    self.chars.get(self.pos + offset).copied()
+  }
+
+  fn starts_with(&self, s: &str, case_insensitive: bool) -> bool {
+    let mut i = self.pos;
+    for c in s.chars() {
+      match self.chars.get(i) {
+        Some(c2) if *c2 == c || (case_insensitive && c2.eq_ignore_ascii_case(&c)) => i += 1,
+        _ => return false,
+      }
+    }
+    true
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.peek(0).is_some_and(is_tag_whitespace) {
+      self.pos += 1;
+    }
+  }
+
+  //consume until (and including) `end`, returning everything before it. if `end` is never found, the rest of the input
+  fn take_until(&mut self, end: &str) -> String {
+    let start = self.pos;
+    while self.pos < self.chars.len() {
+      if self.starts_with(end, false) {
+        let taken = self.chars[start..self.pos].iter().collect();
+        self.pos += end.chars().count();
+        return taken;
+      }
+      self.pos += 1;
+    }
+    self.chars[start..].iter().collect()
+  }
+
+  //whether the '<' at pos starts some kind of tag or markup, rather than being a literal '<' in text
+  fn is_tag_start(&self) -> bool {
+    self.peek(0) == Some('<') && self.peek(1).is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!' || c == '?')
+  }
+
+  fn name(&mut self) -> String {
+    let start = self.pos;
+    while self.peek(0).is_some_and(|c| !is_tag_whitespace(c) && c != '/' && c != '>') {
+      self.pos += 1;
+    }
+    self.chars[start..self.pos].iter().collect::<String>().to_lowercase()
+  }
+
+  //attributes and the closing > (or />) of a tag. None if the input ends inside the tag, in which case it is dropped
+  //values are kept exactly as written, quotes and all
+  fn tag_rest(&mut self) -> Option<(Vec<(String, String)>, bool)> {
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    loop {
+      self.skip_whitespace();
+      match self.peek(0)? {
+        '>' => {
+          self.pos += 1;
+          return Some((attributes, false));
+        },
+        '/' => {
+          self.pos += 1;
+          if self.peek(0) == Some('>') {
+            self.pos += 1;
+            return Some((attributes, true));
           }
-        }
-      } else if (n.tag_name == "script" || n.tag_name == "style") && !n.text_node {
-        //need to handle this carefully since < and > could be present
-        let mut so_far = String::new();
-        let loc = add_to_parent(&mut top_level_nodes, &parent_location, n.clone());
-        parent_location.push(loc);
-        //won't handle if </script> appears in a string
-        loop {
-          let c2 = chars.next();
-          if c2.is_none() {
-            break;
+        },
+        _ => {
+          //first char is always part of the name, even if it is a =
+          let start = self.pos;
+          self.pos += 1;
+          while self.peek(0).is_some_and(|c| !is_tag_whitespace(c) && c != '/' && c != '>' && c != '=') {
+            self.pos += 1;
           }
-          let c2 = c2.unwrap();
-          so_far += &c2.to_string();
-          let end_len = n.tag_name.len() + 3;
-          if so_far.len() >= end_len {
-            let end = so_far.chars().count();
-            if so_far.substring(end - end_len, end) == "</".to_string() + &n.tag_name + ">" {
-              current_node = None;
-              let n2: Node = Node { text_node: true, tag_name: so_far.substring(0, end - end_len).to_string(), ..Default::default() };
-              add_to_parent(&mut top_level_nodes, &parent_location, n2);
-              parent_location.pop();
-              recording_tag_name = false;
-              break;
+          let name = self.chars[start..self.pos].iter().collect::<String>().to_lowercase();
+          self.skip_whitespace();
+          let mut value = String::new();
+          if self.peek(0) == Some('=') {
+            self.pos += 1;
+            self.skip_whitespace();
+            let start = self.pos;
+            match self.peek(0)? {
+              q @ ('"' | '\'') => {
+                self.pos += 1;
+                while self.peek(0).is_some_and(|c| c != q) {
+                  self.pos += 1;
+                }
+                self.peek(0)?;
+                self.pos += 1;
+              },
+              _ => {
+                while self.peek(0).is_some_and(|c| !is_tag_whitespace(c) && c != '>') {
+                  self.pos += 1;
+                }
+              },
             }
+            value = self.chars[start..self.pos].iter().collect();
           }
+          //duplicate attributes: first one wins
+          if !attributes.iter().any(|(n, _)| n == &name) {
+            attributes.push((name, value));
+          }
+        },
+      }
+    }
+  }
+
+  //contents of script, style, textarea, etc, which end only at the matching end tag
+  fn raw_text(&mut self, end: &str) -> String {
+    let start = self.pos;
+    while self.pos < self.chars.len() {
+      if self.peek(0) == Some('<') && self.peek(1) == Some('/') {
+        self.pos += 2;
+        let matches = self.starts_with(end, true) && self.peek(end.len()).is_none_or(|c| is_tag_whitespace(c) || c == '/' || c == '>');
+        self.pos -= 2;
+        if matches {
+          break;
         }
-      } else if (c == ' ' || c == '\n') && recording_tag_name && !n.text_node {
-        recording_tag_name = false;
-      } else if (c == '>' || (c == '/' && chars.peek().unwrap_or(&' ') == &'>') || (n.text_node && chars.peek().unwrap_or(&' ') == &'<')) && (!in_string || quote_type == Some(c)) {
-        if n.text_node {
-          n.tag_name += &c.to_string();
+      }
+      self.pos += 1;
+    }
+    self.chars[start..self.pos].iter().collect()
+  }
+}
+
+impl Iterator for Tokenizer {
+  type Item = Token;
+
+  fn next(&mut self) -> Option<Token> {
+    loop {
+      if let Some(end) = self.raw_text_end.take() {
+        let text = self.raw_text(&end);
+        if !text.is_empty() {
+          return Some(Token::Text(text));
         }
-        let loc = add_to_parent(&mut top_level_nodes, &parent_location, n.clone());
-        if c == '>' && !SELF_CLOSING.contains(&n.tag_name.as_str()) {
-          parent_location.push(loc);
-        } else if c == '/' {
-          chars.next();
+      }
+      if self.pos >= self.chars.len() {
+        return None;
+      }
+      if !self.is_tag_start() {
+        //text, up to the next tag. a '<' that doesn't start a tag is just text
+        let start = self.pos;
+        self.pos += 1;
+        while self.pos < self.chars.len() && !self.is_tag_start() {
+          self.pos += 1;
         }
-        //catch attributes like disabled with no = or value
-        if attribute_name.len() > 0 && !recording_attribute_value {
-          n.attributes.entry(attribute_name.clone()).insert_entry(String::new());
+        return Some(Token::Text(self.chars[start..self.pos].iter().collect()));
+      }
+      self.pos += 1;
+      if self.starts_with("!--", false) {
+        self.pos += 3;
+        //<!--> and <!---> are (empty) comments too
+        if self.starts_with(">", false) {
+          self.pos += 1;
+        } else if self.starts_with("->", false) {
+          self.pos += 2;
+        } else {
+          self.take_until("-->");
         }
-        recording_tag_name = false;
-        recording_attribute_value = false;
-        attribute_name = String::new();
-        current_node = None;
-      } else if recording_tag_name {
-        n.tag_name += &c.to_string();
-      } else if c == ' ' && !in_string {
-        //catch attributes like disabled with no = or value
-        if attribute_name.len() > 0 && !recording_attribute_value {
-          n.attributes.entry(attribute_name.clone()).insert_entry(String::new());
-        } else if recording_attribute_value {
-          //^this can just be an "else", not an "else if", probably
-          recording_attribute_value = false;
+      } else if self.starts_with("!doctype", true) {
+        self.pos += 8;
+        self.skip_whitespace();
+        let name = self.name();
+        self.take_until(">");
+        return Some(Token::Doctype(name));
+      } else if self.starts_with("![CDATA[", false) {
+        self.pos += 8;
+        if self.cdata_allowed {
+          return Some(Token::CData(self.take_until("]]>")));
         }
-        attribute_name = String::new();
-      } else if recording_attribute_value {
-        if (c == '"' || c == '\'') && (quote_type == Some(c) || quote_type.is_none()) {
-          in_string = *n.attributes.get(&attribute_name).unwrap() == "";
-          quote_type = Some(c);
-          if !in_string {
-            quote_type = None;
-          }
+        self.take_until(">");
+      } else if self.peek(0) == Some('/') {
+        self.pos += 1;
+        if self.peek(0).is_some_and(|c| c.is_ascii_alphabetic()) {
+          let name = self.name();
+          //end tags can technically have attributes, they're just ignored
+          self.tag_rest()?;
+          return Some(Token::EndTag(name));
         }
-        n.attributes.entry(attribute_name.clone()).and_modify(|s| *s += &c.to_string());
-      } else if c == '=' {
-        n.attributes.entry(attribute_name.clone()).insert_entry(String::new());
-        recording_attribute_value = true;
+        //</> is ignored, anything else is a bogus comment
+        self.take_until(">");
+      } else if self.peek(0).is_some_and(|c| c.is_ascii_alphabetic()) {
+        let name = self.name();
+        let (attributes, self_closing) = self.tag_rest()?;
+        return Some(Token::StartTag(name, attributes, self_closing));
       } else {
-        attribute_name += &c.to_string();
+        //<!whatever> and <?whatever>, bogus comments
+        self.take_until(">");
+      }
+    }
+  }
+}
+
+//tree construction, a simplified version of the whatwg "in body" insertion mode
+//no implied html/head/body/tbody and no reconstructing formatting elements, but implied end tags and mismatched end tags are handled
+
+const VOID_ELEMENTS: [&'static str; 18] = ["area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input", "keygen", "link", "meta", "param", "source", "track", "wbr"];
+
+//elements whose contents are not parsed as html
+const RAW_TEXT_ELEMENTS: [&'static str; 9] = ["script", "style", "textarea", "title", "xmp", "iframe", "noembed", "noframes", "plaintext"];
+
+//start tags that close an open <p>
+const CLOSES_P: [&'static str; 38] = ["address", "article", "aside", "blockquote", "center", "details", "dialog", "dir", "div", "dl", "fieldset", "figcaption", "figure", "footer", "header", "hgroup", "main", "menu", "nav", "ol", "p", "search", "section", "summary", "ul", "h1", "h2", "h3", "h4", "h5", "h6", "pre", "listing", "form", "table", "hr", "xmp", "plaintext"];
+
+const HEADINGS: [&'static str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+//end tags that close the element only if it is in (default) scope, closing anything open inside it
+const SCOPED_END_TAGS: [&'static str; 47] = ["address", "article", "aside", "blockquote", "button", "center", "details", "dialog", "dir", "div", "dl", "dd", "dt", "fieldset", "figcaption", "figure", "footer", "header", "hgroup", "listing", "main", "menu", "nav", "ol", "pre", "search", "section", "summary", "ul", "form", "applet", "marquee", "object", "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u"];
+
+const TABLE_ELEMENTS: [&'static str; 8] = ["table", "caption", "tbody", "thead", "tfoot", "tr", "td", "th"];
+
+const DEFAULT_SCOPE: [&'static str; 9] = ["applet", "caption", "html", "table", "td", "th", "marquee", "object", "template"];
+
+const TABLE_SCOPE: [&'static str; 3] = ["html", "table", "template"];
+
+//the "special" category. unmatched end tags don't close these
+const SPECIAL: [&'static str; 83] = ["address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote", "body", "br", "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div", "dl", "dt", "embed", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "iframe", "img", "input", "keygen", "li", "link", "listing", "main", "marquee", "menu", "meta", "nav", "noembed", "noframes", "noscript", "object", "ol", "p", "param", "plaintext", "pre", "script", "search", "section", "select", "source", "style", "summary", "table", "tbody", "td", "template", "textarea", "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp"];
+
+#[derive(Default)]
+struct TreeBuilder {
+  top_level_nodes: Vec<Box<Node>>,
+  parent_location: Vec<usize>, //vec of indexes
+  open_elements: Vec<String>, //tag names, same length as parent_location
+  started: bool,
+}
+
+impl TreeBuilder {
+  fn children(&mut self) -> &mut Vec<Box<Node>> {
+    let mut children = &mut self.top_level_nodes;
+    for i in &self.parent_location {
+      children = &mut children[*i].children;
+    }
+    children
+  }
+
+  fn insert(&mut self, node: Node, push: bool) {
+    let name = node.tag_name.clone();
+    let children = self.children();
+    children.push(Box::new(node));
+    let loc = children.len() - 1;
+    if push {
+      self.parent_location.push(loc);
+      self.open_elements.push(name);
+    }
+  }
+
+  fn insert_text(&mut self, text: &str) {
+    if text.is_empty() {
+      return;
+    }
+    let children = self.children();
+    if let Some(last) = children.last_mut().filter(|n| n.text_node) {
+      last.tag_name += text;
+    } else {
+      children.push(Box::new(Node { tag_name: text.to_string(), text_node: true, ..Default::default() }));
+    }
+  }
+
+  fn in_foreign(&self) -> bool {
+    self.open_elements.iter().any(|n| n == "svg" || n == "math")
+  }
+
+  fn current_is(&self, names: &[&str]) -> bool {
+    self.open_elements.last().is_some_and(|n| names.contains(&n.as_str()))
+  }
+
+  //index in open_elements of the closest of `names`, unless a scope boundary is hit first
+  fn in_scope(&self, names: &[&str], scope: &[&str]) -> Option<usize> {
+    for (i, n) in self.open_elements.iter().enumerate().rev() {
+      if names.contains(&n.as_str()) {
+        return Some(i);
+      } else if scope.contains(&n.as_str()) {
+        return None;
+      }
+    }
+    None
+  }
+
+  //pop the element at index and everything opened after it (implied end tags)
+  fn pop_to(&mut self, index: usize) {
+    self.parent_location.truncate(index);
+    self.open_elements.truncate(index);
+  }
+
+  fn close_p(&mut self) {
+    if let Some(i) = self.in_scope(&["p"], &[&DEFAULT_SCOPE[..], &["button"]].concat()) {
+      self.pop_to(i);
+    }
+  }
+
+  //for <li>, <dd> and <dt>, which close an earlier one unless something special (other than address, div, p) is in the way
+  fn close_list_item(&mut self, names: &[&str]) {
+    for (i, n) in self.open_elements.iter().enumerate().rev() {
+      if names.contains(&n.as_str()) {
+        self.pop_to(i);
+        return;
+      } else if SPECIAL.contains(&n.as_str()) && n != "address" && n != "div" && n != "p" {
+        return;
       }
-      //todo: record attributes
-    } else if c == '<' {
-      whitespace_only = false;
-      if chars.peek().unwrap_or(&' ') == &'/' {
-        parent_location.pop();
-        //skip the rest of the </ >
-        loop {
-          let c2 = chars.next();
-          if c2.is_none() || c2.unwrap() == '>' {
+    }
+  }
+
+  fn start_tag(&mut self, name: String, attributes: Vec<(String, String)>, self_closing: bool) {
+    let attributes = attributes.into_iter().map(|(n, v)| (n, decode_entities(&v, true))).collect();
+    let node = Node { tag_name: name.clone(), attributes, ..Default::default() };
+    if self.in_foreign() || name == "svg" || name == "math" {
+      //in svg and math, a self closing tag actually means something
+      self.insert(node, !self_closing);
+      return;
+    }
+    let name = name.as_str();
+    match name {
+      "html" | "head" | "body" if self.open_elements.iter().any(|n| n == name) => return,
+      "li" => self.close_list_item(&["li"]),
+      "dd" | "dt" => self.close_list_item(&["dd", "dt"]),
+      "button" | "a" | "nobr" => {
+        //can't nest these
+        if let Some(i) = self.in_scope(&[name], &DEFAULT_SCOPE) {
+          self.pop_to(i);
+        }
+      },
+      "td" | "th" | "tr" | "tbody" | "thead" | "tfoot" => {
+        if let Some(i) = self.in_scope(&["td", "th"], &TABLE_SCOPE) {
+          self.pop_to(i);
+        }
+        if name != "td" && name != "th" {
+          if let Some(i) = self.in_scope(&["tr"], &TABLE_SCOPE) {
+            self.pop_to(i);
+          }
+          if name != "tr" {
+            if let Some(i) = self.in_scope(&["tbody", "thead", "tfoot"], &TABLE_SCOPE) {
+              self.pop_to(i);
+            }
+          }
+        }
+      },
+      "option" | "optgroup" => {
+        if self.current_is(&["option"]) {
+          self.pop_to(self.open_elements.len() - 1);
+        }
+        if name == "optgroup" && self.current_is(&["optgroup"]) {
+          self.pop_to(self.open_elements.len() - 1);
+        }
+      },
+      _ => {},
+    }
+    if CLOSES_P.contains(&name) || name == "li" || name == "dd" || name == "dt" {
+      self.close_p();
+    }
+    if HEADINGS.contains(&name) && self.current_is(&HEADINGS) {
+      self.pop_to(self.open_elements.len() - 1);
+    }
+    self.insert(node, !VOID_ELEMENTS.contains(&name));
+  }
+
+  fn end_tag(&mut self, name: String) {
+    let name = name.as_str();
+    let index = match name {
+      //whatever comes after these still goes in the body, so they may as well be left open
+      "html" | "body" => return,
+      "br" => {
+        //</br> is treated as <br>
+        self.insert(Node { tag_name: name.to_string(), ..Default::default() }, false);
+        return;
+      },
+      "p" => {
+        let index = self.in_scope(&["p"], &[&DEFAULT_SCOPE[..], &["button"]].concat());
+        if index.is_none() {
+          //a </p> with no <p> makes an empty paragraph
+          self.insert(Node { tag_name: name.to_string(), ..Default::default() }, false);
+        }
+        index
+      },
+      "li" => self.in_scope(&["li"], &[&DEFAULT_SCOPE[..], &["ol", "ul"]].concat()),
+      _ if HEADINGS.contains(&name) => self.in_scope(&HEADINGS, &DEFAULT_SCOPE),
+      _ if TABLE_ELEMENTS.contains(&name) => self.in_scope(&[name], &TABLE_SCOPE),
+      _ if SCOPED_END_TAGS.contains(&name) => self.in_scope(&[name], &DEFAULT_SCOPE),
+      _ => {
+        //any other end tag: close the matching element, unless something special is open inside it
+        let mut index = None;
+        for (i, n) in self.open_elements.iter().enumerate().rev() {
+          if n == name {
+            index = Some(i);
+            break;
+          } else if SPECIAL.contains(&n.as_str()) && !self.in_foreign() {
             break;
           }
         }
-      } else {
-        current_node = Some(Default::default());
-        recording_tag_name = true;
-      }
-    } else if c == '\n' {
-      whitespace_only = true;
-    } else if !is_whitespace(c) || !whitespace_only {
-      if !is_whitespace(c) {
-        whitespace_only = false;
-      }
-      //text node
-      let n: Node = Node { tag_name: c.to_string(), text_node: true, ..Default::default() };
-      if chars.peek().unwrap_or(&' ') == &'<' {
-        add_to_parent(&mut top_level_nodes, &parent_location, n);
-      } else {
-        recording_tag_name = true;
-        current_node = Some(n);
+        index
+      },
+    };
+    if let Some(index) = index {
+      self.pop_to(index);
+    }
+  }
+
+  fn process(&mut self, token: Token) {
+    let started = self.started;
+    self.started = true;
+    match token {
+      Token::Doctype(name) => {
+        if self.open_elements.is_empty() {
+          let mut node = Node { tag_name: "!DOCTYPE".to_string(), ..Default::default() };
+          if !name.is_empty() {
+            node.attributes.insert(name, String::new());
+          }
+          self.insert(node, false);
+        }
+      },
+      Token::StartTag(name, attributes, self_closing) => self.start_tag(name, attributes, self_closing),
+      Token::EndTag(name) => self.end_tag(name),
+      Token::Text(text) => {
+        if self.current_is(&["textarea", "title"]) {
+          //rcdata, character references still work
+          self.insert_text(&decode_entities(&text, false));
+          return;
+        } else if self.current_is(&RAW_TEXT_ELEMENTS) {
+          self.insert_text(&text);
+          return;
+        } else if self.open_elements.iter().any(|n| n == "pre" || n == "listing") {
+          //whitespace is kept as is, except a newline right after the start tag
+          let first = self.current_is(&["pre", "listing"]) && self.children().is_empty();
+          let text = if first { text.strip_prefix("\r\n").or(text.strip_prefix('\n')).unwrap_or(&text) } else { &text };
+          self.insert_text(&decode_entities(text, false));
+          return;
+        }
+        //runs of whitespace, including the newlines and indentation of the html source, become one space
+        let only_whitespace = text.chars().all(|c| is_whitespace(c) || c == '\n' || c == '\r');
+        if only_whitespace && (!started || text.contains('\n')) {
+          //formatting between tags, or before the document starts
+          return;
+        }
+        let mut collapsed = String::with_capacity(text.len());
+        let mut in_run = !started;
+        for c in text.chars() {
+          if is_whitespace(c) || c == '\n' || c == '\r' {
+            if !in_run {
+              collapsed.push(' ');
+            }
+            in_run = true;
+          } else {
+            collapsed.push(c);
+            in_run = false;
+          }
+        }
+        self.insert_text(&decode_entities(&collapsed, false));
+      },
+      Token::CData(text) => self.insert_text(&text),
+    }
+  }
+}
+
+/// Parse html, mostly like the whatwg html parser does, with implied end tags, void elements, raw text elements and so on.
+///
+/// Unlike a real browser, `<html>`, `<head>` and `<body>` aren't added if missing, so a snippet gives just its own nodes.
+pub fn parse(xml_string: &str) -> Vec<Box<Node>> {
+  let mut tokenizer = Tokenizer::new(xml_string);
+  let mut builder: TreeBuilder = Default::default();
+  loop {
+    tokenizer.cdata_allowed = builder.in_foreign();
+    let Some(token) = tokenizer.next() else {
+      break;
+    };
+    if let Token::StartTag(name, _, _) = &token {
+      if RAW_TEXT_ELEMENTS.contains(&name.as_str()) && !builder.in_foreign() {
+        tokenizer.raw_text_end = Some(name.clone());
       }
     }
+    builder.process(token);
   }
-  top_level_nodes
+  builder.top_level_nodes
 }
 
 #[test]
@@ -414,6 +1105,102 @@ fn test_comments_xml_parse() {
 }
 
 #[test]
+fn test_implied_end_tags() {
+  let nodes = parse("<ul><li>one<li>two</ul><p>a<p>b<div>c</div>");
+  assert!(nodes.len() == 4);
+  assert!(nodes[0].children.len() == 2);
+  assert!(nodes[0].children[1].children[0].tag_name == "two");
+  assert!(nodes[1].tag_name == "p" && nodes[2].tag_name == "p");
+  assert!(nodes[2].children.len() == 1);
+  assert!(nodes[3].tag_name == "div");
+  //void elements never have children
+  let nodes = parse("<p>a<br>b<img src=x>c");
+  assert!(nodes[0].children.len() == 5);
+  let nodes = parse("<table><tr><td>a<td>b<tr><th>c<table><tr><td>d</table></table>");
+  assert!(nodes[0].children.len() == 2);
+  assert!(nodes[0].children[0].children.len() == 2);
+  assert!(nodes[0].children[1].children[0].children[1].tag_name == "table");
+  let nodes = parse("<h1>a<h2>b</h1>c");
+  assert!(nodes.len() == 3);
+  assert!(nodes[2].tag_name == "c");
+}
+
+#[test]
+fn test_mismatched_end_tags() {
+  let nodes = parse("<div><span>a</div>b");
+  assert!(nodes.len() == 2);
+  assert!(nodes[0].children[0].tag_name == "span");
+  assert!(nodes[1].tag_name == "b");
+  //stray end tags are ignored
+  let nodes = parse("<p>a</span>b</p><b>c<div>d</b>e</div>");
+  assert!(nodes[0].children.len() == 1);
+  assert!(nodes[0].children[0].tag_name == "ab");
+  //closing the formatting element closes the div too (no adoption agency algorithm here)
+  assert!(nodes[1].children[1].children[0].tag_name == "d");
+  assert!(nodes[2].tag_name == "e");
+  //</p> without a <p> is an empty paragraph
+  let nodes = parse("a</p>b");
+  assert!(nodes[1].tag_name == "p" && nodes[1].children.is_empty());
+  //case insensitive
+  let nodes = parse("<DIV Class=\"x\"><P>hi</p></Div>");
+  assert!(nodes.len() == 1);
+  assert!(nodes[0].tag_name == "div");
+  assert!(nodes[0].attributes.get("class").unwrap() == "\"x\"");
+  assert!(nodes[0].children[0].tag_name == "p");
+}
+
+#[test]
+fn test_raw_text_and_cdata() {
+  let nodes = parse("<textarea name=t><b>not bold</b></textarea><title>a<b</TITLE><script>if (a</b) {}</script >");
+  assert!(nodes.len() == 3);
+  assert!(nodes[0].children[0].tag_name == "<b>not bold</b>");
+  assert!(nodes[1].children[0].tag_name == "a<b");
+  assert!(nodes[2].children[0].tag_name == "if (a</b) {}");
+  //cdata is only a thing in svg and math
+  let nodes = parse("<p><![CDATA[x]]>y</p><svg><text><![CDATA[a<b&c]]></text><path d=\"\"/><path/></svg>");
+  assert!(nodes[0].children[0].tag_name == "y");
+  assert!(nodes[1].children[0].children[0].tag_name == "a<b&c");
+  assert!(nodes[1].children.len() == 3);
+  //literal <, and a doctype
+  let nodes = parse("<!doctype HTML><p>1 < 2 <3</p>");
+  assert!(nodes[0].tag_name == "!DOCTYPE");
+  assert!(nodes[1].children[0].tag_name == "1 < 2 <3");
+}
+
+#[test]
+fn test_decode_entities() {
+  assert_eq!(decode_entities("caf&eacute; &mdash; &#8217;&#x2014;&#X2014&hellip;", false), "café — ’——…");
+  assert_eq!(decode_entities("&#150; &#0; &#xD800; &#1114112; &#99999999999;", false), "\u{2013} \u{FFFD} \u{FFFD} \u{FFFD} \u{FFFD}");
+  //legacy references without ;
+  assert_eq!(decode_entities("&copy 2025 &notit; &notin;", false), "© 2025 ¬it; ∉");
+  assert_eq!(decode_entities("?a=1&copy=2&amp;b=3&not", true), "?a=1&copy=2&b=3¬");
+  //not references
+  assert_eq!(decode_entities("AT&T & &#; &#x; &bogus; &", false), "AT&T & &#; &#x; &bogus; &");
+}
+
+#[test]
+fn test_real_entities() {
+  use std::fs::read_to_string;
+  fn find<'a>(nodes: &'a [Box<Node>], f: &dyn Fn(&Node) -> bool) -> Option<&'a Node> {
+    nodes.iter().find_map(|n| if f(n) { Some(&**n) } else { find(&n.children, f) })
+  }
+  let nodes = parse(&read_to_string("./real_tests/wikipedia.html").unwrap());
+  //&#10; in an attribute
+  let a = find(&nodes, &|n| n.attributes.get("href").is_some_and(|h| h.contains("Parsoid/Feedback"))).unwrap();
+  assert_eq!(a.attributes.get("title").unwrap(), "\"If you see display errors,\nit may be due to Parsoid\"");
+  //&nbsp; in text
+  assert_eq!(a.children[0].tag_name, "Report visual bug\u{A0}");
+  //&#039;
+  let a = find(&nodes, &|n| n.attributes.get("hreflang") == Some(&"\"gn\"".to_string())).unwrap();
+  assert_eq!(a.attributes.get("data-language-autonym").unwrap(), "\"Avañe'ẽ\"");
+  //&amp; in a url
+  let link = find(&nodes, &|n| n.tag_name == "link" && n.attributes.get("href").is_some_and(|h| h.contains("site.styles"))).unwrap();
+  assert_eq!(link.attributes.get("href").unwrap(), "\"/w/load.php?lang=en&modules=site.styles&only=styles&skin=vector-2022\"");
+  assert!(find(&nodes, &|n| n.attributes.values().any(|v| v.contains("&amp;")) || (n.text_node && n.tag_name.contains("&amp;"))).is_none());
+}
+
+#[test]
+#[allow(clippy::unnecessary_get_then_check)]
 fn test_weird_attr() {
   //weird order
   let nodes = parse("<input type=\"text\" disabled name=\"one\">");
@@ -448,6 +1235,15 @@ fn test_form_parse_and_output() {
   //
 }
 
+#[test]
+fn test_anchor_output() {
+  let nodes = parse("<div id=\"top\"><p>a</p><a name=toc>b</a><a href=\"#top\">c</a></div>");
+  let output = nodes[0].to_output();
+  assert!(output[0] == OutputType::Anchor("top".to_string()));
+  assert!(output.contains(&OutputType::Anchor("toc".to_string())));
+  assert!(output.iter().filter(|o| matches!(o, OutputType::Anchor(_))).count() == 2);
+}
+
 #[test]
 fn test_strings_again() {
   let nodes = parse("<span data-value='woah\"cheeseburgers\"'>Nice</span>");
@@ -465,3 +1261,71 @@ fn test_real() {
   println!("{:?}", nodes[1].children[1].to_output());
   //println!("{}", nodes[12323233].children[1].tag_name);
 }
+
+#[test]
+fn test_style_outputs() {
+  let nodes = parse("<h2>a <em>b</em></h2><strong>c</strong>");
+  let outputs: Vec<OutputType> = nodes.iter().flat_map(|n| n.to_output()).collect();
+  assert!(outputs.contains(&OutputType::StartStyle(Style { heading: 2, ..Style::default() })));
+  assert!(outputs.contains(&OutputType::StartStyle(Style { italic: true, ..Style::default() })));
+  assert!(outputs.contains(&OutputType::StartStyle(Style { bold: true, ..Style::default() })));
+  //every style is ended
+  assert_eq!(outputs.iter().filter(|o| matches!(o, OutputType::StartStyle(_))).count(), outputs.iter().filter(|o| **o == OutputType::EndStyle).count());
+  assert_eq!(Style { heading: 1, ..Style::default() }.add(&Style { heading: 3, bold: true, ..Style::default() }), Style { heading: 3, bold: true, ..Style::default() });
+  assert_eq!(Style::of_tag("div"), None);
+}
+
+#[test]
+fn test_table_outputs() {
+  let nodes = parse("<table border=1><caption>Koxinga</caption><tr><th>a<td colspan=2>b<tr><td rowspan=0>c<form><input name=q></form></table>");
+  let outputs = nodes[0].to_output();
+  assert_eq!(outputs[..2], [OutputType::Text("Koxinga".to_string()), OutputType::Newline]);
+  let OutputType::Table(table) = &outputs[2] else {
+    panic!("not a table");
+  };
+  assert!(table.border);
+  assert_eq!(table.rows.iter().map(|r| r.len()).collect::<Vec<usize>>(), vec![2, 1]);
+  assert_eq!((table.rows[0][1].colspan, table.rows[1][0].rowspan), (2, 1));
+  //th is bold
+  assert_eq!(table.rows[0][0].outputs[0], OutputType::StartStyle(Style { bold: true, ..Style::default() }));
+  //inputs in tables still belong to their form
+  let nodes = parse("<form><table><tr><td><input name=q></table></form>");
+  assert!(matches!(nodes[0].to_output().last(), Some(OutputType::Form(form)) if form.input_names == vec!["q".to_string()]));
+}
+
+#[test]
+fn test_list_outputs() {
+  let markers = |html: &str| parse(html).iter().flat_map(|n| n.to_output()).filter_map(|o| match o {
+    OutputType::ListMarker(m) => Some(m),
+    OutputType::Indent(i) => Some(i.to_string()),
+    _ => None,
+  }).collect::<Vec<String>>();
+  assert_eq!(markers("<ol start=9><li>a<li>b<li value=1>c<li>d</ol>"), vec!["40", "9. ", "10. ", "1. ", "2. ", "0"]);
+  assert_eq!(markers("<ol reversed><li>a<li>b<li>c</ol><ol type=I start=4><li>a</ol><ol type=a start=27><li>a</ol>"), vec!["40", "3. ", "2. ", "1. ", "0", "40", "IV. ", "0", "40", "aa. ", "0"]);
+  //nested lists are indented further, with different bullets, and the indent goes back after
+  assert_eq!(markers("<div indent=1><ul><li>a<ul><li>b<ol><li>c</ol></ul><li>d</ul></div>"), vec!["32", "72", "• ", "112", "◦ ", "152", "1. ", "112", "72", "• ", "32"]);
+  //the item with the nested list is already ended by it, so there's no blank line after
+  let outputs = parse("<ul><li>a<ul><li>b</ul></ul>")[0].to_output();
+  assert_eq!(outputs.iter().filter(|o| **o == OutputType::Newline).count(), 1);
+  assert_eq!(roman(1994), "mcmxciv");
+}
+
+#[test]
+fn test_collapse_whitespace() {
+  //every line of indented text loses its indentation, not just the first
+  let nodes = parse("<div>\n  <p>foo\n        bar  baz\n\tqux</p>\n</div>");
+  let p = &nodes[0].children[0];
+  assert_eq!(nodes[0].children.len(), 1);
+  assert_eq!(p.children[0].tag_name, "foo bar baz qux");
+  assert_eq!(parse("a\r\n  <b>b</b>  c")[0].tag_name, "a ");
+}
+
+#[test]
+fn test_preformatted_outputs() {
+  let nodes = parse("<div>\n  <pre>\n  fn main() {\n\t<b>x</b>  &lt;\n  }\n</pre>\n  <p>  after</p></div>");
+  let outputs = nodes[0].to_output();
+  let texts: String = outputs.iter().filter_map(|o| if let OutputType::Text(t) = o { Some(t.as_str()) } else { None }).collect();
+  //indentation in the pre is kept, but not the html's around it
+  assert_eq!(texts, "  fn main() {\n\tx  <\n  } after");
+  assert_eq!(outputs.iter().filter(|o| matches!(o, OutputType::Preformatted(_))).count(), 2);
+}